@@ -1,7 +1,15 @@
 //! Types for the Ontario DTM Package Index API.
 
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use geo::orient::{Direction, Orient};
+use geo::{Area, Contains, Coord, LineString, MultiPolygon, Polygon};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use crate::package_client::FieldMapping;
+
 /// A DTM package from the Ontario Lidar-derived package index.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Package {
@@ -17,12 +25,92 @@ pub struct Package {
     pub project: String,
     /// Year range extracted from project name (e.g., "2016-18")
     pub year_range: Option<String>,
+    /// Acquisition years parsed from the project or package name
+    #[serde(default)]
+    pub acquisition_period: Option<AcquisitionPeriod>,
+    /// Identifier of the package source this package came from
+    #[serde(default)]
+    pub source: String,
     /// Coverage area in square kilometers
     pub coverage_km2: f64,
     /// The geometry as GeoJSON
     pub geometry: GeoJSONGeometry,
 }
 
+/// First and last acquisition year of a package, inclusive.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AcquisitionPeriod {
+    pub start_year: u32,
+    pub end_year: u32,
+}
+
+impl AcquisitionPeriod {
+    /// Parse the first year or year range in `text`.
+    ///
+    /// Handles two-digit range ends ("2016-18", "1998-02"), en dashes and
+    /// slashes. An end year before the start year is ignored.
+    pub fn parse(text: &str) -> Option<Self> {
+        let captures = YEAR_RANGE_RE.captures(text)?;
+        let start_year: u32 = captures[1].parse().ok()?;
+        let end_year = match captures.get(2) {
+            Some(end) if end.as_str().len() == 2 => {
+                let two_digit: u32 = end.as_str().parse().ok()?;
+                let mut end_year = start_year / 100 * 100 + two_digit;
+                if end_year < start_year {
+                    end_year += 100;
+                }
+                end_year
+            }
+            Some(end) => end.as_str().parse().ok()?,
+            None => start_year,
+        };
+
+        Some(Self {
+            start_year,
+            end_year: end_year.max(start_year),
+        })
+    }
+
+    /// Parse from the project name, falling back to the package name.
+    pub fn from_names(project: &str, package_name: &str) -> Option<Self> {
+        Self::parse(project).or_else(|| Self::parse(package_name))
+    }
+
+    /// Whether any year of this period lies within the optional bounds.
+    pub fn overlaps(&self, min_year: Option<u32>, max_year: Option<u32>) -> bool {
+        min_year.is_none_or(|min| self.end_year >= min)
+            && max_year.is_none_or(|max| self.start_year <= max)
+    }
+}
+
+/// Order packages by acquisition period, oldest first, then by project and
+/// package name. Packages without a period come last.
+pub fn sort_chronologically(packages: &mut [Package]) {
+    packages.sort_by(|a, b| {
+        let period = |p: &Package| (p.acquisition_period.is_none(), p.acquisition_period);
+        period(a)
+            .cmp(&period(b))
+            .then_with(|| a.project.cmp(&b.project))
+            .then_with(|| a.package_name.cmp(&b.package_name))
+    });
+}
+
+/// Distinct project names of `packages`, in order of first appearance.
+pub fn project_names(packages: &[Package]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    packages
+        .iter()
+        .filter(|p| seen.insert(p.project.as_str()))
+        .map(|p| p.project.clone())
+        .collect()
+}
+
+/// A four-digit year, optionally followed by a range end of two or four digits.
+static YEAR_RANGE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b((?:19|20)\d{2})(?:\s*[-–/]\s*((?:19|20)\d{2}|\d{2}))?\b")
+        .expect("valid year range regex")
+});
+
 /// GeoJSON Geometry representation.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", content = "coordinates")]
@@ -36,11 +124,114 @@ pub enum GeoJSONGeometry {
 }
 
 impl GeoJSONGeometry {
-    /// Create a Polygon from ESRI rings format.
-    /// ESRI rings are an array of rings, where each ring is an array of [x, y] points.
+    /// Create a Polygon or MultiPolygon from ESRI rings format.
+    ///
+    /// ESRI rings are a flat array of rings, where each ring is an array of
+    /// [x, y] points. Clockwise rings are exteriors and counterclockwise rings
+    /// are holes, each belonging to the smallest exterior that contains the
+    /// whole ring.
+    /// A hole outside every exterior is kept as an exterior, and degenerate
+    /// rings are dropped. The output follows the RFC 7946 right-hand rule.
     pub fn from_esri_rings(rings: Vec<Vec<Vec<f64>>>) -> Self {
-        GeoJSONGeometry::Polygon(rings)
+        let mut polygons: Vec<Polygon<f64>> = Vec::new();
+        let mut holes = Vec::new();
+        for ring in &rings {
+            let line = positions_to_line_string(ring);
+            if line.0.len() < 4 {
+                continue;
+            }
+            let signed_area = Polygon::new(line.clone(), Vec::new()).signed_area();
+            if signed_area < 0.0 {
+                polygons.push(Polygon::new(line, Vec::new()));
+            } else if signed_area > 0.0 {
+                holes.push(line);
+            }
+        }
+
+        let areas: Vec<f64> = polygons.iter().map(|p| p.unsigned_area()).collect();
+        let mut orphans = Vec::new();
+        for hole in holes {
+            // Test the whole ring: a point inside the hole may lie on an island.
+            let hole_polygon = Polygon::new(hole.clone(), Vec::new());
+            let hole_area = hole_polygon.unsigned_area();
+            let owner = polygons
+                .iter()
+                .enumerate()
+                .filter(|(i, polygon)| {
+                    areas[*i] > hole_area
+                        && Polygon::new(polygon.exterior().clone(), Vec::new())
+                            .contains(&hole_polygon)
+                })
+                .min_by(|(a, _), (b, _)| areas[*a].total_cmp(&areas[*b]))
+                .map(|(i, _)| i);
+            match owner {
+                Some(i) => polygons[i].interiors_push(hole),
+                None => orphans.push(Polygon::new(hole, Vec::new())),
+            }
+        }
+        polygons.extend(orphans);
+
+        let multi_polygon = MultiPolygon::new(polygons).orient(Direction::Default);
+        match multi_polygon.0.as_slice() {
+            [] => GeoJSONGeometry::Polygon(Vec::new()),
+            [polygon] => GeoJSONGeometry::Polygon(polygon_to_rings(polygon)),
+            _ => GeoJSONGeometry::from_multi_polygon(&multi_polygon),
+        }
+    }
+
+    /// Convert polygonal geometries to a `geo` MultiPolygon.
+    /// Non-polygonal geometries yield an empty MultiPolygon.
+    pub fn to_multi_polygon(&self) -> MultiPolygon<f64> {
+        match self {
+            GeoJSONGeometry::Polygon(rings) => {
+                MultiPolygon::new(rings_to_polygon(rings).into_iter().collect())
+            }
+            GeoJSONGeometry::MultiPolygon(polygons) => MultiPolygon::new(
+                polygons
+                    .iter()
+                    .filter_map(|rings| rings_to_polygon(rings))
+                    .collect(),
+            ),
+            _ => MultiPolygon::new(Vec::new()),
+        }
     }
+
+    /// Create a MultiPolygon geometry from a `geo` MultiPolygon.
+    pub fn from_multi_polygon(multi_polygon: &MultiPolygon<f64>) -> Self {
+        GeoJSONGeometry::MultiPolygon(multi_polygon.iter().map(polygon_to_rings).collect())
+    }
+}
+
+fn polygon_to_rings(polygon: &Polygon<f64>) -> Vec<Vec<Vec<f64>>> {
+    std::iter::once(polygon.exterior())
+        .chain(polygon.interiors())
+        .map(line_string_to_positions)
+        .collect()
+}
+
+fn rings_to_polygon(rings: &[Vec<Vec<f64>>]) -> Option<Polygon<f64>> {
+    let (exterior, interiors) = rings.split_first()?;
+    Some(Polygon::new(
+        positions_to_line_string(exterior),
+        interiors
+            .iter()
+            .map(|ring| positions_to_line_string(ring))
+            .collect(),
+    ))
+}
+
+fn positions_to_line_string(positions: &[Vec<f64>]) -> LineString<f64> {
+    LineString::new(
+        positions
+            .iter()
+            .filter(|p| p.len() >= 2)
+            .map(|p| Coord { x: p[0], y: p[1] })
+            .collect(),
+    )
+}
+
+fn line_string_to_positions(line: &LineString<f64>) -> Vec<Vec<f64>> {
+    line.coords().map(|c| vec![c.x, c.y]).collect()
 }
 
 /// Bounding box for spatial queries.
@@ -87,36 +278,68 @@ impl BoundingBox {
 /// Root response from ArcGIS query endpoint.
 #[derive(Debug, Deserialize)]
 pub(crate) struct ArcGISQueryResponse {
-    pub features: Vec<ArcGISFeature>,
-    /// Indicates if more results are available (pagination needed)
-    /// Currently unused but kept for potential future use
-    #[allow(dead_code)]
     #[serde(default)]
+    pub features: Vec<ArcGISFeature>,
+    /// Set when the server capped the page and more results are available
+    #[serde(default, rename = "exceededTransferLimit")]
     pub exceeded_transfer_limit: bool,
 }
 
+/// Response from an ArcGIS query with `returnIdsOnly=true`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ArcGISObjectIdsResponse {
+    #[serde(default, rename = "objectIds")]
+    pub object_ids: Option<Vec<i64>>,
+}
+
+/// Layer metadata from `GET {layer}?f=json`, reduced to what paging needs.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ArcGISLayerInfo {
+    #[serde(default, rename = "advancedQueryCapabilities")]
+    pub advanced_query_capabilities: Option<ArcGISAdvancedQueryCapabilities>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ArcGISAdvancedQueryCapabilities {
+    #[serde(default, rename = "supportsPagination")]
+    pub supports_pagination: Option<bool>,
+}
+
+/// Error body returned by ArcGIS REST endpoints, usually with HTTP status 200.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ArcGISErrorResponse {
+    pub error: ArcGISError,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ArcGISError {
+    pub code: i64,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub details: Vec<String>,
+}
+
 /// A single feature from the ArcGIS response.
+///
+/// Attribute names differ between layers, so they are kept as raw JSON and
+/// read through a [`crate::package_client::FieldMapping`].
 #[derive(Debug, Deserialize)]
 pub(crate) struct ArcGISFeature {
-    pub attributes: ArcGISAttributes,
+    #[serde(default)]
+    pub attributes: serde_json::Map<String, serde_json::Value>,
     #[serde(default)]
     pub geometry: Option<ArcGISPolygonGeometry>,
 }
 
-/// Attributes from the ArcGIS feature.
-#[derive(Debug, Deserialize)]
+/// Package attributes read from an ArcGIS feature.
+#[derive(Debug, Default)]
 pub(crate) struct ArcGISAttributes {
-    #[serde(rename = "Package")]
     pub package: Option<String>,
-    #[serde(rename = "Size_GB")]
     pub size_gb: Option<f64>,
-    #[serde(rename = "Resolution")]
     pub resolution: Option<f64>,
-    #[serde(rename = "DownloadLink")]
     pub download_link: Option<String>,
-    #[serde(rename = "Project")]
     pub project: Option<String>,
-    #[serde(rename = "Shape__Area")]
     pub shape_area: Option<f64>,
 }
 
@@ -136,6 +359,145 @@ pub struct QueryRequest {
     pub min_y: f64,
     pub max_x: f64,
     pub max_y: f64,
+    #[serde(flatten)]
+    pub filter: PackageFilter,
+}
+
+/// Response format of `/api/packages/query`, selected with `?format=`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryFormat {
+    /// [`QueryResult`].
+    #[default]
+    Json,
+    /// RFC 7946 FeatureCollection in WGS84.
+    Geojson,
+    /// Zipped ESRI Shapefile in WGS84.
+    Shapefile,
+}
+
+impl QueryFormat {
+    /// The format asked for by an `Accept` header, if it names a GIS format.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        if accept.contains("application/geo+json") {
+            Some(QueryFormat::Geojson)
+        } else if accept.contains("application/zip") {
+            Some(QueryFormat::Shapefile)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QueryFormatParams {
+    #[serde(default)]
+    pub format: Option<QueryFormat>,
+}
+
+/// Attribute filters applied to package queries. All fields are optional.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PackageFilter {
+    /// Exact project names to include.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub projects: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_resolution: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_resolution: Option<f64>,
+    /// Earliest acquisition year; packages whose years overlap the range match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_year: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_year: Option<u32>,
+    /// Size budget: results are cut off before the package that would make
+    /// their summed size exceed it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_size_gb: Option<f64>,
+}
+
+impl PackageFilter {
+    /// Build the ArcGIS `where` clause for the filters the service can evaluate,
+    /// naming the attributes of `fields`.
+    ///
+    /// Filters on unmapped attributes, and acquisition years, which are
+    /// embedded in the project name, are only checked by
+    /// [`PackageFilter::matches`].
+    pub fn to_where_clause(&self, fields: &FieldMapping) -> String {
+        let mut clauses = Vec::new();
+
+        if let Some(project) = fields
+            .project
+            .as_ref()
+            .filter(|_| !self.projects.is_empty())
+        {
+            let names: Vec<String> = self.projects.iter().map(|p| sql_string(p)).collect();
+            clauses.push(format!("{} IN ({})", project, names.join(", ")));
+        }
+        if let Some(resolution) = &fields.resolution {
+            if let Some(min) = self.min_resolution.filter(|v| v.is_finite()) {
+                clauses.push(format!("{} >= {}", resolution, min));
+            }
+            if let Some(max) = self.max_resolution.filter(|v| v.is_finite()) {
+                clauses.push(format!("{} <= {}", resolution, max));
+            }
+        }
+
+        if clauses.is_empty() {
+            "1=1".to_string()
+        } else {
+            clauses.join(" AND ")
+        }
+    }
+
+    /// Keep the leading `packages` that fit within `max_total_size_gb`,
+    /// returning whether any were dropped.
+    pub fn limit_total_size(&self, packages: &mut Vec<Package>) -> bool {
+        let Some(budget) = self.max_total_size_gb else {
+            return false;
+        };
+        let mut total = 0.0;
+        let fitting = packages
+            .iter()
+            .take_while(|p| {
+                total += p.size_gb;
+                total <= budget
+            })
+            .count();
+        let truncated = fitting < packages.len();
+        packages.truncate(fitting);
+        truncated
+    }
+
+    /// Whether a single package passes the per-package filters.
+    pub fn matches(&self, package: &Package) -> bool {
+        if !self.projects.is_empty() && !self.projects.contains(&package.project) {
+            return false;
+        }
+        if self
+            .min_resolution
+            .is_some_and(|min| package.resolution < min)
+        {
+            return false;
+        }
+        if self
+            .max_resolution
+            .is_some_and(|max| package.resolution > max)
+        {
+            return false;
+        }
+        if self.min_year.is_some() || self.max_year.is_some() {
+            return package
+                .acquisition_period
+                .is_some_and(|period| period.overlaps(self.min_year, self.max_year));
+        }
+        true
+    }
+}
+
+/// Quote a string literal for an ArcGIS SQL expression.
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -143,6 +505,9 @@ pub struct QueryResult {
     pub packages: Vec<Package>,
     pub projects: Vec<String>,
     pub total_size_gb: f64,
+    /// Whether packages were left out to stay within `max_total_size_gb`.
+    #[serde(default)]
+    pub truncated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -150,6 +515,117 @@ pub struct DownloadRequest {
     pub packages: Vec<Package>,
     pub clip_extent: Option<ClipExtentRequest>,
     pub compression: String,
+    /// Nodata value for the merged output; detected from the inputs when omitted.
+    pub output_nodata: Option<f64>,
+    /// Optional interpolation of nodata voids in the merged output.
+    pub void_fill: Option<VoidFillRequest>,
+    /// Vertical datum for output heights; the source datum is kept when omitted.
+    pub target_vertical_datum: Option<VerticalDatum>,
+    /// Vertical datum of the package heights; CGVD2013 when omitted.
+    #[serde(default)]
+    pub source_vertical_datum: Option<VerticalDatum>,
+    /// Zonal statistics to compute from the merged output.
+    #[serde(default)]
+    pub statistics: Option<StatisticsRequest>,
+}
+
+/// Zonal statistics over a polygon, or the whole raster when none is given.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatisticsRequest {
+    /// Polygon or MultiPolygon in `srid` coordinates.
+    #[serde(default)]
+    pub polygon: Option<GeoJSONGeometry>,
+    #[serde(default = "default_spatial_reference")]
+    pub srid: u32,
+    /// Histogram buckets; 256 when omitted.
+    #[serde(default)]
+    pub histogram_bins: Option<usize>,
+    /// Percentiles between 0 and 100; 5, 25, 50, 75 and 95 when omitted.
+    #[serde(default)]
+    pub percentiles: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ZonalStatistics {
+    /// Pixels in the zone with data.
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// Population standard deviation, as reported by `gdalinfo -stats`.
+    pub std_dev: Option<f64>,
+    pub percentiles: Vec<PercentileValue>,
+    pub histogram: Histogram,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PercentileValue {
+    pub percentile: f64,
+    pub value: f64,
+}
+
+/// Equal-width buckets from `min` to `max`, the last one including `max`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Histogram {
+    pub min: f64,
+    pub max: f64,
+    pub counts: Vec<u64>,
+}
+
+/// Canadian vertical datums.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerticalDatum {
+    /// Canadian Geodetic Vertical Datum of 1928 (levelling-based).
+    Cgvd28,
+    /// Canadian Geodetic Vertical Datum of 2013 (geoid-based).
+    Cgvd2013,
+}
+
+impl VerticalDatum {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VerticalDatum::Cgvd28 => "CGVD28",
+            VerticalDatum::Cgvd2013 => "CGVD2013",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoidFillRequest {
+    /// Maximum search distance in pixels.
+    pub max_distance: u32,
+    #[serde(default)]
+    pub smoothing_iterations: u32,
+}
+
+/// Pre-flight estimate of the download, disk and output size of a job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadEstimate {
+    pub package_count: usize,
+    /// Packages that are already fully downloaded, extracted or not.
+    pub cached_packages: usize,
+    /// Bytes still to download, excluding cached ZIPs.
+    pub download_bytes: u64,
+    /// Extra disk space needed to extract the ZIPs.
+    pub extraction_bytes: u64,
+    pub output_width: u64,
+    pub output_height: u64,
+    pub output_pixels: u64,
+    /// Estimated size of the output COG.
+    pub output_bytes: u64,
+    /// Disk space needed in the work directory, including intermediates.
+    pub work_bytes: u64,
+    pub compression: String,
+    /// Output resolution in metres, the finest of the packages.
+    pub resolution: f64,
+    pub cache_dir: String,
+    pub cache_free_bytes: Option<u64>,
+    pub work_dir: String,
+    pub work_free_bytes: Option<u64>,
+    /// Whether the cache and work directories have room for the job.
+    pub enough_disk_space: bool,
+    pub estimated_download_seconds: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -158,6 +634,85 @@ pub struct ClipExtentRequest {
     pub min_y: f64,
     pub max_x: f64,
     pub max_y: f64,
+    /// EPSG code of the coordinates; Web Mercator when omitted.
+    #[serde(default = "default_spatial_reference")]
+    pub srid: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverageRequest {
+    /// Rectangular area of interest; ignored when `polygon` is given.
+    pub extent: Option<ClipExtentRequest>,
+    /// Polygon or MultiPolygon area of interest.
+    pub polygon: Option<GeoJSONGeometry>,
+    pub packages: Vec<Package>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoverageReport {
+    /// Part of the area of interest not covered by any package footprint.
+    pub uncovered: GeoJSONGeometry,
+    pub covered_percentage: f64,
+    pub uncovered_percentage: f64,
+    pub is_complete: bool,
+}
+
+/// How packages are prioritised when selecting coverage for an area.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStrategy {
+    /// Only packages from the selected project.
+    SelectedOnly,
+    /// The selected project, with gaps filled from its previous acquisition.
+    PreferSelectedWithFallback,
+    /// Most recent acquisition first.
+    Newest,
+    /// Smallest pixel size first.
+    FinestResolution,
+}
+
+impl SelectionStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelectionStrategy::SelectedOnly => "selected-only",
+            SelectionStrategy::PreferSelectedWithFallback => "prefer-selected-with-fallback",
+            SelectionStrategy::Newest => "newest",
+            SelectionStrategy::FinestResolution => "finest-resolution",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectionRequest {
+    /// Rectangular area of interest; ignored when `polygon` is given.
+    pub extent: Option<ClipExtentRequest>,
+    /// Polygon or MultiPolygon area of interest.
+    pub polygon: Option<GeoJSONGeometry>,
+    pub strategy: SelectionStrategy,
+    /// Exact project name, required by the selected-only strategies.
+    pub selected_project: Option<String>,
+    /// Candidate packages; when omitted, packages intersecting the area are queried.
+    pub packages: Option<Vec<Package>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectionResult {
+    pub strategy: SelectionStrategy,
+    pub packages: Vec<Package>,
+    pub total_size_gb: f64,
+    pub coverage: CoverageReport,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexStatus {
+    pub path: String,
+    pub package_count: usize,
+    /// Unix timestamp of the last successful sync.
+    pub synced_at: Option<u64>,
+    pub age_seconds: Option<u64>,
+    pub refresh_interval_seconds: u64,
+    pub syncing: bool,
+    pub last_error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -165,6 +720,105 @@ pub struct DownloadStartResponse {
     pub download_id: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    /// The output was deleted by the retention policy.
+    Expired,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadStatus {
+    pub download_id: String,
+    pub status: JobStatus,
+    /// Output file name, once the job has completed.
+    pub filename: Option<String>,
+    pub error: Option<String>,
+    /// Seconds until the output is deleted, when a retention period applies.
+    pub expires_in_seconds: Option<u64>,
+    /// Zonal statistics computed by the job, if it asked for them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statistics: Option<ZonalStatistics>,
+}
+
+/// `GET /api/elevation` parameters.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ElevationQuery {
+    pub x: f64,
+    pub y: f64,
+    /// EPSG code of `x`/`y`; Web Mercator when omitted.
+    #[serde(default = "default_spatial_reference")]
+    pub srid: u32,
+    /// Sample this job's finished output instead of the cached packages.
+    #[serde(default)]
+    pub download_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElevationResponse {
+    pub x: f64,
+    pub y: f64,
+    pub srid: u32,
+    /// `None` where no cached raster has data at the point.
+    pub elevation: Option<f64>,
+    /// Package the value was read from, or `output` for a job output.
+    pub source: Option<String>,
+    /// Packages covering the point that are not in the local cache.
+    pub uncached_packages: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfileRequest {
+    /// A LineString in `srid` coordinates.
+    pub line: GeoJSONGeometry,
+    #[serde(default = "default_spatial_reference")]
+    pub srid: u32,
+    /// Number of evenly spaced samples, including both ends.
+    #[serde(default)]
+    pub samples: Option<usize>,
+    #[serde(default)]
+    pub download_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProfilePoint {
+    /// Ground distance from the start of the line in metres.
+    pub distance: f64,
+    pub x: f64,
+    pub y: f64,
+    pub elevation: Option<f64>,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileResponse {
+    pub srid: u32,
+    /// Ground length of the line in metres.
+    pub length: f64,
+    pub points: Vec<ProfilePoint>,
+    pub uncached_packages: Vec<String>,
+}
+
+/// Rendering of preview tiles, selected with `?style=`.
+#[derive(Debug, Clone, Copy, Default, Hash, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TileStyle {
+    #[default]
+    Hillshade,
+    /// Hypsometric color ramp over the output's elevation range.
+    Color,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TileParams {
+    #[serde(default)]
+    pub style: TileStyle,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct DownloadProgressEvent {
     pub package_name: String,
@@ -187,8 +841,67 @@ pub struct ProcessingProgressEvent {
 pub enum ProgressEvent {
     Download(DownloadProgressEvent),
     Processing(ProcessingProgressEvent),
-    Complete { output_filename: String },
-    Error { message: String },
+    Complete {
+        output_filename: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        statistics: Option<ZonalStatistics>,
+    },
+    Warning {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Extract the actual URL from an HTML anchor tag.
+/// Handles both double and single quoted href attributes, with optional spaces around =.
+pub fn extract_download_url(html: &str) -> Option<String> {
+    let html = html.trim();
+
+    // Find href, then skip any whitespace and = and more whitespace
+    let href_pos = html.find("href")?;
+    let after_href = &html[href_pos + 4..];
+
+    // Skip whitespace
+    let after_href = after_href.trim_start();
+
+    // Expect =
+    if !after_href.starts_with('=') {
+        return None;
+    }
+    let after_eq = &after_href[1..];
+
+    // Skip whitespace after =
+    let after_eq = after_eq.trim_start();
+
+    // Expect quote
+    let (quote, rest) = if let Some(rest) = after_eq.strip_prefix('"') {
+        ('"', rest)
+    } else if let Some(rest) = after_eq.strip_prefix('\'') {
+        ('\'', rest)
+    } else {
+        return None;
+    };
+
+    // Find closing quote
+    if let Some(end) = rest.find(quote) {
+        return Some(rest[..end].to_string());
+    }
+
+    None
+}
+
+/// Dash-separated year ranges only, so `year_range` keeps its established format.
+static YEAR_RANGE_LABEL_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(19|20)\d{2}(?:\s*[-–]\s*(?:19|20)?\d{2})?\b").expect("valid year label regex")
+});
+
+/// Extract year range from project name.
+/// Examples: "OMAFRA Lidar 2016-18" -> "2016-18", "GTA 2014" -> "2014"
+pub fn extract_year_range(project: &str) -> Option<String> {
+    let match_opt = YEAR_RANGE_LABEL_RE.find(project)?;
+    Some(match_opt.as_str().replace(" ", ""))
 }
 
 // ============================================================
@@ -198,6 +911,198 @@ pub enum ProgressEvent {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::PackageBuilder;
+
+    #[test]
+    fn test_filter_where_clause_escapes_project_names() {
+        let filter = PackageFilter {
+            projects: vec!["OMAFRA Lidar 2022".to_string(), "O'Hara 2019".to_string()],
+            min_resolution: Some(0.5),
+            max_resolution: Some(1.0),
+            ..Default::default()
+        };
+        let fields = FieldMapping::default();
+        assert_eq!(
+            filter.to_where_clause(&fields),
+            "Project IN ('OMAFRA Lidar 2022', 'O''Hara 2019') AND Resolution >= 0.5 AND Resolution <= 1"
+        );
+        assert_eq!(PackageFilter::default().to_where_clause(&fields), "1=1");
+
+        let fields = FieldMapping {
+            project: Some("PROJ_NAME".to_string()),
+            resolution: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.to_where_clause(&fields),
+            "PROJ_NAME IN ('OMAFRA Lidar 2022', 'O''Hara 2019')"
+        );
+    }
+
+    #[test]
+    fn test_filter_where_clause_uses_field_mapping() {
+        let filter = PackageFilter {
+            projects: vec!["HRDEM 2021".to_string()],
+            min_resolution: Some(1.0),
+            max_resolution: Some(2.0),
+            ..Default::default()
+        };
+        let fields = FieldMapping {
+            project: Some("survey".to_string()),
+            resolution: Some("gsd_m".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.to_where_clause(&fields),
+            "survey IN ('HRDEM 2021') AND gsd_m >= 1 AND gsd_m <= 2"
+        );
+
+        // Unmapped attributes are left to `matches`.
+        let fields = FieldMapping {
+            project: None,
+            resolution: None,
+            ..Default::default()
+        };
+        assert_eq!(filter.to_where_clause(&fields), "1=1");
+        let fields = FieldMapping {
+            project: None,
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.to_where_clause(&fields),
+            "Resolution >= 1 AND Resolution <= 2"
+        );
+    }
+
+    #[test]
+    fn test_limit_total_size_keeps_leading_packages() {
+        let mut packages: Vec<_> = [2.0, 1.5, 1.0, 0.5]
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| PackageBuilder::new(&i.to_string()).size_gb(size).build())
+            .collect();
+
+        assert!(!PackageFilter::default().limit_total_size(&mut packages));
+        assert_eq!(packages.len(), 4);
+
+        let filter = PackageFilter {
+            max_total_size_gb: Some(4.0),
+            ..Default::default()
+        };
+        assert!(filter.limit_total_size(&mut packages));
+        let names: Vec<_> = packages.iter().map(|p| p.package_name.as_str()).collect();
+        assert_eq!(names, vec!["0", "1"]);
+        assert!(!filter.limit_total_size(&mut packages));
+    }
+
+    #[test]
+    fn test_filter_matches_year_overlap() {
+        let mut package = PackageBuilder::new("Cochrane A")
+            .project("OMAFRA Lidar 2016-18")
+            .years("2016-18")
+            .build();
+        let filter = PackageFilter {
+            min_year: Some(2018),
+            ..Default::default()
+        };
+        assert!(filter.matches(&package));
+
+        let filter = PackageFilter {
+            min_year: Some(2019),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&package));
+
+        package.acquisition_period = None;
+        assert!(!filter.matches(&package));
+        assert!(PackageFilter::default().matches(&package));
+    }
+
+    /// Acquisition periods of the project names in the fixture index.
+    const FIXTURE_PERIODS: &[(&str, (u32, u32))] = &[
+        ("OMAFRA Lidar 2016-18", (2016, 2018)),
+        ("OMAFRA Lidar 2022", (2022, 2022)),
+        ("GTA 2014-18", (2014, 2018)),
+        ("LEAP 2009", (2009, 2009)),
+    ];
+
+    fn fixture_names() -> Vec<(String, String)> {
+        let index: serde_json::Value =
+            serde_json::from_str(crate::package_index::tests::FIXTURE).unwrap();
+        index["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|feature| {
+                let properties = &feature["properties"];
+                (
+                    properties["project"].as_str().unwrap().to_string(),
+                    properties["package_name"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_acquisition_period_parsing() {
+        for (project, package_name) in fixture_names() {
+            let expected = FIXTURE_PERIODS
+                .iter()
+                .find(|(name, _)| *name == project)
+                .map(|(_, years)| *years)
+                .unwrap_or_else(|| panic!("{:?} missing from FIXTURE_PERIODS", project));
+            let parsed = AcquisitionPeriod::from_names(&project, &package_name)
+                .map(|p| (p.start_year, p.end_year));
+            assert_eq!(parsed, Some(expected), "parsing {:?}", project);
+            // Package names in the index carry no years of their own.
+            assert_eq!(AcquisitionPeriod::parse(&package_name), None);
+        }
+    }
+
+    #[test]
+    fn test_acquisition_period_parsing_separator_variants() {
+        for (project, (start, end)) in FIXTURE_PERIODS {
+            let Some((prefix, _)) = project.split_once('-') else {
+                continue;
+            };
+            let variants = [
+                project.replace('-', "–"),
+                project.replace('-', "/"),
+                project.replace('-', " - "),
+                format!("{}-{}", prefix, end),
+            ];
+            for variant in variants {
+                let parsed = AcquisitionPeriod::parse(&variant).map(|p| (p.start_year, p.end_year));
+                assert_eq!(parsed, Some((*start, *end)), "parsing {:?}", variant);
+            }
+        }
+    }
+
+    #[test]
+    fn test_acquisition_period_falls_back_to_package_name() {
+        let period = AcquisitionPeriod::from_names("GTA", "GTA 2023 Tile A").unwrap();
+        assert_eq!((period.start_year, period.end_year), (2023, 2023));
+        assert!(AcquisitionPeriod::from_names("GTA", "Tile A").is_none());
+    }
+
+    #[test]
+    fn test_acquisition_periods_sort_chronologically() {
+        let mut periods: Vec<_> = [
+            "SNC Lidar 2018-19",
+            "LEAP 2009",
+            "GTA 2014-18",
+            "CLOCA Lidar 2018",
+        ]
+        .iter()
+        .filter_map(|name| AcquisitionPeriod::parse(name))
+        .collect();
+        periods.sort();
+        let starts: Vec<_> = periods.iter().map(|p| (p.start_year, p.end_year)).collect();
+        assert_eq!(
+            starts,
+            vec![(2009, 2009), (2014, 2018), (2018, 2018), (2018, 2019)]
+        );
+    }
 
     #[test]
     fn test_bounding_box_to_esri_geometry() {
@@ -261,6 +1166,144 @@ mod tests {
         }
     }
 
+    /// Signed shoelace area of a ring; positive when counterclockwise.
+    fn ring_area(ring: &[Vec<f64>]) -> f64 {
+        ring.windows(2)
+            .map(|w| w[0][0] * w[1][1] - w[1][0] * w[0][1])
+            .sum::<f64>()
+            / 2.0
+    }
+
+    #[test]
+    fn test_esri_rings_split_into_parts_with_holes() {
+        // A synthetic footprint with a lake, an island inside the lake and a
+        // second part holding its own pond, in the package index query format.
+        // Real multi-part footprints are checked against the live index by
+        // `package_client::tests::test_real_multipart_footprints_integration`.
+        let response: ArcGISQueryResponse = serde_json::from_str(include_str!(
+            "../fixtures/esri_synthetic_multipart_footprint.json"
+        ))
+        .unwrap();
+        let rings = response
+            .features
+            .into_iter()
+            .next()
+            .unwrap()
+            .geometry
+            .unwrap()
+            .rings;
+
+        let GeoJSONGeometry::MultiPolygon(polygons) = GeoJSONGeometry::from_esri_rings(rings)
+        else {
+            panic!("expected a MultiPolygon");
+        };
+        let ring_counts: Vec<usize> = polygons.iter().map(|p| p.len()).collect();
+        assert_eq!(ring_counts, vec![2, 1, 2]);
+
+        for polygon in &polygons {
+            assert!(
+                ring_area(&polygon[0]) > 0.0,
+                "exteriors are counterclockwise"
+            );
+            for hole in &polygon[1..] {
+                assert!(ring_area(hole) < 0.0, "holes are clockwise");
+            }
+        }
+        // The island lies inside the lake hole of the first part.
+        assert_eq!(polygons[1][0][0], vec![-10566000.0, 6344000.0]);
+
+        // Lake and pond areas are subtracted from their parts.
+        let footprint = GeoJSONGeometry::MultiPolygon(polygons).to_multi_polygon();
+        let expected =
+            10_000.0 * 10_250.0 - 4_000.0 * 4_250.0 + 1_000_000.0 + 9_000_000.0 - 1_000_000.0;
+        assert!((footprint.unsigned_area() - expected).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_esri_lake_with_centered_island_belongs_to_outer_ring() {
+        let square = |min: f64, max: f64, clockwise: bool| {
+            let mut ring = vec![
+                vec![min, min],
+                vec![min, max],
+                vec![max, max],
+                vec![max, min],
+                vec![min, min],
+            ];
+            if !clockwise {
+                ring.reverse();
+            }
+            ring
+        };
+        // The island is listed first, so it is the smallest exterior around
+        // the lake's centre.
+        let rings = vec![
+            square(40.0, 60.0, true),
+            square(0.0, 100.0, true),
+            square(20.0, 80.0, false),
+        ];
+
+        let footprint = GeoJSONGeometry::from_esri_rings(rings).to_multi_polygon();
+        let mut polygons: Vec<_> = footprint
+            .iter()
+            .map(|p| (p.unsigned_area(), p.interiors().len()))
+            .collect();
+        polygons.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(polygons, vec![(400.0, 0), (10_000.0 - 3_600.0, 1)]);
+    }
+
+    #[test]
+    fn test_esri_single_ring_becomes_counterclockwise_polygon() {
+        let rings = vec![vec![
+            vec![0.0, 0.0],
+            vec![0.0, 1.0],
+            vec![1.0, 1.0],
+            vec![1.0, 0.0],
+            vec![0.0, 0.0],
+        ]];
+        let GeoJSONGeometry::Polygon(rings) = GeoJSONGeometry::from_esri_rings(rings) else {
+            panic!("expected a Polygon");
+        };
+        assert_eq!(rings.len(), 1);
+        assert!(ring_area(&rings[0]) > 0.0);
+    }
+
+    #[test]
+    fn test_geojson_multi_polygon_round_trip() {
+        let geom = GeoJSONGeometry::Polygon(vec![
+            vec![
+                vec![0.0, 0.0],
+                vec![4.0, 0.0],
+                vec![4.0, 4.0],
+                vec![0.0, 4.0],
+                vec![0.0, 0.0],
+            ],
+            vec![
+                vec![1.0, 1.0],
+                vec![1.0, 2.0],
+                vec![2.0, 2.0],
+                vec![2.0, 1.0],
+                vec![1.0, 1.0],
+            ],
+        ]);
+        let multi_polygon = geom.to_multi_polygon();
+        assert_eq!(multi_polygon.0.len(), 1);
+        assert_eq!(multi_polygon.0[0].interiors().len(), 1);
+
+        match GeoJSONGeometry::from_multi_polygon(&multi_polygon) {
+            GeoJSONGeometry::MultiPolygon(polygons) => {
+                assert_eq!(polygons.len(), 1);
+                assert_eq!(polygons[0].len(), 2);
+            }
+            _ => panic!("Expected MultiPolygon"),
+        }
+    }
+
+    #[test]
+    fn test_non_polygon_geometry_to_multi_polygon_is_empty() {
+        let geom = GeoJSONGeometry::Point(vec![1.0, 2.0]);
+        assert!(geom.to_multi_polygon().0.is_empty());
+    }
+
     #[test]
     fn test_parse_arcgis_response() {
         let json = r#"{
@@ -277,17 +1320,17 @@ mod tests {
                     "rings": [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]]]
                 }
             }],
-            "exceeded_transfer_limit": false
+            "exceededTransferLimit": true
         }"#;
 
         let response: ArcGISQueryResponse = serde_json::from_str(json).unwrap();
         assert_eq!(response.features.len(), 1);
-        assert!(!response.exceeded_transfer_limit);
+        assert!(response.exceeded_transfer_limit);
 
         let feature = &response.features[0];
-        assert_eq!(feature.attributes.package, Some("Test Package".to_string()));
-        assert_eq!(feature.attributes.size_gb, Some(1.5));
-        assert_eq!(feature.attributes.shape_area, Some(1000000000.0));
+        assert_eq!(feature.attributes["Package"], "Test Package");
+        assert_eq!(feature.attributes["Size_GB"], 1.5);
+        assert_eq!(feature.attributes["Shape__Area"], 1000000000.0);
         assert!(feature.geometry.is_some());
     }
 
@@ -319,56 +1362,52 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_year_range_19xx() {
+    fn test_extract_year_range_keeps_dash_format() {
         assert_eq!(
-            extract_year_range("Old Data 1999"),
-            Some("1999".to_string())
+            extract_year_range("OMAFRA Lidar 2016–18"),
+            Some("2016–18".to_string())
+        );
+        assert_eq!(
+            extract_year_range("OMAFRA Lidar 2016/18"),
+            Some("2016".to_string())
         );
     }
-}
 
-/// Extract the actual URL from an HTML anchor tag.
-/// Handles both double and single quoted href attributes, with optional spaces around =.
-pub fn extract_download_url(html: &str) -> Option<String> {
-    let html = html.trim();
-
-    // Find href, then skip any whitespace and = and more whitespace
-    let href_pos = html.find("href")?;
-    let after_href = &html[href_pos + 4..];
-
-    // Skip whitespace
-    let after_href = after_href.trim_start();
+    #[test]
+    fn test_sort_chronologically_orders_fixture_packages() {
+        let mut packages = crate::package_index::tests::fixture_packages();
+        packages.reverse();
+        let gta = packages.iter_mut().find(|p| p.package_name == "GTA 1");
+        gta.unwrap().acquisition_period = None;
 
-    // Expect =
-    if !after_href.starts_with('=') {
-        return None;
+        sort_chronologically(&mut packages);
+        let names: Vec<_> = packages.iter().map(|p| p.package_name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "LEAP East",
+                "Cochrane A",
+                "Cochrane B",
+                "Ottawa River A",
+                "GTA 1"
+            ]
+        );
+        assert_eq!(
+            project_names(&packages),
+            vec![
+                "LEAP 2009",
+                "OMAFRA Lidar 2016-18",
+                "OMAFRA Lidar 2022",
+                "GTA 2014-18"
+            ]
+        );
     }
-    let after_eq = &after_href[1..];
 
-    // Skip whitespace after =
-    let after_eq = after_eq.trim_start();
-
-    // Expect quote
-    let (quote, rest) = if after_eq.starts_with('"') {
-        ('"', &after_eq[1..])
-    } else if after_eq.starts_with('\'') {
-        ('\'', &after_eq[1..])
-    } else {
-        return None;
-    };
-
-    // Find closing quote
-    if let Some(end) = rest.find(quote) {
-        return Some(rest[..end].to_string());
+    #[test]
+    fn test_extract_year_range_19xx() {
+        assert_eq!(
+            extract_year_range("Old Data 1999"),
+            Some("1999".to_string())
+        );
     }
-
-    None
-}
-
-/// Extract year range from project name.
-/// Examples: "OMAFRA Lidar 2016-18" -> "2016-18", "GTA 2014" -> "2014"
-pub fn extract_year_range(project: &str) -> Option<String> {
-    let re = regex::Regex::new(r"\b(19|20)\d{2}(?:\s*[-–]\s*(?:19|20)?\d{2})?\b").ok()?;
-    let match_opt = re.find(project)?;
-    Some(match_opt.as_str().replace(" ", ""))
 }
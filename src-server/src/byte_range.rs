@@ -0,0 +1,474 @@
+//! Serving files with HTTP validators and byte ranges.
+//!
+//! Job outputs can be many gigabytes. Serving them with `Content-Length`,
+//! `ETag`, `Last-Modified` and `Range` support lets browsers resume dropped
+//! downloads and lets GDAL clients read the COG in place over `/vsicurl/`.
+
+use std::io::SeekFrom;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::export::civil_from_days;
+
+/// Ranges beyond this count are answered with the whole file.
+const MAX_RANGES: usize = 64;
+
+/// Parse an HTTP `Range` header into sorted, non-overlapping inclusive byte ranges.
+///
+/// Returns `None` for a header that is not a valid `bytes` range, and an
+/// empty list when none of the ranges lie within the file.
+pub fn parse_ranges(value: &str, total: u64) -> Option<Vec<(u64, u64)>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+
+    for part in spec.split(',') {
+        let (first, last) = part.trim().split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+        let range = match (first, last) {
+            ("", "") => return None,
+            ("", suffix) => {
+                let len: u64 = suffix.parse().ok()?;
+                (len > 0 && total > 0).then(|| (total.saturating_sub(len), total - 1))
+            }
+            (start, end) => {
+                let start: u64 = start.parse().ok()?;
+                let end: u64 = if end.is_empty() {
+                    u64::MAX
+                } else {
+                    end.parse().ok()?
+                };
+                if start > end {
+                    return None;
+                }
+                (start < total).then(|| (start, end.min(total - 1)))
+            }
+        };
+        ranges.extend(range);
+    }
+
+    // Overlapping or adjacent ranges are coalesced.
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end)
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(merged)
+}
+
+/// Format a time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let days = secs.div_euclid(86_400);
+    let time_of_day = secs.rem_euclid(86_400);
+    let [year, month, day] = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60
+    )
+}
+
+/// The bytes of a file served so far, as merged half-open ranges.
+///
+/// Lets a caller tell a client that fetched the whole file, possibly over
+/// several resumed requests, from one that only read parts of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ByteCoverage {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl ByteCoverage {
+    /// Record bytes `start..end`.
+    pub fn add(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        self.ranges.push((start, end));
+        self.ranges.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(self.ranges.len());
+        for &(start, end) in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    /// Whether every byte of a `total`-byte file has been served.
+    pub fn covers(&self, total: u64) -> bool {
+        total == 0
+            || self
+                .ranges
+                .first()
+                .is_some_and(|&(start, end)| start == 0 && end >= total)
+    }
+}
+
+/// Serve `path` for a GET or HEAD request, honouring `Range`, `If-Range`,
+/// `If-None-Match` and `If-Modified-Since`.
+///
+/// `on_served` gets the half-open byte ranges of a body and the file size
+/// once that body has been streamed in full; interrupted and HEAD responses
+/// do not call it.
+pub async fn serve_file(
+    path: &Path,
+    content_type: &str,
+    disposition: Option<String>,
+    method: &Method,
+    headers: &HeaderMap,
+    on_served: impl FnOnce(Vec<(u64, u64)>, u64) + Send + 'static,
+) -> std::io::Result<Response> {
+    let file = tokio::fs::File::open(path).await?;
+    let meta = file.metadata().await?;
+    let total = meta.len();
+    let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+    let mtime = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let etag = format!("\"{:x}-{:x}\"", total, mtime);
+    let last_modified = http_date(modified);
+
+    let request_header = |name: header::HeaderName| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+
+    let not_modified = match request_header(header::IF_NONE_MATCH) {
+        Some(tags) => tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag),
+        None => request_header(header::IF_MODIFIED_SINCE) == Some(last_modified.as_str()),
+    };
+
+    let mut builder = Response::builder()
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, &etag)
+        .header(header::LAST_MODIFIED, &last_modified);
+    if let Some(disposition) = &disposition {
+        builder = builder.header(header::CONTENT_DISPOSITION, disposition);
+    }
+
+    if not_modified {
+        return Ok(builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap_or_default());
+    }
+
+    // A stale If-Range validator means the client gets the whole new file.
+    let range_applies = match request_header(header::IF_RANGE) {
+        Some(validator) => validator == etag || validator == last_modified,
+        None => true,
+    };
+    let ranges = request_header(header::RANGE)
+        .filter(|_| range_applies)
+        .and_then(|value| parse_ranges(value, total))
+        .filter(|ranges| ranges.len() <= MAX_RANGES);
+
+    let head = method == Method::HEAD;
+    match ranges.as_deref() {
+        None => {
+            let body = if head {
+                Body::empty()
+            } else {
+                ranges_body(file, vec![(0, total)], None, total, on_served)
+            };
+            Ok(builder
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_LENGTH, total)
+                .body(body)
+                .unwrap_or_default())
+        }
+        Some([]) => Ok((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{}", total))],
+        )
+            .into_response()),
+        Some(&[(start, end)]) => {
+            let body = if head {
+                Body::empty()
+            } else {
+                ranges_body(file, vec![(start, end + 1)], None, total, on_served)
+            };
+            Ok(builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, total),
+                )
+                .body(body)
+                .unwrap_or_default())
+        }
+        Some(ranges) => {
+            let boundary = uuid::Uuid::new_v4().simple().to_string();
+            let part_headers: Vec<String> = ranges
+                .iter()
+                .map(|(start, end)| {
+                    format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, content_type, start, end, total
+                    )
+                })
+                .collect();
+            let closing = format!("\r\n--{}--\r\n", boundary);
+            let length = part_headers.iter().map(|h| h.len() as u64).sum::<u64>()
+                + ranges.iter().map(|(s, e)| e - s + 1).sum::<u64>()
+                + closing.len() as u64;
+
+            let body = if head {
+                Body::empty()
+            } else {
+                let parts = ranges
+                    .iter()
+                    .map(|&(start, end)| (start, end + 1))
+                    .collect();
+                ranges_body(file, parts, Some((part_headers, closing)), total, on_served)
+            };
+            Ok(builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary))
+                        .expect("boundary is a valid header value"),
+                )
+                .header(header::CONTENT_LENGTH, length)
+                .body(body)
+                .unwrap_or_default())
+        }
+    }
+}
+
+/// Stream half-open `parts` of `file`, each preceded by its multipart header when given.
+fn ranges_body(
+    mut file: tokio::fs::File,
+    parts: Vec<(u64, u64)>,
+    multipart: Option<(Vec<String>, String)>,
+    total: u64,
+    on_served: impl FnOnce(Vec<(u64, u64)>, u64) + Send + 'static,
+) -> Body {
+    let stream = async_stream::stream! {
+        let (part_headers, closing) = match multipart {
+            Some((headers, closing)) => (Some(headers), Some(closing)),
+            None => (None, None),
+        };
+        for (i, &(start, end)) in parts.iter().enumerate() {
+            if let Some(part_headers) = &part_headers {
+                yield Ok(Bytes::from(part_headers[i].clone()));
+            }
+            if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+                yield Err(e);
+                return;
+            }
+            let mut reader = tokio_util::io::ReaderStream::new((&mut file).take(end - start));
+            while let Some(chunk) = reader.next().await {
+                let failed = chunk.is_err();
+                yield chunk;
+                if failed {
+                    return;
+                }
+            }
+        }
+        if let Some(closing) = closing {
+            yield Ok(Bytes::from(closing));
+        }
+        on_served(parts, total);
+    };
+    Body::from_stream(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(parse_ranges("bytes=0-99", 1000), Some(vec![(0, 99)]));
+        assert_eq!(parse_ranges("bytes=900-", 1000), Some(vec![(900, 999)]));
+        assert_eq!(parse_ranges("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(parse_ranges("bytes=990-2000", 1000), Some(vec![(990, 999)]));
+        assert_eq!(
+            parse_ranges("bytes=50-59, 10-19,15-30", 1000),
+            Some(vec![(10, 30), (50, 59)])
+        );
+        assert_eq!(parse_ranges("bytes=1000-", 1000), Some(vec![]));
+        assert_eq!(parse_ranges("bytes=20-10", 1000), None);
+        assert_eq!(parse_ranges("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(
+            http_date(UNIX_EPOCH + std::time::Duration::from_secs(784_111_777)),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+    }
+
+    async fn serve(
+        path: &Path,
+        method: Method,
+        headers: &[(header::HeaderName, &str)],
+    ) -> Response {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        serve_file(path, "image/tiff", None, &method, &map, |_, _| {})
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_byte_coverage_needs_every_byte() {
+        let mut coverage = ByteCoverage::default();
+        // A tail probe and the last tile, as a /vsicurl/ reader sends.
+        coverage.add(984, 1000);
+        coverage.add(600, 800);
+        assert!(!coverage.covers(1000));
+        // A download resumed after 600 bytes.
+        coverage.add(0, 600);
+        assert!(!coverage.covers(1000));
+        coverage.add(790, 990);
+        assert!(coverage.covers(1000));
+        assert!(ByteCoverage::default().covers(0));
+    }
+
+    #[tokio::test]
+    async fn test_served_ranges_are_reported_after_the_body() {
+        let path = std::env::temp_dir().join(format!("dtm-range-{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, vec![7u8; 100]).unwrap();
+        let served = Arc::new(Mutex::new(Vec::new()));
+
+        for (method, range) in [
+            (Method::GET, "bytes=-16"),
+            (Method::HEAD, "bytes=0-9"),
+            (Method::GET, "bytes=0-9,20-29"),
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+            let log = served.clone();
+            let response = serve_file(
+                &path,
+                "image/tiff",
+                None,
+                &method,
+                &headers,
+                move |parts, total| {
+                    log.lock().unwrap().push((parts, total));
+                },
+            )
+            .await
+            .unwrap();
+            to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        }
+
+        assert_eq!(
+            *served.lock().unwrap(),
+            vec![(vec![(84, 100)], 100), (vec![(0, 10), (20, 30)], 100)]
+        );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_serve_file_ranges_and_validators() {
+        let path = std::env::temp_dir().join(format!("dtm-range-{}.bin", uuid::Uuid::new_v4()));
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let full = serve(&path, Method::GET, &[]).await;
+        assert_eq!(full.status(), StatusCode::OK);
+        assert_eq!(full.headers()[header::CONTENT_LENGTH], "1000");
+        assert_eq!(full.headers()[header::ACCEPT_RANGES], "bytes");
+        let etag = full.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(to_bytes(full.into_body(), usize::MAX).await.unwrap(), data);
+
+        let head = serve(&path, Method::HEAD, &[]).await;
+        assert_eq!(head.headers()[header::CONTENT_LENGTH], "1000");
+        assert!(to_bytes(head.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let single = serve(&path, Method::GET, &[(header::RANGE, "bytes=100-199")]).await;
+        assert_eq!(single.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            single.headers()[header::CONTENT_RANGE],
+            "bytes 100-199/1000"
+        );
+        assert_eq!(
+            to_bytes(single.into_body(), usize::MAX).await.unwrap(),
+            data[100..200]
+        );
+
+        let multi = serve(&path, Method::GET, &[(header::RANGE, "bytes=0-9,-10")]).await;
+        assert_eq!(multi.status(), StatusCode::PARTIAL_CONTENT);
+        let content_type = multi.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let boundary = content_type.split("boundary=").nth(1).unwrap().to_string();
+        let length: usize = multi.headers()[header::CONTENT_LENGTH]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let body = to_bytes(multi.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.len(), length);
+        let text = String::from_utf8_lossy(&body);
+        assert!(text.contains("Content-Range: bytes 0-9/1000"));
+        assert!(text.contains("Content-Range: bytes 990-999/1000"));
+        assert!(text.ends_with(&format!("--{}--\r\n", boundary)));
+
+        let unsatisfiable = serve(&path, Method::GET, &[(header::RANGE, "bytes=5000-")]).await;
+        assert_eq!(unsatisfiable.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            unsatisfiable.headers()[header::CONTENT_RANGE],
+            "bytes */1000"
+        );
+
+        let cached = serve(&path, Method::GET, &[(header::IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+
+        let stale = serve(
+            &path,
+            Method::GET,
+            &[(header::RANGE, "bytes=0-9"), (header::IF_RANGE, "\"old\"")],
+        )
+        .await;
+        assert_eq!(stale.status(), StatusCode::OK);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,618 @@
+//! Range reads of remote Cloud Optimized GeoTIFFs with an on-disk block cache.
+//!
+//! GDAL opens remote COGs through `/vsicurl/` and only requests the byte
+//! ranges covering the tiles it needs. Instead of the upstream URL, GDAL is
+//! pointed at a small local proxy that serves those ranges from fixed-size
+//! blocks cached on disk, so later jobs over the same area reuse them.
+//! Remote files are assumed not to change once published.
+//!
+//! The cache is capped at `DTM_COG_CACHE_MB`; once a write takes it over
+//! the cap, the least recently used blocks are deleted.
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use axum::body::Body;
+use axum::extract::{Path as UrlPath, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures::Stream;
+use thiserror::Error;
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::byte_range::parse_ranges;
+
+/// Size of the cached blocks. GDAL reads COG headers and tiles in chunks of
+/// 16 KiB and up, so a few hundred KiB keeps the request count low without
+/// fetching much outside the requested tiles.
+pub const DEFAULT_BLOCK_SIZE: u64 = 256 * 1024;
+
+/// Block cache size unless `DTM_COG_CACHE_MB` is set.
+const DEFAULT_CACHE_MB: u64 = 2048;
+
+#[derive(Debug, Error)]
+pub enum CogCacheError {
+    #[error("HTTP request failed: {0}")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("Server does not support range requests: {0}")]
+    RangeNotSupported(String),
+}
+
+/// Byte blocks of remote files, cached under one directory per URL.
+pub struct BlockCache {
+    dir: PathBuf,
+    block_size: u64,
+    /// Cap on the bytes of cached blocks; zero means unlimited.
+    max_bytes: u64,
+    /// Bytes of cached blocks, counted from disk on the first write.
+    used: Mutex<Option<u64>>,
+    client: reqwest::Client,
+}
+
+impl BlockCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_bytes: 0,
+            used: Mutex::new(None),
+            client: reqwest::Client::builder()
+                .user_agent("OntarioDTMDownloader/1.0")
+                .build()
+                .unwrap(),
+        }
+    }
+
+    pub fn with_block_size(mut self, block_size: u64) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Cache in `dir` capped by `DTM_COG_CACHE_MB`, `0` for no cap.
+    pub fn from_env(dir: impl Into<PathBuf>) -> Self {
+        let mb = std::env::var("DTM_COG_CACHE_MB")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_CACHE_MB);
+        Self::new(dir).with_max_bytes(mb * 1024 * 1024)
+    }
+
+    fn entry_dir(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}", fnv1a(url.as_bytes())))
+    }
+
+    /// Size of the remote file in bytes.
+    pub async fn content_length(&self, url: &str) -> Result<u64, CogCacheError> {
+        let size_path = self.entry_dir(url).join("size");
+        if let Ok(text) = tokio::fs::read_to_string(&size_path).await {
+            if let Ok(size) = text.trim().parse() {
+                return Ok(size);
+            }
+        }
+
+        let response = self
+            .client
+            .get(url)
+            .header(header::RANGE, "bytes=0-0")
+            .send()
+            .await?
+            .error_for_status()?;
+        let size = response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit('/').next())
+            .and_then(|total| total.trim().parse().ok())
+            .or_else(|| {
+                (response.status() == reqwest::StatusCode::OK)
+                    .then(|| response.content_length())
+                    .flatten()
+            })
+            .ok_or_else(|| CogCacheError::RangeNotSupported(url.to_string()))?;
+
+        write_atomic(&size_path, size.to_string().as_bytes()).await?;
+        Ok(size)
+    }
+
+    /// Bytes `start..=end` of the remote file, fetching only missing blocks.
+    pub async fn read_range(
+        &self,
+        url: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<u8>, CogCacheError> {
+        let total = self.content_length(url).await?;
+        let end = end.min(total.saturating_sub(1));
+        let mut data = Vec::with_capacity((end + 1).saturating_sub(start) as usize);
+        if start > end {
+            return Ok(data);
+        }
+        for index in start / self.block_size..=end / self.block_size {
+            data.extend(self.block_slice(url, index, total, start, end).await?);
+        }
+        Ok(data)
+    }
+
+    /// Like [`Self::read_range`], but yields one block at a time so a large
+    /// read is never held in memory. `total` is the remote file size.
+    pub fn stream_range(
+        self: Arc<Self>,
+        url: String,
+        start: u64,
+        end: u64,
+        total: u64,
+    ) -> impl Stream<Item = Result<Vec<u8>, CogCacheError>> {
+        let end = end.min(total.saturating_sub(1));
+        async_stream::try_stream! {
+            if start <= end {
+                for index in start / self.block_size..=end / self.block_size {
+                    yield self.block_slice(&url, index, total, start, end).await?;
+                }
+            }
+        }
+    }
+
+    /// The part of block `index` that falls within `start..=end`.
+    async fn block_slice(
+        &self,
+        url: &str,
+        index: u64,
+        total: u64,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<u8>, CogCacheError> {
+        let mut block = self.block(url, index, total).await?;
+        let block_start = index * self.block_size;
+        let to = ((end - block_start + 1) as usize).min(block.len());
+        block.truncate(to);
+        block.drain(..start.saturating_sub(block_start) as usize);
+        Ok(block)
+    }
+
+    async fn block(&self, url: &str, index: u64, total: u64) -> Result<Vec<u8>, CogCacheError> {
+        let entry_dir = self.entry_dir(url);
+        let path = entry_dir.join(index.to_string());
+        if let Ok(data) = tokio::fs::read(&path).await {
+            // The modification time doubles as the last use for eviction.
+            let touched = path.clone();
+            let _ = tokio::task::spawn_blocking(move || {
+                std::fs::File::options()
+                    .write(true)
+                    .open(&touched)
+                    .and_then(|file| file.set_modified(SystemTime::now()))
+            })
+            .await;
+            return Ok(data);
+        }
+
+        let start = index * self.block_size;
+        let end = (start + self.block_size).min(total) - 1;
+        let response = self
+            .client
+            .get(url)
+            .header(header::RANGE, format!("bytes={}-{}", start, end))
+            .send()
+            .await?
+            .error_for_status()?;
+        let status = response.status();
+        let body = response.bytes().await?;
+
+        match status {
+            reqwest::StatusCode::PARTIAL_CONTENT => {
+                write_atomic(&path, &body).await?;
+                self.record_write(body.len() as u64).await;
+                Ok(body.to_vec())
+            }
+            // The whole file came back, e.g. because the range covered all
+            // of it; keep every block rather than discarding the rest.
+            reqwest::StatusCode::OK if body.len() as u64 == total => {
+                for (i, chunk) in body.chunks(self.block_size as usize).enumerate() {
+                    write_atomic(&entry_dir.join(i.to_string()), chunk).await?;
+                }
+                self.record_write(total).await;
+                Ok(body[start as usize..=end as usize].to_vec())
+            }
+            _ => Err(CogCacheError::RangeNotSupported(url.to_string())),
+        }
+    }
+}
+
+impl BlockCache {
+    /// Count `bytes` of new blocks and evict down to 90% of the cap once over it.
+    async fn record_write(&self, bytes: u64) {
+        if self.max_bytes == 0 {
+            return;
+        }
+        let mut used = self.used.lock().await;
+        let current = match *used {
+            Some(current) => current + bytes,
+            // The first write of this process; the scan already includes it.
+            None => {
+                let dir = self.dir.clone();
+                tokio::task::spawn_blocking(move || {
+                    cached_blocks(&dir).iter().map(|block| block.1).sum()
+                })
+                .await
+                .unwrap_or(bytes)
+            }
+        };
+        if current <= self.max_bytes {
+            *used = Some(current);
+            return;
+        }
+        let dir = self.dir.clone();
+        let target = self.max_bytes / 10 * 9;
+        *used = tokio::task::spawn_blocking(move || evict(&dir, target))
+            .await
+            .ok();
+    }
+}
+
+/// Every cached block as `(last used, length, path)`.
+fn cached_blocks(dir: &Path) -> Vec<(SystemTime, u64, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| std::fs::read_dir(entry.path()).ok())
+        .flat_map(|files| files.flatten())
+        // Blocks are named by their index; skip `size` files and partial writes.
+        .filter(|file| file.file_name().to_string_lossy().parse::<u64>().is_ok())
+        .filter_map(|file| {
+            let meta = file.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), file.path()))
+        })
+        .collect()
+}
+
+/// Delete the least recently used blocks until at most `target` bytes remain.
+///
+/// Returns the bytes left. URLs left without blocks are removed entirely.
+fn evict(dir: &Path, target: u64) -> u64 {
+    let mut blocks = cached_blocks(dir);
+    blocks.sort();
+    let mut used: u64 = blocks.iter().map(|block| block.1).sum();
+    for (_, len, path) in blocks {
+        if used <= target {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => used -= len,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => used -= len,
+            Err(e) => eprintln!("Failed to evict {}: {}", path.display(), e),
+        }
+        if let Some(entry) = path.parent() {
+            let has_blocks = std::fs::read_dir(entry).is_ok_and(|mut files| {
+                files.any(|file| {
+                    file.is_ok_and(|f| f.file_name().to_string_lossy().parse::<u64>().is_ok())
+                })
+            });
+            if !has_blocks {
+                let _ = std::fs::remove_dir_all(entry);
+            }
+        }
+    }
+    used
+}
+
+/// Write through a temporary file so concurrent readers never see a partial block.
+async fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+/// 64-bit FNV-1a, stable across builds so cache directories survive upgrades.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Start a proxy on a free loopback port that serves remote files through `cache`.
+///
+/// A file is addressed as `/{hex-encoded URL}.tif`, see [`proxied_url`].
+pub async fn start_proxy(cache: BlockCache) -> io::Result<SocketAddr> {
+    let app = axum::Router::new()
+        .route("/{name}", axum::routing::get(serve_range))
+        .with_state(Arc::new(cache));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            eprintln!("COG proxy stopped: {}", e);
+        }
+    });
+    Ok(addr)
+}
+
+/// The process-wide proxy, started on first use with blocks cached in `dir`.
+pub async fn shared_proxy(dir: &Path) -> io::Result<SocketAddr> {
+    static PROXY: OnceCell<SocketAddr> = OnceCell::const_new();
+    PROXY
+        .get_or_try_init(|| start_proxy(BlockCache::from_env(dir)))
+        .await
+        .copied()
+}
+
+/// URL under which the proxy at `addr` serves `url`.
+pub fn proxied_url(addr: SocketAddr, url: &str) -> String {
+    let hex: String = url.bytes().map(|b| format!("{:02x}", b)).collect();
+    format!("http://{}/{}.tif", addr, hex)
+}
+
+fn decode_proxied_name(name: &str) -> Option<String> {
+    let hex = name.strip_suffix(".tif")?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Parse an HTTP `Range` header into an inclusive byte range.
+///
+/// Multiple ranges are answered with the single range spanning all of them.
+/// Returns `None` when no part of the range lies within the file.
+fn parse_range(value: &str, total: u64) -> Option<(u64, u64)> {
+    let ranges = parse_ranges(value, total)?;
+    Some((ranges.first()?.0, ranges.last()?.1))
+}
+
+async fn serve_range(
+    State(cache): State<Arc<BlockCache>>,
+    UrlPath(name): UrlPath<String>,
+    method: Method,
+    headers: HeaderMap,
+) -> Response {
+    // GDAL also probes for sidecar files and directory listings; those are
+    // not hex-encoded URLs and are answered with 404.
+    let Some(url) = decode_proxied_name(&name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let total = match cache.content_length(&url).await {
+        Ok(total) => total,
+        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    };
+
+    let requested = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+    let (status, start, end) = match requested {
+        Some(value) => match parse_range(value, total) {
+            Some((start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+            None => {
+                return (
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, format!("bytes */{}", total))],
+                )
+                    .into_response()
+            }
+        },
+        None => (StatusCode::OK, 0, total.saturating_sub(1)),
+    };
+    let length = if total == 0 { 0 } else { end - start + 1 };
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_TYPE, "image/tiff")
+        .header(header::CONTENT_LENGTH, length);
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, total),
+        );
+    }
+
+    let body = if method == Method::HEAD || length == 0 {
+        Body::empty()
+    } else {
+        Body::from_stream(cache.stream_range(url, start, end, total))
+    };
+    response.body(body).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Upstream {
+        data: Vec<u8>,
+        requests: AtomicUsize,
+    }
+
+    /// A file server with range support that counts the requests it receives.
+    async fn spawn_upstream(len: usize) -> (String, Arc<Upstream>) {
+        let upstream = Arc::new(Upstream {
+            data: (0..len).map(|i| (i % 251) as u8).collect(),
+            requests: AtomicUsize::new(0),
+        });
+        let app = axum::Router::new()
+            .route(
+                "/dem.tif",
+                axum::routing::get(
+                    |State(upstream): State<Arc<Upstream>>, headers: HeaderMap| async move {
+                        upstream.requests.fetch_add(1, Ordering::SeqCst);
+                        let total = upstream.data.len() as u64;
+                        let range = headers
+                            .get(header::RANGE)
+                            .and_then(|v| v.to_str().ok())
+                            .and_then(|v| parse_range(v, total));
+                        match range {
+                            Some((start, end)) => (
+                                StatusCode::PARTIAL_CONTENT,
+                                [(
+                                    header::CONTENT_RANGE,
+                                    format!("bytes {}-{}/{}", start, end, total),
+                                )],
+                                upstream.data[start as usize..=end as usize].to_vec(),
+                            )
+                                .into_response(),
+                            None => upstream.data.clone().into_response(),
+                        }
+                    },
+                ),
+            )
+            .with_state(upstream.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/dem.tif", addr), upstream)
+    }
+
+    fn temp_cache_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dtm-cog-cache-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some((990, 999)));
+        assert_eq!(parse_range("bytes=10-19, 50-59", 1000), Some((10, 59)));
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_proxied_url_round_trips() {
+        let url = "https://example.com/hrdem/dtm_1m.tif?sig=a%2Fb";
+        let proxied = proxied_url("127.0.0.1:8080".parse().unwrap(), url);
+        let name = proxied.rsplit('/').next().unwrap();
+        assert_eq!(decode_proxied_name(name).as_deref(), Some(url));
+        assert_eq!(decode_proxied_name("dtm.tif.aux.xml"), None);
+    }
+
+    #[tokio::test]
+    async fn test_only_touched_blocks_are_fetched_and_reused() {
+        let (url, upstream) = spawn_upstream(10_000).await;
+        let dir = temp_cache_dir("blocks");
+        let cache = BlockCache::new(&dir).with_block_size(1024);
+
+        let data = cache.read_range(&url, 1000, 2100).await.unwrap();
+        assert_eq!(data, upstream.data[1000..=2100]);
+        // One size probe plus blocks 0, 1 and 2.
+        assert_eq!(upstream.requests.load(Ordering::SeqCst), 4);
+
+        let entry = cache.entry_dir(&url);
+        let mut blocks: Vec<_> = std::fs::read_dir(&entry)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name != "size")
+            .collect();
+        blocks.sort();
+        assert_eq!(blocks, vec!["0", "1", "2"]);
+
+        // A second cache over the same directory, as in a later job.
+        let cache = BlockCache::new(&dir).with_block_size(1024);
+        let data = cache.read_range(&url, 1500, 1600).await.unwrap();
+        assert_eq!(data, upstream.data[1500..=1600]);
+        assert_eq!(upstream.requests.load(Ordering::SeqCst), 4);
+
+        // The last block is shorter than the block size.
+        let data = cache.read_range(&url, 9990, 20_000).await.unwrap();
+        assert_eq!(data, upstream.data[9990..]);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_blocks_are_evicted() {
+        let (url, upstream) = spawn_upstream(10_000).await;
+        let dir = temp_cache_dir("evict");
+        let cache = BlockCache::new(&dir)
+            .with_block_size(1024)
+            .with_max_bytes(3 * 1024);
+        let pause = || tokio::time::sleep(std::time::Duration::from_millis(20));
+
+        for index in 0..3 {
+            cache
+                .read_range(&url, index * 1024, index * 1024)
+                .await
+                .unwrap();
+            pause().await;
+        }
+        // Using block 0 again makes block 1 the least recently used.
+        cache.read_range(&url, 0, 0).await.unwrap();
+        pause().await;
+        // Block 3 takes the cache over its cap; it is cut back to 90%.
+        cache.read_range(&url, 3 * 1024, 3 * 1024).await.unwrap();
+
+        let mut blocks: Vec<_> = cached_blocks(&dir)
+            .into_iter()
+            .map(|(_, _, path)| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        blocks.sort();
+        assert_eq!(blocks, vec!["0", "3"]);
+
+        // Evicted blocks are fetched again on demand.
+        let requests = upstream.requests.load(Ordering::SeqCst);
+        let data = cache.read_range(&url, 1024, 1100).await.unwrap();
+        assert_eq!(data, upstream.data[1024..=1100]);
+        assert_eq!(upstream.requests.load(Ordering::SeqCst), requests + 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_serves_ranges() {
+        let (url, upstream) = spawn_upstream(5000).await;
+        let dir = temp_cache_dir("proxy");
+        let addr = start_proxy(BlockCache::new(&dir).with_block_size(1024))
+            .await
+            .unwrap();
+        let client = reqwest::Client::new();
+        let proxied = proxied_url(addr, &url);
+
+        let response = client
+            .get(&proxied)
+            .header(header::RANGE, "bytes=100-2047")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            "bytes 100-2047/5000"
+        );
+        assert_eq!(response.bytes().await.unwrap(), upstream.data[100..2048]);
+
+        // Without a Range header the whole file is streamed block by block.
+        let response = client.get(&proxied).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.bytes().await.unwrap(), upstream.data);
+
+        let response = client.head(&proxied).send().await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "5000");
+        assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+
+        let sidecar = format!("{}.aux.xml", proxied);
+        let response = client.get(&sidecar).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,113 @@
+//! Coverage analysis of package footprints against an area of interest.
+
+use geo::{unary_union, Area, BooleanOps, MultiPolygon, Orient};
+
+use crate::api_types::{ClipExtentRequest, CoverageReport, GeoJSONGeometry, Package};
+
+/// Uncovered share (in percent) below which coverage is considered complete.
+/// Absorbs floating point slivers along shared package edges.
+pub(crate) const COVERAGE_TOLERANCE_PERCENT: f64 = 0.01;
+
+/// Compute which part of `area` is not covered by the union of the package footprints.
+pub fn compute_coverage(area: &GeoJSONGeometry, packages: &[Package]) -> CoverageReport {
+    let area = normalize(area.to_multi_polygon());
+    let footprints: Vec<MultiPolygon<f64>> = packages
+        .iter()
+        .map(|pkg| normalize(pkg.geometry.to_multi_polygon()))
+        .collect();
+    let union = unary_union(&footprints);
+    let uncovered = area.difference(&union);
+
+    let total_area = area.unsigned_area();
+    let uncovered_percentage = if total_area > 0.0 {
+        (uncovered.unsigned_area() / total_area * 100.0).clamp(0.0, 100.0)
+    } else {
+        0.0
+    };
+
+    CoverageReport {
+        uncovered: GeoJSONGeometry::from_multi_polygon(&uncovered),
+        covered_percentage: 100.0 - uncovered_percentage,
+        uncovered_percentage,
+        is_complete: uncovered_percentage < COVERAGE_TOLERANCE_PERCENT,
+    }
+}
+
+/// Build a rectangular polygon from a clip extent.
+pub fn extent_to_polygon(extent: &ClipExtentRequest) -> GeoJSONGeometry {
+    GeoJSONGeometry::Polygon(vec![vec![
+        vec![extent.min_x, extent.min_y],
+        vec![extent.max_x, extent.min_y],
+        vec![extent.max_x, extent.max_y],
+        vec![extent.min_x, extent.max_y],
+        vec![extent.min_x, extent.min_y],
+    ]])
+}
+
+/// Orient rings consistently (counter-clockwise exteriors) so boolean
+/// operations treat every input the same way.
+pub(crate) fn normalize(multi_polygon: MultiPolygon<f64>) -> MultiPolygon<f64> {
+    multi_polygon.orient(geo::orient::Direction::Default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::PackageBuilder;
+
+    fn square_package(name: &str, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Package {
+        PackageBuilder::new(name)
+            .geometry(extent(min_x, min_y, max_x, max_y))
+            .build()
+    }
+
+    fn extent(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> GeoJSONGeometry {
+        extent_to_polygon(&ClipExtentRequest {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            srid: 3857,
+        })
+    }
+
+    #[test]
+    fn test_full_coverage() {
+        let packages = vec![
+            square_package("A", 0.0, 0.0, 6.0, 10.0),
+            square_package("B", 5.0, 0.0, 10.0, 10.0),
+        ];
+        let report = compute_coverage(&extent(1.0, 1.0, 9.0, 9.0), &packages);
+        assert!(report.is_complete);
+        assert!(report.uncovered_percentage < 1e-9);
+    }
+
+    #[test]
+    fn test_partial_coverage_reports_gap() {
+        let packages = vec![square_package("A", 0.0, 0.0, 5.0, 10.0)];
+        let report = compute_coverage(&extent(0.0, 0.0, 10.0, 10.0), &packages);
+        assert!(!report.is_complete);
+        assert!((report.uncovered_percentage - 50.0).abs() < 1e-9);
+        assert!((report.covered_percentage - 50.0).abs() < 1e-9);
+
+        let uncovered = report.uncovered.to_multi_polygon();
+        assert!((uncovered.unsigned_area() - 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_clockwise_footprints_are_handled() {
+        let mut package = square_package("A", 0.0, 0.0, 10.0, 10.0);
+        if let GeoJSONGeometry::Polygon(rings) = &mut package.geometry {
+            rings[0].reverse();
+        }
+        let report = compute_coverage(&extent(2.0, 2.0, 8.0, 8.0), &[package]);
+        assert!(report.is_complete);
+    }
+
+    #[test]
+    fn test_no_packages_leaves_everything_uncovered() {
+        let report = compute_coverage(&extent(0.0, 0.0, 10.0, 10.0), &[]);
+        assert!(!report.is_complete);
+        assert!((report.uncovered_percentage - 100.0).abs() < 1e-9);
+    }
+}
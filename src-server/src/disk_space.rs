@@ -0,0 +1,202 @@
+//! Free disk space checks for the cache and work directories.
+//!
+//! Jobs check before they start, while downloading and extracting, and from
+//! a monitor task during processing, so that a full volume stops the job
+//! with [`InsufficientDiskSpace`] instead of failing halfway through a write.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use thiserror::Error;
+
+/// Free space kept in reserve unless `DTM_MIN_FREE_MB` is set.
+const DEFAULT_MIN_FREE_MB: u64 = 512;
+
+/// How often the monitor re-checks free space while a job runs.
+pub const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Bytes written between free space checks while downloading.
+pub const CHECK_EVERY_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+#[error("Not enough disk space in {path}: {available} bytes free, {required} bytes needed")]
+pub struct InsufficientDiskSpace {
+    pub path: String,
+    pub available: u64,
+    pub required: u64,
+}
+
+/// Free space to keep on every volume, from `DTM_MIN_FREE_MB`.
+pub fn min_free_bytes() -> u64 {
+    std::env::var("DTM_MIN_FREE_MB")
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MIN_FREE_MB)
+        * 1024
+        * 1024
+}
+
+/// Free space available to this process on the filesystem holding `path`.
+///
+/// Directories that do not exist yet are measured at their nearest existing ancestor.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    fs2::available_space(existing).ok()
+}
+
+/// Whether two paths, or their nearest existing ancestors, share a filesystem.
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let device = |path: &Path| {
+            path.ancestors()
+                .find_map(|p| std::fs::metadata(p).ok())
+                .map(|meta| meta.dev())
+        };
+        matches!((device(a), device(b)), (Some(x), Some(y)) if x == y)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        false
+    }
+}
+
+/// Check that `needed` more bytes fit under `path` while keeping the reserve.
+///
+/// Passes when the free space cannot be determined.
+pub fn ensure_space(path: &Path, needed: u64) -> Result<(), InsufficientDiskSpace> {
+    ensure_space_with_reserve(path, needed, min_free_bytes())
+}
+
+/// Check that a job's cache and work directory needs fit, counting both
+/// against the same free space when the directories share a filesystem.
+pub fn ensure_job_space(
+    cache_dir: &Path,
+    cache_bytes: u64,
+    work_dir: &Path,
+    work_bytes: u64,
+) -> Result<(), InsufficientDiskSpace> {
+    if same_filesystem(cache_dir, work_dir) {
+        ensure_space(cache_dir, cache_bytes.saturating_add(work_bytes))
+    } else {
+        ensure_space(cache_dir, cache_bytes)?;
+        ensure_space(work_dir, work_bytes)
+    }
+}
+
+fn ensure_space_with_reserve(
+    path: &Path,
+    needed: u64,
+    reserve: u64,
+) -> Result<(), InsufficientDiskSpace> {
+    let Some(available) = available_space(path) else {
+        return Ok(());
+    };
+    let required = needed.saturating_add(reserve);
+    if available < required {
+        return Err(InsufficientDiskSpace {
+            path: path.to_string_lossy().to_string(),
+            available,
+            required,
+        });
+    }
+    Ok(())
+}
+
+/// Watches free space on a set of directories while a job runs.
+///
+/// When a directory drops below the reserve, the job's cancel flag is set so
+/// running GDAL steps stop, and the error is kept for the job to report.
+pub struct DiskMonitor {
+    failure: Arc<Mutex<Option<InsufficientDiskSpace>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl DiskMonitor {
+    pub fn spawn(paths: Vec<PathBuf>, cancel: Arc<AtomicBool>) -> Self {
+        Self::spawn_with(paths, cancel, min_free_bytes(), MONITOR_INTERVAL)
+    }
+
+    fn spawn_with(
+        paths: Vec<PathBuf>,
+        cancel: Arc<AtomicBool>,
+        reserve: u64,
+        interval: Duration,
+    ) -> Self {
+        let failure = Arc::new(Mutex::new(None));
+        let slot = failure.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let result = paths
+                    .iter()
+                    .try_for_each(|path| ensure_space_with_reserve(path, 0, reserve));
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                    *slot.lock().unwrap() = Some(e);
+                    cancel.store(true, Ordering::Relaxed);
+                    return;
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+        Self { failure, task }
+    }
+
+    /// The error that stopped the job, if the monitor stopped it.
+    pub fn failure(&self) -> Option<InsufficientDiskSpace> {
+        self.failure.lock().unwrap().clone()
+    }
+}
+
+impl Drop for DiskMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_space_reports_shortfall() {
+        let dir = std::env::temp_dir();
+        assert!(ensure_space_with_reserve(&dir, 0, 0).is_ok());
+
+        let err = ensure_space_with_reserve(&dir, u64::MAX / 2, 0).unwrap_err();
+        assert_eq!(err.required, u64::MAX / 2);
+        assert!(err.available < err.required);
+    }
+
+    #[test]
+    fn test_missing_directory_is_measured_at_its_ancestor() {
+        let dir = std::env::temp_dir()
+            .join("dtm-disk-space-missing")
+            .join("job");
+        assert!(available_space(&dir).is_some());
+        assert!(same_filesystem(&dir, &std::env::temp_dir()));
+    }
+
+    #[tokio::test]
+    async fn test_monitor_cancels_job_when_space_runs_out() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let monitor = DiskMonitor::spawn_with(
+            vec![std::env::temp_dir()],
+            cancel.clone(),
+            u64::MAX,
+            Duration::from_millis(10),
+        );
+        for _ in 0..100 {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(cancel.load(Ordering::Relaxed));
+        assert_eq!(monitor.failure().unwrap().required, u64::MAX);
+    }
+}
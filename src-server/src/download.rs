@@ -9,6 +9,7 @@ use tokio::sync::broadcast;
 use zip::ZipArchive;
 
 use crate::api_types::{DownloadProgressEvent, ProgressEvent};
+use crate::disk_space::{ensure_space, InsufficientDiskSpace, CHECK_EVERY_BYTES};
 
 #[derive(Debug, Error)]
 pub enum DownloadError {
@@ -22,6 +23,8 @@ pub enum DownloadError {
     DirectoryError(String),
     #[error("Server does not support range requests")]
     RangeNotSupported,
+    #[error(transparent)]
+    DiskSpace(#[from] InsufficientDiskSpace),
 }
 
 #[derive(Clone)]
@@ -142,8 +145,12 @@ impl DownloadManager {
             status: "downloading".to_string(),
         }));
 
+        let output_dir = parent_dir(output_path);
+        ensure_space(output_dir, total_bytes)?;
+
         let mut file = File::create(output_path)?;
         let mut downloaded: u64 = 0;
+        let mut last_space_check: u64 = 0;
         let start_time = Instant::now();
         let mut last_update = Instant::now();
 
@@ -153,6 +160,10 @@ impl DownloadManager {
             let chunk = chunk?;
             file.write_all(&chunk)?;
             downloaded += chunk.len() as u64;
+            if downloaded - last_space_check >= CHECK_EVERY_BYTES {
+                ensure_space(output_dir, 0)?;
+                last_space_check = downloaded;
+            }
 
             let now = Instant::now();
             if now.duration_since(last_update).as_millis() > 100 || downloaded == total_bytes {
@@ -234,10 +245,14 @@ impl DownloadManager {
             status: "resuming".to_string(),
         }));
 
+        let output_dir = parent_dir(output_path);
+        ensure_space(output_dir, total_bytes.saturating_sub(partial_size))?;
+
         let mut file = std::fs::OpenOptions::new().write(true).open(output_path)?;
         file.seek(SeekFrom::End(0))?;
 
         let mut downloaded = partial_size;
+        let mut last_space_check = partial_size;
         let start_time = Instant::now();
         let mut last_update = Instant::now();
 
@@ -247,6 +262,10 @@ impl DownloadManager {
             let chunk = chunk?;
             file.write_all(&chunk)?;
             downloaded += chunk.len() as u64;
+            if downloaded - last_space_check >= CHECK_EVERY_BYTES {
+                ensure_space(output_dir, 0)?;
+                last_space_check = downloaded;
+            }
 
             let now = Instant::now();
             if now.duration_since(last_update).as_millis() > 100 || downloaded == total_bytes {
@@ -323,7 +342,7 @@ pub fn check_extraction_complete(zip_path: &str, output_dir: &str) -> Option<Ext
                     return None;
                 }
 
-                let expected_size = zip_file.size() as u64;
+                let expected_size = zip_file.size();
                 let actual_size = std::fs::metadata(&outpath).ok()?.len();
 
                 if actual_size != expected_size {
@@ -335,11 +354,9 @@ pub fn check_extraction_complete(zip_path: &str, output_dir: &str) -> Option<Ext
             }
         };
 
-        if let Some((path, ext)) = result {
-            if let Some(e) = ext {
-                if e == "tif" || e == "tiff" {
-                    tiff_files.push(path);
-                }
+        if let Some((path, Some(e))) = result {
+            if e == "tif" || e == "tiff" {
+                tiff_files.push(path);
             }
         }
     }
@@ -351,6 +368,21 @@ pub fn check_extraction_complete(zip_path: &str, output_dir: &str) -> Option<Ext
     Some(ExtractedFiles { tiff_files })
 }
 
+fn parent_dir(path: &str) -> &Path {
+    Path::new(path).parent().unwrap_or(Path::new("."))
+}
+
+/// Whether `url` points directly at a GeoTIFF rather than a ZIP package,
+/// as with STAC COG assets.
+pub fn is_geotiff_url(url: &str) -> bool {
+    let path = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(url)
+        .to_ascii_lowercase();
+    path.ends_with(".tif") || path.ends_with(".tiff")
+}
+
 pub async fn extract_zip(
     zip_path: &str,
     output_dir: &str,
@@ -370,11 +402,30 @@ pub async fn extract_zip(
         return Ok(extracted.tiff_files);
     }
 
+    let result = extract_all(zip_path, output_dir, package_name, sender).await;
+    if result.is_err() {
+        // Leave no partially extracted package behind; the ZIP is still cached.
+        let _ = std::fs::remove_dir_all(output_dir);
+    }
+    result
+}
+
+async fn extract_all(
+    zip_path: &str,
+    output_dir: &str,
+    package_name: &str,
+    sender: &ProgressSender,
+) -> Result<Vec<String>, DownloadError> {
     let file = File::open(zip_path)?;
     let mut archive = ZipArchive::new(file).map_err(|e| DownloadError::ZipError(e.to_string()))?;
     std::fs::create_dir_all(output_dir)
         .map_err(|e| DownloadError::DirectoryError(e.to_string()))?;
 
+    let uncompressed_size: u64 = (0..archive.len())
+        .filter_map(|i| archive.by_index_raw(i).ok().map(|f| f.size()))
+        .sum();
+    ensure_space(Path::new(output_dir), uncompressed_size)?;
+
     let mut extracted_files = Vec::new();
     let total_files = archive.len();
     let mut last_reported_percent = 0.0;
@@ -410,13 +461,14 @@ pub async fn extract_zip(
                     }
                 }
 
-                let expected_size = file.size() as u64;
+                let expected_size = file.size();
                 let needs_extraction = match std::fs::metadata(&outpath) {
                     Ok(meta) => meta.len() != expected_size,
                     Err(_) => true,
                 };
 
                 if needs_extraction {
+                    ensure_space(Path::new(output_dir), expected_size)?;
                     let mut outfile = File::create(&outpath)?;
                     io::copy(&mut file, &mut outfile)?;
                 }
@@ -464,6 +516,16 @@ mod tests {
         assert!(manager.client.get("https://example.com").build().is_ok());
     }
 
+    #[test]
+    fn test_is_geotiff_url() {
+        assert!(is_geotiff_url("https://example.com/tile-dtm.tif"));
+        assert!(is_geotiff_url("https://example.com/tile.TIFF?sig=abc"));
+        assert!(!is_geotiff_url("https://example.com/package.zip"));
+        assert!(!is_geotiff_url(
+            "https://example.com/download?file=a.zip#x.tif"
+        ));
+    }
+
     #[test]
     fn test_is_download_complete() {
         assert!(!DownloadManager::is_download_complete("/nonexistent", 1000));
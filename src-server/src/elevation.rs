@@ -0,0 +1,289 @@
+//! Spot elevations and profiles read from cached rasters.
+//!
+//! Points are sampled with `gdallocationinfo` straight from a finished job
+//! output or from the packages already in the local cache, without merging.
+//! Each point is answered by the best package whose footprint contains it
+//! and that has data there, falling through to the next package otherwise.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use geo::{Contains, Distance, Geodesic, Point};
+
+use crate::api_types::{Package, SelectionStrategy};
+use crate::download::{check_extraction_complete, is_geotiff_url};
+use crate::processing::{
+    inspect_raster, nodata_equals, remote_cog_input, stream_remote_cogs, ProcessingError,
+};
+use crate::projection::mercator_to_lon_lat;
+use crate::routes::package_cache_key;
+use crate::selection::sort_packages;
+
+/// Profile samples unless the request asks for a number.
+pub const DEFAULT_PROFILE_SAMPLES: usize = 200;
+
+pub const MAX_PROFILE_SAMPLES: usize = 5000;
+
+/// Elevations of a set of points, with where each one came from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Samples {
+    /// `(elevation, source)` per point, `None` where nothing had data.
+    pub values: Vec<Option<(f64, String)>>,
+    /// Packages covering some point that are not in the local cache.
+    pub uncached_packages: Vec<String>,
+}
+
+/// Sample a single raster, such as a finished job output, at Web Mercator points.
+pub async fn sample_file(
+    path: &Path,
+    points: &[[f64; 2]],
+) -> Result<Vec<Option<f64>>, ProcessingError> {
+    let path = path.to_string_lossy().to_string();
+    let points = points.to_vec();
+    tokio::task::spawn_blocking(move || sample_raster(&path, &points))
+        .await
+        .map_err(|e| ProcessingError::GdalError(e.to_string()))?
+}
+
+/// Sample Web Mercator points from the cached rasters of `packages`.
+///
+/// Packages are tried newest first; a package only answers the points inside
+/// its footprint where it has data.
+pub async fn sample_packages(
+    points: &[[f64; 2]],
+    packages: &[Package],
+    cache_root: &Path,
+) -> Result<Samples, ProcessingError> {
+    let mut packages = packages.to_vec();
+    sort_packages(&mut packages, SelectionStrategy::Newest);
+
+    let mut samples = Samples {
+        values: vec![None; points.len()],
+        uncached_packages: Vec::new(),
+    };
+    for package in &packages {
+        let footprint = package.geometry.to_multi_polygon();
+        let pending: Vec<usize> = (0..points.len())
+            .filter(|&i| samples.values[i].is_none())
+            .filter(|&i| footprint.contains(&Point::new(points[i][0], points[i][1])))
+            .collect();
+        if pending.is_empty() {
+            continue;
+        }
+
+        let Some(raster) = package_raster(package, cache_root).await? else {
+            samples.uncached_packages.push(package.package_name.clone());
+            continue;
+        };
+        let pending_points: Vec<[f64; 2]> = pending.iter().map(|&i| points[i]).collect();
+        let values = sample_file(Path::new(&raster), &pending_points).await?;
+        for (i, value) in pending.into_iter().zip(values) {
+            if let Some(value) = value {
+                samples.values[i] = Some((value, package.package_name.clone()));
+            }
+        }
+    }
+    Ok(samples)
+}
+
+/// The locally readable raster of a package, if it has been cached.
+///
+/// Multi-file packages are read through a VRT built once next to the extract.
+async fn package_raster(
+    package: &Package,
+    cache_root: &Path,
+) -> Result<Option<String>, ProcessingError> {
+    let cache_key = package_cache_key(package);
+    let extract_dir = cache_root.join("extracts").join(&cache_key);
+
+    if is_geotiff_url(&package.download_url) {
+        let local = extract_dir.join(format!("{}.tif", cache_key));
+        if local.exists() {
+            return Ok(Some(local.to_string_lossy().to_string()));
+        }
+        if stream_remote_cogs() {
+            let input =
+                remote_cog_input(&package.download_url, &cache_root.join("cog-blocks")).await?;
+            return Ok(Some(input));
+        }
+        return Ok(None);
+    }
+
+    let zip_path = cache_root.join("zips").join(format!("{}.zip", cache_key));
+    let Some(extracted) =
+        check_extraction_complete(&zip_path.to_string_lossy(), &extract_dir.to_string_lossy())
+    else {
+        return Ok(None);
+    };
+    if let [single] = extracted.tiff_files.as_slice() {
+        return Ok(Some(single.clone()));
+    }
+
+    let vrt = cache_root
+        .join("extracts")
+        .join(format!("{}.vrt", cache_key));
+    if !vrt.exists() {
+        let files = extracted.tiff_files;
+        let target = vrt.clone();
+        tokio::task::spawn_blocking(move || build_vrt(&target, &files))
+            .await
+            .map_err(|e| ProcessingError::GdalError(e.to_string()))??;
+    }
+    Ok(Some(vrt.to_string_lossy().to_string()))
+}
+
+fn build_vrt(vrt: &Path, files: &[String]) -> Result<(), ProcessingError> {
+    // Build under a temporary name so a failed run leaves no partial VRT.
+    let partial = vrt.with_extension("vrt.part");
+    let output = Command::new("gdalbuildvrt")
+        .arg("-q")
+        .arg("-of")
+        .arg("VRT")
+        .arg(&partial)
+        .args(files)
+        .output()?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(&partial);
+        return Err(ProcessingError::GdalError(format!(
+            "gdalbuildvrt failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    std::fs::rename(&partial, vrt)?;
+    Ok(())
+}
+
+/// Read band 1 at each Web Mercator point with one `gdallocationinfo` run.
+fn sample_raster(path: &str, points: &[[f64; 2]]) -> Result<Vec<Option<f64>>, ProcessingError> {
+    if points.is_empty() {
+        return Ok(Vec::new());
+    }
+    let nodata = inspect_raster(path)?.nodata;
+
+    let mut child = Command::new("gdallocationinfo")
+        .args(["-valonly", "-b", "1", "-l_srs", "EPSG:3857"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread so a full stdout pipe cannot stall the input.
+    let input: String = points
+        .iter()
+        .map(|[x, y]| format!("{} {}\n", x, y))
+        .collect();
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(ProcessingError::GdalError(format!(
+            "gdallocationinfo failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(parse_values(
+        &String::from_utf8_lossy(&output.stdout),
+        points.len(),
+        nodata,
+    ))
+}
+
+/// One value per line; points off the raster produce an empty line.
+fn parse_values(stdout: &str, count: usize, nodata: Option<f64>) -> Vec<Option<f64>> {
+    let mut values: Vec<Option<f64>> = stdout
+        .lines()
+        .take(count)
+        .map(|line| {
+            let value: f64 = line.trim().parse().ok()?;
+            let is_nodata = nodata.is_some_and(|nodata| nodata_equals(value, nodata));
+            (!value.is_nan() && !is_nodata).then_some(value)
+        })
+        .collect();
+    values.resize(count, None);
+    values
+}
+
+/// Evenly spaced points along a Web Mercator line, as `(ground distance, point)`.
+///
+/// Distances are geodesic, so they are true metres on the ground rather
+/// than Mercator units.
+pub fn profile_points(line: &[[f64; 2]], samples: usize) -> Vec<(f64, [f64; 2])> {
+    let Some(&first) = line.first() else {
+        return Vec::new();
+    };
+    let geodesic = |a: [f64; 2], b: [f64; 2]| {
+        let [lon_a, lat_a] = mercator_to_lon_lat(a[0], a[1]);
+        let [lon_b, lat_b] = mercator_to_lon_lat(b[0], b[1]);
+        Geodesic.distance(Point::new(lon_a, lat_a), Point::new(lon_b, lat_b))
+    };
+    let mut cumulative = vec![0.0];
+    for pair in line.windows(2) {
+        cumulative.push(cumulative.last().unwrap() + geodesic(pair[0], pair[1]));
+    }
+    let length = *cumulative.last().unwrap();
+    if samples < 2 || length == 0.0 {
+        return vec![(0.0, first)];
+    }
+
+    let mut segment = 0;
+    (0..samples)
+        .map(|i| {
+            let distance = length * i as f64 / (samples - 1) as f64;
+            while segment + 2 < line.len() && cumulative[segment + 1] < distance {
+                segment += 1;
+            }
+            let (start, end) = (cumulative[segment], cumulative[segment + 1]);
+            let t = if end > start {
+                ((distance - start) / (end - start)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let [ax, ay] = line[segment];
+            let [bx, by] = line[segment + 1];
+            (distance, [ax + (bx - ax) * t, ay + (by - ay) * t])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_values_skips_nodata_and_off_raster_points() {
+        let stdout = "101.5\n-9999\n\nnan\n";
+        assert_eq!(
+            parse_values(stdout, 5, Some(-9999.0)),
+            vec![Some(101.5), None, None, None, None]
+        );
+        assert_eq!(parse_values("7\n", 1, None), vec![Some(7.0)]);
+    }
+
+    #[test]
+    fn test_profile_points_are_evenly_spaced() {
+        // An L-shaped line near Ottawa, 1 km then 1 km in Mercator units.
+        let line = [
+            [-8_450_000.0, 5_690_000.0],
+            [-8_449_000.0, 5_690_000.0],
+            [-8_449_000.0, 5_691_000.0],
+        ];
+        let points = profile_points(&line, 5);
+        assert_eq!(points.len(), 5);
+        assert_eq!(points[0], (0.0, line[0]));
+        assert_eq!(points[4].1, line[2]);
+        // Both legs are about as long on the ground, so the middle sample is near the corner.
+        assert!((points[2].1[0] - line[1][0]).abs() < 5.0);
+        assert!((points[2].1[1] - line[1][1]).abs() < 5.0);
+
+        // Mercator scale at ~45.5°N is about 1.43, so 2 km of Mercator is ~1.4 km on the ground.
+        let length = points[4].0;
+        assert!((1350.0..1450.0).contains(&length), "length {}", length);
+        assert!((points[1].0 - length / 4.0).abs() < 1e-6);
+
+        assert_eq!(profile_points(&line[..1], 10), vec![(0.0, line[0])]);
+    }
+}
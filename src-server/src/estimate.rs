@@ -0,0 +1,371 @@
+//! Pre-flight estimates of the download, disk and output size of a job.
+//!
+//! Sizes are estimates: package sizes come from the index, extracted sizes
+//! from the ZIP directory when the ZIP is cached and from a typical ratio
+//! otherwise, and output sizes from the pixel count and a typical compression
+//! ratio for Float32 elevation data.
+
+use std::fs::File;
+use std::path::Path;
+
+use geo::{BoundingRect, MultiPolygon, Rect};
+use zip::ZipArchive;
+
+use crate::api_types::{DownloadEstimate, DownloadRequest, Package};
+use crate::disk_space::{available_space, ensure_job_space};
+use crate::download::{check_extraction_complete, is_geotiff_url};
+use crate::processing::{stream_remote_cogs, CompressionType};
+use crate::projection::mercator_to_lon_lat;
+
+/// Extracted size relative to the ZIP size when the ZIP is not cached yet.
+const EXTRACTION_RATIO: f64 = 1.3;
+
+/// Bytes per output pixel (Float32 elevations).
+const BYTES_PER_PIXEL: u64 = 4;
+
+/// Download bandwidth assumed for time estimates unless `DTM_ESTIMATE_BANDWIDTH_MBPS` is set.
+const DEFAULT_BANDWIDTH_MBPS: f64 = 50.0;
+
+/// Typical compressed size of Float32 elevation data, relative to raw.
+fn compression_ratio(compression: CompressionType) -> f64 {
+    match compression {
+        CompressionType::Zstd => 0.5,
+        CompressionType::Lzma => 0.45,
+        CompressionType::Deflate => 0.55,
+        CompressionType::Lzw => 0.65,
+    }
+}
+
+/// Estimate the download and disk needs of `req`, with packages cached under
+/// `cache_root` and outputs written under `work_dir`.
+///
+/// `req.clip_extent` must already be in Web Mercator.
+pub fn estimate_download(
+    req: &DownloadRequest,
+    cache_root: &Path,
+    work_dir: &Path,
+) -> DownloadEstimate {
+    let stream_cogs = stream_remote_cogs();
+    let clip = req
+        .clip_extent
+        .as_ref()
+        .map(|c| Rect::new((c.min_x, c.min_y), (c.max_x, c.max_y)));
+
+    let mut download_bytes = 0;
+    let mut extraction_bytes = 0;
+    let mut cached_packages = 0;
+    for pkg in &req.packages {
+        let needs = package_needs(pkg, cache_root, stream_cogs, clip.as_ref());
+        download_bytes += needs.download_bytes;
+        extraction_bytes += needs.extraction_bytes;
+        if needs.cached {
+            cached_packages += 1;
+        }
+    }
+
+    let resolution = req
+        .packages
+        .iter()
+        .map(|p| p.resolution)
+        .filter(|r| *r > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    let resolution = if resolution.is_finite() {
+        resolution
+    } else {
+        1.0
+    };
+
+    let output_area = clip.or_else(|| {
+        MultiPolygon::new(
+            req.packages
+                .iter()
+                .flat_map(|p| p.geometry.to_multi_polygon().0)
+                .collect(),
+        )
+        .bounding_rect()
+    });
+    let (output_width, output_height) = output_area
+        .map(|rect| ground_pixels(&rect, resolution))
+        .unwrap_or((0, 0));
+    let output_pixels = output_width * output_height;
+
+    let compression = CompressionType::from_str(&req.compression);
+    let mut bytes_per_pixel = BYTES_PER_PIXEL as f64;
+    if req.void_fill.is_some() {
+        // One byte per pixel for the filled-pixel mask band.
+        bytes_per_pixel += 1.0;
+    }
+    let output_bytes =
+        (output_pixels as f64 * bytes_per_pixel * compression_ratio(compression)) as u64;
+
+    // The warped intermediate and the final COG, plus one more intermediate
+    // for each optional processing stage.
+    let stages = 2
+        + u64::from(req.void_fill.is_some()) * 3
+        + u64::from(req.target_vertical_datum.is_some()) * 2;
+    let work_bytes = output_bytes * stages;
+
+    let cache_free_bytes = available_space(cache_root);
+    let work_free_bytes = available_space(work_dir);
+    let enough_disk_space = cache_free_bytes.is_some()
+        && work_free_bytes.is_some()
+        && ensure_job_space(
+            cache_root,
+            download_bytes + extraction_bytes,
+            work_dir,
+            work_bytes,
+        )
+        .is_ok();
+
+    DownloadEstimate {
+        package_count: req.packages.len(),
+        cached_packages,
+        download_bytes,
+        extraction_bytes,
+        output_width,
+        output_height,
+        output_pixels,
+        output_bytes,
+        work_bytes,
+        compression: compression.to_gdal_string().to_string(),
+        resolution,
+        cache_dir: cache_root.to_string_lossy().to_string(),
+        cache_free_bytes,
+        work_dir: work_dir.to_string_lossy().to_string(),
+        work_free_bytes,
+        enough_disk_space,
+        estimated_download_seconds: download_bytes as f64 * 8.0 / (bandwidth_mbps() * 1e6),
+    }
+}
+
+struct PackageNeeds {
+    download_bytes: u64,
+    extraction_bytes: u64,
+    /// Nothing is left to download.
+    cached: bool,
+}
+
+fn package_needs(
+    pkg: &Package,
+    cache_root: &Path,
+    stream_cogs: bool,
+    clip: Option<&Rect<f64>>,
+) -> PackageNeeds {
+    let expected = (pkg.size_gb.max(0.0) * 1e9) as u64;
+    let cache_key = crate::routes::package_cache_key(pkg);
+    let extract_dir = cache_root.join("extracts").join(&cache_key);
+
+    if is_geotiff_url(&pkg.download_url) {
+        let download_bytes = if stream_cogs {
+            // Only the tiles inside the clip extent are read.
+            (expected as f64 * clip_fraction(pkg, clip)) as u64
+        } else {
+            let tiff_path = extract_dir.join(format!("{}.tif", cache_key));
+            remaining_bytes(&tiff_path, expected)
+        };
+        return PackageNeeds {
+            download_bytes,
+            extraction_bytes: 0,
+            cached: download_bytes == 0,
+        };
+    }
+
+    let zip_path = cache_root.join("zips").join(format!("{}.zip", cache_key));
+    let zip_str = zip_path.to_string_lossy();
+    if check_extraction_complete(&zip_str, &extract_dir.to_string_lossy()).is_some() {
+        return PackageNeeds {
+            download_bytes: 0,
+            extraction_bytes: 0,
+            cached: true,
+        };
+    }
+
+    // The index size is rounded, so the ZIP's length cannot tell a complete
+    // download from a partial one. Its central directory is written last,
+    // though: a ZIP that opens as an archive is complete.
+    if let Some(uncompressed) = zip_uncompressed_size(&zip_path) {
+        return PackageNeeds {
+            download_bytes: 0,
+            extraction_bytes: uncompressed,
+            cached: true,
+        };
+    }
+
+    PackageNeeds {
+        download_bytes: remaining_bytes(&zip_path, expected),
+        extraction_bytes: (expected as f64 * EXTRACTION_RATIO) as u64,
+        cached: false,
+    }
+}
+
+/// Bytes of `expected` not yet present in `path`.
+///
+/// Zero for a partial file that already reaches the rounded `expected`.
+fn remaining_bytes(path: &Path, expected: u64) -> u64 {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.len() >= expected => 0,
+        Ok(meta) => expected - meta.len(),
+        Err(_) => expected,
+    }
+}
+
+/// Total uncompressed size of the entries of a cached ZIP.
+fn zip_uncompressed_size(path: &Path) -> Option<u64> {
+    let mut archive = ZipArchive::new(File::open(path).ok()?).ok()?;
+    let mut total = 0;
+    for i in 0..archive.len() {
+        total += archive.by_index_raw(i).ok()?.size();
+    }
+    Some(total)
+}
+
+/// Share of a package's footprint inside the clip extent, by bounding box.
+fn clip_fraction(pkg: &Package, clip: Option<&Rect<f64>>) -> f64 {
+    let (Some(clip), Some(footprint)) = (clip, pkg.geometry.to_multi_polygon().bounding_rect())
+    else {
+        return 1.0;
+    };
+    let width = footprint.max().x.min(clip.max().x) - footprint.min().x.max(clip.min().x);
+    let height = footprint.max().y.min(clip.max().y) - footprint.min().y.max(clip.min().y);
+    let area = footprint.width() * footprint.height();
+    if area <= 0.0 {
+        return 1.0;
+    }
+    (width.max(0.0) * height.max(0.0) / area).min(1.0)
+}
+
+/// Output size in pixels of a Web Mercator rectangle at a ground resolution
+/// in metres. Mercator distances are scaled by the cosine of the latitude.
+fn ground_pixels(rect: &Rect<f64>, resolution: f64) -> (u64, u64) {
+    let [_, lat] = mercator_to_lon_lat(rect.center().x, rect.center().y);
+    let scale = lat.to_radians().cos();
+    let width = (rect.width() * scale / resolution).ceil().max(0.0) as u64;
+    let height = (rect.height() * scale / resolution).ceil().max(0.0) as u64;
+    (width, height)
+}
+
+fn bandwidth_mbps() -> f64 {
+    std::env::var("DTM_ESTIMATE_BANDWIDTH_MBPS")
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|v| *v > 0.0)
+        .unwrap_or(DEFAULT_BANDWIDTH_MBPS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_types::{ClipExtentRequest, GeoJSONGeometry};
+    use crate::test_support::PackageBuilder;
+    use std::io::Write;
+
+    fn package(name: &str, url: &str, size_gb: f64) -> Package {
+        // 10 km square near Toronto, in Web Mercator.
+        let (x, y) = (-8_850_000.0, 5_400_000.0);
+        PackageBuilder::new(name)
+            .size_gb(size_gb)
+            .download_url(url)
+            .coverage_km2(100.0)
+            .geometry(GeoJSONGeometry::Polygon(vec![vec![
+                vec![x, y],
+                vec![x, y + 10_000.0],
+                vec![x + 10_000.0, y + 10_000.0],
+                vec![x + 10_000.0, y],
+                vec![x, y],
+            ]]))
+            .build()
+    }
+
+    fn request(packages: Vec<Package>, clip: Option<ClipExtentRequest>) -> DownloadRequest {
+        DownloadRequest {
+            packages,
+            clip_extent: clip,
+            compression: "deflate".to_string(),
+            output_nodata: None,
+            void_fill: None,
+            target_vertical_datum: None,
+            source_vertical_datum: None,
+            statistics: None,
+        }
+    }
+
+    #[test]
+    fn test_cached_zip_is_not_downloaded_again() {
+        let cache_root =
+            std::env::temp_dir().join(format!("dtm-estimate-{}", uuid::Uuid::new_v4()));
+        // The index rounds sizes, so the cached ZIP is smaller than its listed size.
+        let cached = package("Cached", "https://example.com/cached.zip", 0.25);
+        let partial = package("Partial", "https://example.com/partial.zip", 0.5);
+        let missing = package("Missing", "https://example.com/missing.zip", 2.0);
+
+        // A cached ZIP holding one 1000-byte tile that has not been extracted.
+        let zip_path = cache_root
+            .join("zips")
+            .join(format!("{}.zip", crate::routes::package_cache_key(&cached)));
+        std::fs::create_dir_all(zip_path.parent().unwrap()).unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file("tile.tif", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&[0u8; 1000]).unwrap();
+        zip.finish().unwrap();
+
+        // An interrupted download: the first 100 bytes of a ZIP.
+        let partial_path = cache_root.join("zips").join(format!(
+            "{}.zip",
+            crate::routes::package_cache_key(&partial)
+        ));
+        std::fs::write(&partial_path, &std::fs::read(&zip_path).unwrap()[..100]).unwrap();
+
+        let estimate = estimate_download(
+            &request(vec![cached, partial, missing], None),
+            &cache_root,
+            &std::env::temp_dir(),
+        );
+        assert_eq!(estimate.package_count, 3);
+        assert_eq!(estimate.download_bytes, 499_999_900 + 2_000_000_000);
+        assert_eq!(
+            estimate.extraction_bytes,
+            1000 + 650_000_000 + 2_600_000_000
+        );
+        assert_eq!(estimate.cached_packages, 1);
+        assert!(estimate.cache_free_bytes.is_some());
+        assert!(estimate.estimated_download_seconds > 0.0);
+
+        let _ = std::fs::remove_dir_all(cache_root);
+    }
+
+    #[test]
+    fn test_output_size_follows_clip_and_resolution() {
+        let pkg = package("Tile", "https://example.com/tile.zip", 1.0);
+        let clip = ClipExtentRequest {
+            min_x: -8_850_000.0,
+            min_y: 5_400_000.0,
+            max_x: -8_849_000.0,
+            max_y: 5_401_000.0,
+            srid: 3857,
+        };
+        let estimate = estimate_download(
+            &request(vec![pkg], Some(clip)),
+            &std::env::temp_dir(),
+            &std::env::temp_dir(),
+        );
+
+        // 1 km of Web Mercator at 43.5 degrees north is about 725 m on the ground.
+        assert_eq!(estimate.resolution, 0.5);
+        assert!((1440..1460).contains(&estimate.output_width));
+        assert_eq!(estimate.output_width, estimate.output_height);
+        assert_eq!(
+            estimate.output_bytes,
+            (estimate.output_pixels as f64 * 4.0 * 0.55) as u64
+        );
+        assert_eq!(estimate.compression, "DEFLATE");
+    }
+
+    #[test]
+    fn test_streamed_cog_counts_only_the_clipped_share() {
+        let pkg = package("COG", "https://example.com/dtm.tif", 1.0);
+        let clip = Rect::new((-8_850_000.0, 5_400_000.0), (-8_845_000.0, 5_405_000.0));
+        assert!((clip_fraction(&pkg, Some(&clip)) - 0.25).abs() < 1e-12);
+        assert_eq!(clip_fraction(&pkg, None), 1.0);
+    }
+}
@@ -0,0 +1,468 @@
+//! Export of package footprints to GIS formats.
+//!
+//! Footprints are stored in Web Mercator; every export writes them in WGS84
+//! longitude/latitude, as RFC 7946 requires for GeoJSON.
+
+use std::io::{self, Cursor, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use geo::orient::{Direction, Orient};
+use geo::{BoundingRect, MultiPolygon};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::api_types::{GeoJSONGeometry, Package};
+use crate::projection::geometry_to_lon_lat;
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    #[error("ZIP creation failed: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+}
+
+/// An RFC 7946 FeatureCollection with one feature per package.
+pub fn feature_collection(packages: &[Package]) -> Value {
+    json!({
+        "type": "FeatureCollection",
+        "features": packages.iter().map(package_to_feature).collect::<Vec<_>>(),
+    })
+}
+
+/// A GeoJSON Feature whose properties are the package attributes.
+///
+/// Exterior rings are counterclockwise and holes clockwise (the right-hand rule).
+pub fn package_to_feature(package: &Package) -> Value {
+    let mut properties = match serde_json::to_value(package) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    properties.remove("geometry");
+
+    let geometry = match geometry_to_lon_lat(&package.geometry) {
+        geometry @ (GeoJSONGeometry::Polygon(_) | GeoJSONGeometry::MultiPolygon(_)) => {
+            let oriented = geometry.to_multi_polygon().orient(Direction::Default);
+            match (geometry, GeoJSONGeometry::from_multi_polygon(&oriented)) {
+                (GeoJSONGeometry::Polygon(_), GeoJSONGeometry::MultiPolygon(mut polygons))
+                    if polygons.len() == 1 =>
+                {
+                    GeoJSONGeometry::Polygon(polygons.remove(0))
+                }
+                (_, oriented) => oriented,
+            }
+        }
+        other => other,
+    };
+
+    json!({
+        "type": "Feature",
+        "id": package.package_name,
+        "geometry": geometry,
+        "properties": properties,
+    })
+}
+
+/// A zipped ESRI Shapefile (`.shp`, `.shx`, `.dbf`, `.prj`, `.cpg`) of the
+/// package footprints, with files named `{name}.*`.
+pub fn shapefile_zip(packages: &[Package], name: &str) -> Result<Vec<u8>, ExportError> {
+    let footprints: Vec<MultiPolygon<f64>> = packages
+        .iter()
+        .map(|pkg| {
+            // Shapefile rings run the other way: exteriors clockwise.
+            geometry_to_lon_lat(&pkg.geometry)
+                .to_multi_polygon()
+                .orient(Direction::Reversed)
+        })
+        .collect();
+    let (shp, shx) = write_shp(&footprints);
+    let dbf = write_dbf(packages);
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    for (extension, data) in [
+        ("shp", shp.as_slice()),
+        ("shx", shx.as_slice()),
+        ("dbf", dbf.as_slice()),
+        ("prj", WGS84_PRJ.as_bytes()),
+        ("cpg", b"UTF-8".as_slice()),
+    ] {
+        zip.start_file(format!("{}.{}", name, extension), options)?;
+        zip.write_all(data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+const WGS84_PRJ: &str = "GEOGCS[\"GCS_WGS_1984\",DATUM[\"D_WGS_1984\",SPHEROID[\"WGS_1984\",6378137.0,298.257223563]],PRIMEM[\"Greenwich\",0.0],UNIT[\"Degree\",0.0174532925199433]]";
+
+const SHAPE_NULL: i32 = 0;
+const SHAPE_POLYGON: i32 = 5;
+const SHP_HEADER_BYTES: usize = 100;
+
+/// Write the `.shp` and `.shx` files for polygon records.
+fn write_shp(footprints: &[MultiPolygon<f64>]) -> (Vec<u8>, Vec<u8>) {
+    let mut records = Vec::new();
+    let mut index = Vec::new();
+    let mut bounds: Option<[f64; 4]> = None;
+
+    for (i, footprint) in footprints.iter().enumerate() {
+        let content = match footprint.bounding_rect() {
+            Some(rect) => {
+                let [xmin, ymin, xmax, ymax] =
+                    [rect.min().x, rect.min().y, rect.max().x, rect.max().y];
+                bounds = Some(match bounds {
+                    Some(b) => [
+                        b[0].min(xmin),
+                        b[1].min(ymin),
+                        b[2].max(xmax),
+                        b[3].max(ymax),
+                    ],
+                    None => [xmin, ymin, xmax, ymax],
+                });
+                polygon_record(footprint, [xmin, ymin, xmax, ymax])
+            }
+            None => SHAPE_NULL.to_le_bytes().to_vec(),
+        };
+
+        let offset_words = (SHP_HEADER_BYTES + records.len()) / 2;
+        index.extend((offset_words as i32).to_be_bytes());
+        index.extend(((content.len() / 2) as i32).to_be_bytes());
+
+        records.extend((i as i32 + 1).to_be_bytes());
+        records.extend(((content.len() / 2) as i32).to_be_bytes());
+        records.extend(content);
+    }
+
+    let bounds = bounds.unwrap_or_default();
+    let mut shp = shp_header(SHP_HEADER_BYTES + records.len(), bounds);
+    shp.extend(records);
+    let mut shx = shp_header(SHP_HEADER_BYTES + index.len(), bounds);
+    shx.extend(index);
+    (shp, shx)
+}
+
+fn polygon_record(footprint: &MultiPolygon<f64>, bbox: [f64; 4]) -> Vec<u8> {
+    let rings: Vec<_> = footprint
+        .iter()
+        .flat_map(|polygon| std::iter::once(polygon.exterior()).chain(polygon.interiors()))
+        .collect();
+    let point_count: usize = rings.iter().map(|ring| ring.0.len()).sum();
+
+    let mut content = Vec::with_capacity(44 + 4 * rings.len() + 16 * point_count);
+    content.extend(SHAPE_POLYGON.to_le_bytes());
+    for value in bbox {
+        content.extend(value.to_le_bytes());
+    }
+    content.extend((rings.len() as i32).to_le_bytes());
+    content.extend((point_count as i32).to_le_bytes());
+    let mut start = 0;
+    for ring in &rings {
+        content.extend((start as i32).to_le_bytes());
+        start += ring.0.len();
+    }
+    for coord in rings.iter().flat_map(|ring| ring.coords()) {
+        content.extend(coord.x.to_le_bytes());
+        content.extend(coord.y.to_le_bytes());
+    }
+    content
+}
+
+fn shp_header(file_bytes: usize, bbox: [f64; 4]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(SHP_HEADER_BYTES);
+    header.extend(9994i32.to_be_bytes());
+    header.extend([0u8; 20]);
+    header.extend(((file_bytes / 2) as i32).to_be_bytes());
+    header.extend(1000i32.to_le_bytes());
+    header.extend(SHAPE_POLYGON.to_le_bytes());
+    for value in bbox {
+        header.extend(value.to_le_bytes());
+    }
+    // Z and M ranges are unused for 2D polygons.
+    header.extend([0u8; 32]);
+    header
+}
+
+/// One `.dbf` column: name (at most 10 characters), type, width and decimals.
+struct DbfField {
+    name: &'static str,
+    kind: u8,
+    width: usize,
+    decimals: u8,
+}
+
+const DBF_FIELDS: [DbfField; 10] = [
+    DbfField {
+        name: "PACKAGE",
+        kind: b'C',
+        width: 254,
+        decimals: 0,
+    },
+    DbfField {
+        name: "SIZE_GB",
+        kind: b'N',
+        width: 19,
+        decimals: 3,
+    },
+    DbfField {
+        name: "RESOLUTION",
+        kind: b'N',
+        width: 19,
+        decimals: 3,
+    },
+    DbfField {
+        name: "URL",
+        kind: b'C',
+        width: 254,
+        decimals: 0,
+    },
+    DbfField {
+        name: "PROJECT",
+        kind: b'C',
+        width: 254,
+        decimals: 0,
+    },
+    DbfField {
+        name: "YEARS",
+        kind: b'C',
+        width: 20,
+        decimals: 0,
+    },
+    DbfField {
+        name: "START_YEAR",
+        kind: b'N',
+        width: 4,
+        decimals: 0,
+    },
+    DbfField {
+        name: "END_YEAR",
+        kind: b'N',
+        width: 4,
+        decimals: 0,
+    },
+    DbfField {
+        name: "SOURCE",
+        kind: b'C',
+        width: 64,
+        decimals: 0,
+    },
+    DbfField {
+        name: "AREA_KM2",
+        kind: b'N',
+        width: 19,
+        decimals: 3,
+    },
+];
+
+fn dbf_values(pkg: &Package) -> [String; 10] {
+    let period = pkg.acquisition_period;
+    [
+        pkg.package_name.clone(),
+        format!("{:.3}", pkg.size_gb),
+        format!("{:.3}", pkg.resolution),
+        pkg.download_url.clone(),
+        pkg.project.clone(),
+        pkg.year_range.clone().unwrap_or_default(),
+        period.map(|p| p.start_year.to_string()).unwrap_or_default(),
+        period.map(|p| p.end_year.to_string()).unwrap_or_default(),
+        pkg.source.clone(),
+        format!("{:.3}", pkg.coverage_km2),
+    ]
+}
+
+/// Write a dBASE III attribute table with one record per package.
+fn write_dbf(packages: &[Package]) -> Vec<u8> {
+    let header_len = 32 + 32 * DBF_FIELDS.len() + 1;
+    let record_len = 1 + DBF_FIELDS.iter().map(|f| f.width).sum::<usize>();
+    let [year, month, day] = utc_date();
+
+    let mut dbf = Vec::with_capacity(header_len + record_len * packages.len() + 1);
+    dbf.extend([0x03, (year - 1900) as u8, month as u8, day as u8]);
+    dbf.extend((packages.len() as u32).to_le_bytes());
+    dbf.extend((header_len as u16).to_le_bytes());
+    dbf.extend((record_len as u16).to_le_bytes());
+    dbf.extend([0u8; 20]);
+
+    for field in &DBF_FIELDS {
+        let mut name = [0u8; 11];
+        name[..field.name.len()].copy_from_slice(field.name.as_bytes());
+        dbf.extend(name);
+        dbf.push(field.kind);
+        dbf.extend([0u8; 4]);
+        dbf.push(field.width as u8);
+        dbf.push(field.decimals);
+        dbf.extend([0u8; 14]);
+    }
+    dbf.push(0x0D);
+
+    for pkg in packages {
+        dbf.push(b' ');
+        for (field, value) in DBF_FIELDS.iter().zip(dbf_values(pkg)) {
+            let value = truncate_utf8(&value, field.width);
+            let padding = vec![b' '; field.width - value.len()];
+            if field.kind == b'N' {
+                dbf.extend(&padding);
+                dbf.extend(value.as_bytes());
+            } else {
+                dbf.extend(value.as_bytes());
+                dbf.extend(&padding);
+            }
+        }
+    }
+    dbf.push(0x1A);
+    dbf
+}
+
+/// The longest prefix of `value` that fits in `max_bytes` without splitting a character.
+fn truncate_utf8(value: &str, max_bytes: usize) -> &str {
+    if value.len() <= max_bytes {
+        return value;
+    }
+    let mut end = max_bytes;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Today's UTC date as `[year, month, day]`, for the `.dbf` header.
+fn utc_date() -> [i64; 3] {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64 / 86_400)
+        .unwrap_or(0);
+    civil_from_days(days)
+}
+
+/// Convert days since 1970-01-01 to a proleptic Gregorian date.
+pub(crate) fn civil_from_days(days: i64) -> [i64; 3] {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    [year, month, day]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::PackageBuilder;
+    use std::io::Read;
+
+    /// A 1 km square near Toronto in Web Mercator, with clockwise (ESRI) winding.
+    fn package(name: &str) -> Package {
+        let (x, y) = (-8_836_000.0, 5_411_000.0);
+        PackageBuilder::new(name)
+            .size_gb(1.25)
+            .download_url("https://example.com/tile.zip")
+            .project("GTA 2023")
+            .years("2023")
+            .source("ontario-dtm")
+            .geometry(GeoJSONGeometry::Polygon(vec![vec![
+                vec![x, y],
+                vec![x, y + 1000.0],
+                vec![x + 1000.0, y + 1000.0],
+                vec![x + 1000.0, y],
+                vec![x, y],
+            ]]))
+            .build()
+    }
+
+    #[test]
+    fn test_feature_follows_rfc_7946() {
+        let collection = feature_collection(&[package("GTA 1")]);
+        let feature = &collection["features"][0];
+        assert_eq!(feature["type"], "Feature");
+        assert_eq!(feature["properties"]["package_name"], "GTA 1");
+        assert_eq!(feature["properties"]["source"], "ontario-dtm");
+        assert!(feature["properties"].get("geometry").is_none());
+        assert_eq!(feature["geometry"]["type"], "Polygon");
+
+        let ring: Vec<[f64; 2]> =
+            serde_json::from_value(feature["geometry"]["coordinates"][0].clone()).unwrap();
+        assert!(ring
+            .iter()
+            .all(|[lon, lat]| (-80.0..-79.0).contains(lon) && (43.0..44.0).contains(lat)));
+        // Shoelace sum is positive for a counterclockwise exterior.
+        let signed_area: f64 = ring
+            .windows(2)
+            .map(|w| w[0][0] * w[1][1] - w[1][0] * w[0][1])
+            .sum();
+        assert!(signed_area > 0.0);
+    }
+
+    #[test]
+    fn test_shapefile_zip_contents() {
+        let packages = [package("GTA 1"), package("GTA 2")];
+        let bytes = shapefile_zip(&packages, "packages").unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+
+        let mut names: Vec<_> = archive.file_names().map(str::to_string).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "packages.cpg",
+                "packages.dbf",
+                "packages.prj",
+                "packages.shp",
+                "packages.shx"
+            ]
+        );
+
+        let read = |archive: &mut zip::ZipArchive<Cursor<Vec<u8>>>, name: &str| {
+            let mut data = Vec::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            data
+        };
+
+        let shp = read(&mut archive, "packages.shp");
+        assert_eq!(i32::from_be_bytes(shp[0..4].try_into().unwrap()), 9994);
+        assert_eq!(
+            i32::from_be_bytes(shp[24..28].try_into().unwrap()) as usize * 2,
+            shp.len()
+        );
+        // Each record: 8-byte header plus 44 + 4 (one part) + 5 * 16 bytes.
+        assert_eq!(shp.len(), 100 + 2 * (8 + 44 + 4 + 80));
+
+        let shx = read(&mut archive, "packages.shx");
+        assert_eq!(shx.len(), 100 + 2 * 8);
+        assert_eq!(
+            i32::from_be_bytes(shx[108..112].try_into().unwrap()),
+            (100 + 136) / 2
+        );
+
+        let dbf = read(&mut archive, "packages.dbf");
+        assert_eq!(u32::from_le_bytes(dbf[4..8].try_into().unwrap()), 2);
+        let header_len = u16::from_le_bytes(dbf[8..10].try_into().unwrap()) as usize;
+        let record_len = u16::from_le_bytes(dbf[10..12].try_into().unwrap()) as usize;
+        assert_eq!(dbf.len(), header_len + 2 * record_len + 1);
+        let record = std::str::from_utf8(&dbf[header_len..header_len + record_len]).unwrap();
+        assert!(record.starts_with(" GTA 1 "));
+        assert!(record.contains("  1.250"));
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), [1970, 1, 1]);
+        assert_eq!(civil_from_days(20_744), [2026, 10, 18]);
+        assert_eq!(civil_from_days(11_016), [2000, 2, 29]);
+    }
+
+    #[test]
+    fn test_truncate_utf8_keeps_characters_whole() {
+        assert_eq!(truncate_utf8("Québec", 3), "Qu");
+        assert_eq!(truncate_utf8("Ottawa", 10), "Ottawa");
+    }
+}
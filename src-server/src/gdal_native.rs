@@ -0,0 +1,218 @@
+//! In-process GDAL processing backend built on the GDAL C API.
+//!
+//! Runs the same warp/translate argument lists as the CLI backend through
+//! `GDALWarp` and `GDALTranslate`, which gives per-block progress callbacks,
+//! cancellation and errors without spawning `gdalwarp`/`gdal_translate`.
+//! Enabled with the `native-gdal` cargo feature.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use gdal::Dataset;
+
+use crate::processing::{ProcessingError, RasterInfo};
+
+/// State shared with the GDAL progress trampoline.
+struct ProgressState<'a> {
+    cancel: &'a AtomicBool,
+    on_progress: &'a mut dyn FnMut(f64),
+}
+
+/// GDAL progress callback; returning 0 asks GDAL to abort the operation.
+///
+/// A panic must not unwind into GDAL's C frames, so it aborts the operation instead.
+unsafe extern "C" fn progress_trampoline(
+    complete: f64,
+    _message: *const c_char,
+    user_data: *mut c_void,
+) -> c_int {
+    let state = &mut *(user_data as *mut ProgressState);
+    if state.cancel.load(Ordering::Relaxed) {
+        return 0;
+    }
+    match catch_unwind(AssertUnwindSafe(|| {
+        (state.on_progress)(complete.clamp(0.0, 1.0))
+    })) {
+        Ok(()) => 1,
+        Err(_) => {
+            eprintln!("GDAL progress callback panicked; aborting operation");
+            0
+        }
+    }
+}
+
+/// Null-terminated argv built from owned strings.
+struct Argv {
+    _strings: Vec<CString>,
+    pointers: Vec<*mut c_char>,
+}
+
+impl Argv {
+    fn new(args: &[String]) -> Result<Self, ProcessingError> {
+        let strings = args
+            .iter()
+            .map(|arg| CString::new(arg.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ProcessingError::GdalError(e.to_string()))?;
+        let pointers = strings
+            .iter()
+            .map(|s| s.as_ptr() as *mut c_char)
+            .chain(std::iter::once(null_mut()))
+            .collect();
+        Ok(Self {
+            _strings: strings,
+            pointers,
+        })
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut *mut c_char {
+        self.pointers.as_mut_ptr()
+    }
+}
+
+/// Merge `inputs` into `output` with `GDALWarp`, using gdalwarp-style `args`.
+pub(crate) fn warp(
+    inputs: &[String],
+    output: &str,
+    args: &[String],
+    cancel: &AtomicBool,
+    on_progress: &mut dyn FnMut(f64),
+) -> Result<(), ProcessingError> {
+    let datasets = inputs
+        .iter()
+        .map(Dataset::open)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut handles: Vec<gdal_sys::GDALDatasetH> =
+        datasets.iter().map(|dataset| dataset.c_dataset()).collect();
+    let c_output = c_path(output)?;
+    let mut argv = Argv::new(args)?;
+    let mut state = ProgressState {
+        cancel,
+        on_progress,
+    };
+
+    let result = unsafe {
+        let options = gdal_sys::GDALWarpAppOptionsNew(argv.as_mut_ptr(), null_mut());
+        if options.is_null() {
+            return Err(last_error("GDALWarpAppOptionsNew"));
+        }
+        gdal_sys::GDALWarpAppOptionsSetProgress(
+            options,
+            Some(progress_trampoline),
+            &mut state as *mut ProgressState as *mut c_void,
+        );
+        let mut usage_error: c_int = 0;
+        let result = gdal_sys::GDALWarp(
+            c_output.as_ptr(),
+            null_mut(),
+            handles.len() as c_int,
+            handles.as_mut_ptr(),
+            options,
+            &mut usage_error,
+        );
+        gdal_sys::GDALWarpAppOptionsFree(options);
+        result
+    };
+
+    close_result(result, "GDALWarp", cancel)
+}
+
+/// Convert `input` to `output` with `GDALTranslate`, using gdal_translate-style `args`.
+pub(crate) fn translate(
+    input: &str,
+    output: &str,
+    args: &[String],
+    cancel: &AtomicBool,
+    on_progress: &mut dyn FnMut(f64),
+) -> Result<(), ProcessingError> {
+    let dataset = Dataset::open(input)?;
+    let c_output = c_path(output)?;
+    let mut argv = Argv::new(args)?;
+    let mut state = ProgressState {
+        cancel,
+        on_progress,
+    };
+
+    let result = unsafe {
+        let options = gdal_sys::GDALTranslateOptionsNew(argv.as_mut_ptr(), null_mut());
+        if options.is_null() {
+            return Err(last_error("GDALTranslateOptionsNew"));
+        }
+        gdal_sys::GDALTranslateOptionsSetProgress(
+            options,
+            Some(progress_trampoline),
+            &mut state as *mut ProgressState as *mut c_void,
+        );
+        let mut usage_error: c_int = 0;
+        let result = gdal_sys::GDALTranslate(
+            c_output.as_ptr(),
+            dataset.c_dataset(),
+            options,
+            &mut usage_error,
+        );
+        gdal_sys::GDALTranslateOptionsFree(options);
+        result
+    };
+
+    close_result(result, "GDALTranslate", cancel)
+}
+
+/// Read raster properties without going through `gdalinfo`.
+pub(crate) fn raster_info(path: &str) -> Result<RasterInfo, ProcessingError> {
+    let dataset = Dataset::open(path)?;
+    let band = dataset.rasterband(1)?;
+    let (columns, rows) = dataset.raster_size();
+    let extent = dataset.geo_transform().ok().map(|gt| {
+        let x1 = gt[0];
+        let y1 = gt[3];
+        let x2 = gt[0] + gt[1] * columns as f64;
+        let y2 = gt[3] + gt[5] * rows as f64;
+        [x1.min(x2), y1.min(y2), x1.max(x2), y1.max(y2)]
+    });
+    let srs_wkt = Some(dataset.projection()).filter(|wkt| !wkt.is_empty());
+
+    Ok(RasterInfo {
+        data_type: band.band_type().name(),
+        nodata: band.no_data_value(),
+        size: Some([columns as u64, rows as u64]),
+        extent,
+        srs_wkt,
+    })
+}
+
+fn c_path(path: &str) -> Result<CString, ProcessingError> {
+    CString::new(path).map_err(|e| ProcessingError::GdalError(e.to_string()))
+}
+
+/// Close the dataset returned by a GDAL utility, flushing it to disk, or map
+/// a null result to a cancellation or the last GDAL error.
+fn close_result(
+    result: gdal_sys::GDALDatasetH,
+    method: &str,
+    cancel: &AtomicBool,
+) -> Result<(), ProcessingError> {
+    if result.is_null() {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(ProcessingError::Cancelled);
+        }
+        return Err(last_error(method));
+    }
+    drop(unsafe { Dataset::from_c_dataset(result) });
+    Ok(())
+}
+
+fn last_error(method: &str) -> ProcessingError {
+    let message = unsafe {
+        let message = gdal_sys::CPLGetLastErrorMsg();
+        let text = if message.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(message).to_string_lossy().to_string()
+        };
+        gdal_sys::CPLErrorReset();
+        text
+    };
+    ProcessingError::GdalError(format!("{} failed: {}", method, message))
+}
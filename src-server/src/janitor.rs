@@ -0,0 +1,273 @@
+//! Background cleanup of finished download jobs.
+//!
+//! Job outputs live under `temp_dir()/dtm-downloads/<id>` and each job keeps
+//! an entry in [`AppState::downloads`]. The janitor deletes both once the
+//! retention policy says the output is no longer needed, and leaves a small
+//! tombstone behind so the job reports `expired` rather than "not found".
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::sync::RwLock;
+
+use crate::api_types::JobStatus;
+use crate::routes::{work_root_dir, AppState, DownloadJob};
+
+/// How often the janitor sweeps.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Hours an output is kept after completion unless `DTM_OUTPUT_RETENTION_HOURS` is set.
+const DEFAULT_RETENTION_HOURS: u64 = 24;
+
+/// Time given to a client after its download finished before the output goes.
+const DOWNLOAD_GRACE: Duration = Duration::from_secs(60);
+
+/// How long expired job ids keep reporting `expired`.
+const TOMBSTONE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Time after a job finishes before its output is deleted; zero keeps it.
+    pub retention: Duration,
+    /// Delete the output once it has been downloaded in full.
+    pub delete_after_download: bool,
+}
+
+impl RetentionPolicy {
+    /// Read `DTM_OUTPUT_RETENTION_HOURS` and `DTM_DELETE_AFTER_DOWNLOAD`.
+    pub fn from_env() -> Self {
+        let hours = std::env::var("DTM_OUTPUT_RETENTION_HOURS")
+            .ok()
+            .and_then(|v| v.trim().parse::<f64>().ok())
+            .filter(|h| h.is_finite() && *h >= 0.0)
+            .unwrap_or(DEFAULT_RETENTION_HOURS as f64);
+        let delete_after_download = std::env::var("DTM_DELETE_AFTER_DOWNLOAD")
+            .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        Self {
+            retention: Duration::from_secs_f64(hours * 3600.0),
+            delete_after_download,
+        }
+    }
+
+    /// Time left before the job's output expires, if it is set to expire.
+    pub fn expires_in(&self, job: &DownloadJob, now: Instant) -> Option<Duration> {
+        if job.status == JobStatus::Running {
+            return None;
+        }
+        let finished = job.finished_at?;
+        let by_age = (!self.retention.is_zero()).then(|| finished + self.retention);
+        let by_download = job
+            .downloaded_at
+            .filter(|_| self.delete_after_download)
+            .map(|at| at + DOWNLOAD_GRACE);
+        let deadline = match (by_age, by_download) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b)?,
+        };
+        Some(deadline.saturating_duration_since(now))
+    }
+
+    fn is_expired(&self, job: &DownloadJob, now: Instant) -> bool {
+        self.expires_in(job, now).is_some_and(|left| left.is_zero())
+    }
+}
+
+/// Sweep finished jobs every [`SWEEP_INTERVAL`] for the life of the server.
+pub fn spawn_janitor(state: Arc<RwLock<AppState>>) {
+    tokio::spawn(async move {
+        let work_root = work_root_dir();
+        loop {
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+            let policy = RetentionPolicy::from_env();
+            let removed = sweep(&state, &policy, &work_root, Instant::now()).await;
+            if removed > 0 {
+                println!("Removed {} expired download(s)", removed);
+            }
+        }
+    });
+}
+
+/// Remove expired jobs and stray work directories; returns the number of jobs removed.
+pub async fn sweep(
+    state: &Arc<RwLock<AppState>>,
+    policy: &RetentionPolicy,
+    work_root: &Path,
+    now: Instant,
+) -> usize {
+    let jobs: Vec<_> = state
+        .read()
+        .await
+        .downloads
+        .iter()
+        .map(|(id, job)| (id.clone(), job.clone()))
+        .collect();
+
+    let mut expired = Vec::new();
+    for (id, job_state) in jobs {
+        let job = job_state.read().await;
+        match job.as_ref() {
+            Some(job) if policy.is_expired(job, now) => {
+                expired.push((id, Some(job.work_dir.clone())))
+            }
+            Some(_) => {}
+            None => expired.push((id, None)),
+        }
+    }
+
+    for (_, work_dir) in &expired {
+        if let Some(work_dir) = work_dir {
+            remove_dir(work_dir);
+        }
+    }
+
+    let active: HashSet<String> = {
+        let mut state = state.write().await;
+        for (id, _) in &expired {
+            state.downloads.remove(id);
+            state.expired.insert(id.clone(), now);
+            state.tiles.remove_job(id);
+        }
+        state
+            .expired
+            .retain(|_, at| now.saturating_duration_since(*at) < TOMBSTONE_TTL);
+        state.downloads.keys().cloned().collect()
+    };
+
+    if !policy.retention.is_zero() {
+        remove_stray_work_dirs(work_root, &active, policy.retention);
+    }
+    expired.len()
+}
+
+/// Delete work directories no job owns, such as those left by a previous
+/// server run or by failed jobs, once they are older than the retention period.
+fn remove_stray_work_dirs(work_root: &Path, active: &HashSet<String>, retention: Duration) {
+    let Ok(entries) = std::fs::read_dir(work_root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if active.contains(&name) {
+            continue;
+        }
+        let age = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        if age.is_some_and(|age| age >= retention) {
+            remove_dir(&entry.path());
+        }
+    }
+}
+
+fn remove_dir(path: &Path) {
+    match std::fs::remove_dir_all(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("Failed to remove {}: {}", path.display(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::byte_range::ByteCoverage;
+    use crate::package_index::PackageIndex;
+    use std::sync::atomic::AtomicBool;
+    use tokio::sync::broadcast;
+
+    fn finished_job(work_dir: &Path, finished_at: Instant) -> DownloadJob {
+        DownloadJob {
+            output_path: work_dir.join("out.tif").to_string_lossy().to_string(),
+            filename: "out.tif".to_string(),
+            work_dir: work_dir.to_path_buf(),
+            sender: broadcast::channel(1).0,
+            cancel: Arc::new(AtomicBool::new(false)),
+            status: JobStatus::Completed,
+            error: None,
+            finished_at: Some(finished_at),
+            downloaded_at: None,
+            served: ByteCoverage::default(),
+            statistics: None,
+        }
+    }
+
+    #[test]
+    fn test_expiry_by_age_and_by_download() {
+        let now = Instant::now();
+        let dir = std::env::temp_dir();
+        let hour = Duration::from_secs(3600);
+        let by_age = RetentionPolicy {
+            retention: hour,
+            delete_after_download: false,
+        };
+
+        let mut job = finished_job(&dir, now);
+        assert_eq!(by_age.expires_in(&job, now), Some(hour));
+        assert!(by_age.is_expired(&job, now + hour));
+
+        job.status = JobStatus::Running;
+        assert!(!by_age.is_expired(&job, now + hour * 10));
+
+        let by_download = RetentionPolicy {
+            retention: Duration::ZERO,
+            delete_after_download: true,
+        };
+        let mut job = finished_job(&dir, now);
+        assert_eq!(by_download.expires_in(&job, now), None);
+        job.downloaded_at = Some(now);
+        assert!(!by_download.is_expired(&job, now));
+        assert!(by_download.is_expired(&job, now + DOWNLOAD_GRACE));
+    }
+
+    #[tokio::test]
+    async fn test_sweep_removes_expired_jobs_and_stray_dirs() {
+        let work_root = std::env::temp_dir().join(format!("dtm-janitor-{}", uuid::Uuid::new_v4()));
+        let old_dir = work_root.join("old");
+        let fresh_dir = work_root.join("fresh");
+        let stray_dir = work_root.join("stray");
+        for dir in [&old_dir, &fresh_dir, &stray_dir] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+
+        let now = Instant::now();
+        let hour = Duration::from_secs(3600);
+        let index = PackageIndex::new(work_root.join("index.json"), Duration::ZERO);
+        let mut app = AppState::with_index(Arc::new(index));
+        for (id, dir, finished) in [("old", &old_dir, now), ("fresh", &fresh_dir, now + hour)] {
+            let job = finished_job(dir, finished);
+            app.downloads
+                .insert(id.to_string(), Arc::new(RwLock::new(Some(job))));
+        }
+        let state = Arc::new(RwLock::new(app));
+
+        let policy = RetentionPolicy {
+            retention: hour,
+            delete_after_download: false,
+        };
+        let removed = sweep(&state, &policy, &work_root, now + hour).await;
+        assert_eq!(removed, 1);
+
+        let state = state.read().await;
+        assert!(state.expired.contains_key("old"));
+        assert!(state.downloads.contains_key("fresh"));
+        assert!(!old_dir.exists());
+        assert!(fresh_dir.exists());
+        // Just created, so younger than the retention period.
+        assert!(stray_dir.exists());
+
+        remove_stray_work_dirs(
+            &work_root,
+            &HashSet::from(["fresh".to_string()]),
+            Duration::ZERO,
+        );
+        assert!(!stray_dir.exists());
+        assert!(fresh_dir.exists());
+
+        let _ = std::fs::remove_dir_all(&work_root);
+    }
+}
@@ -1,8 +1,30 @@
 pub mod api_types;
+pub mod byte_range;
+pub mod cog_cache;
+pub mod coverage;
+pub mod disk_space;
 pub mod download;
+pub mod elevation;
+pub mod estimate;
+pub mod export;
+#[cfg(feature = "native-gdal")]
+mod gdal_native;
+pub mod janitor;
+pub mod ogc_api;
 pub mod package_client;
+pub mod package_index;
+pub mod package_source;
 pub mod processing;
+pub mod projection;
 pub mod routes;
+pub mod selection;
+pub mod spatial_index;
+pub mod stac_client;
+pub mod statistics;
+#[cfg(test)]
+mod test_support;
+pub mod tiles;
+pub mod vertical_datum;
 
 use axum::{
     routing::{get, post},
@@ -17,21 +39,66 @@ use tower_http::{
 };
 
 pub fn create_router() -> Router {
-    create_router_with_frontend_dist(resolve_frontend_dist_dir())
+    let state = routes::AppState::new();
+    package_index::spawn_refresh_task(state.index.clone(), state.sources.clone());
+    let state = Arc::new(RwLock::new(state));
+    janitor::spawn_janitor(state.clone());
+    build_router(state, resolve_frontend_dist_dir())
 }
 
+#[cfg(test)]
 fn create_router_with_frontend_dist(frontend_dist_dir: Option<PathBuf>) -> Router {
-    let state = Arc::new(RwLock::new(routes::AppState::new()));
+    create_router_with_state(routes::AppState::new(), frontend_dist_dir)
+}
 
+#[cfg(test)]
+fn create_router_with_state(state: routes::AppState, frontend_dist_dir: Option<PathBuf>) -> Router {
+    build_router(Arc::new(RwLock::new(state)), frontend_dist_dir)
+}
+
+fn build_router(
+    state: Arc<RwLock<routes::AppState>>,
+    frontend_dist_dir: Option<PathBuf>,
+) -> Router {
     let router = Router::new()
         .route("/api/packages/query", post(routes::query_packages))
+        .route("/api/packages/coverage", post(routes::coverage_report))
+        .route("/api/packages/select", post(routes::select_coverage))
+        .route("/api/index/status", get(routes::index_status))
+        .route("/api/index/sync", post(routes::sync_index))
+        .route("/api/download/estimate", post(routes::estimate_download))
         .route("/api/download/start", post(routes::start_download))
         .route(
             "/api/download/{id}/progress",
             get(routes::download_progress),
         )
+        .route("/api/download/{id}/status", get(routes::download_status))
         .route("/api/download/{id}/file", get(routes::download_file))
+        // Dynamic suffixes are not supported by the router; `{tile}` is `{y}.png`.
+        .route(
+            "/api/download/{id}/tiles/{z}/{x}/{tile}",
+            get(routes::download_tile),
+        )
+        .route(
+            "/api/download/{id}/statistics",
+            post(routes::download_statistics),
+        )
+        .route("/api/download/{id}/cancel", post(routes::cancel_download))
+        .route("/api/elevation", get(routes::elevation))
+        .route("/api/profile", post(routes::profile))
         .route("/api/health", get(routes::health))
+        .route("/ogc", get(ogc_api::landing_page))
+        .route("/ogc/conformance", get(ogc_api::conformance))
+        .route("/ogc/collections", get(ogc_api::collections))
+        .route("/ogc/collections/{collection_id}", get(ogc_api::collection))
+        .route(
+            "/ogc/collections/{collection_id}/items",
+            get(ogc_api::items),
+        )
+        .route(
+            "/ogc/collections/{collection_id}/items/{feature_id}",
+            get(ogc_api::item),
+        )
         .with_state(state)
         .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any));
 
@@ -125,6 +192,287 @@ mod tests {
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
+    #[tokio::test]
+    async fn test_query_is_served_from_local_index() {
+        let index = package_index::tests::fixture_index().await;
+        let state = routes::AppState::with_index(Arc::new(index));
+        let app = create_router_with_state(state, None);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/packages/query")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        r#"{"min_x":-8460000,"min_y":5690000,"max_x":-8440000,"max_y":5700000}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let result: api_types::QueryResult = serde_json::from_slice(&body).unwrap();
+        assert_eq!(result.packages.len(), 1);
+        assert_eq!(result.packages[0].package_name, "Ottawa River A");
+        assert_eq!(result.projects, vec!["OMAFRA Lidar 2022".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_query_applies_attribute_filters() {
+        let index = package_index::tests::fixture_index().await;
+        let state = routes::AppState::with_index(Arc::new(index));
+        let app = create_router_with_state(state, None);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/packages/query")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        r#"{"min_x":-8860000,"min_y":5390000,"max_x":-8790000,"max_y":5450000,"max_resolution":0.5,"min_year":2015}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let result: api_types::QueryResult = serde_json::from_slice(&body).unwrap();
+        let names: Vec<_> = result
+            .packages
+            .iter()
+            .map(|p| p.package_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["GTA 1"]);
+    }
+
+    #[tokio::test]
+    async fn test_query_formats() {
+        let index = package_index::tests::fixture_index().await;
+        let state = routes::AppState::with_index(Arc::new(index));
+        let app = create_router_with_state(state, None);
+        let query = |uri: &'static str, accept: &'static str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header("content-type", "application/json")
+                    .header("accept", accept)
+                    .body(Body::from(
+                        r#"{"min_x":-8460000,"min_y":5690000,"max_x":-8440000,"max_y":5700000}"#,
+                    ))
+                    .unwrap(),
+            )
+        };
+
+        let response = query("/api/packages/query?format=geojson", "*/*")
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "application/geo+json");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let collection: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(collection["type"], "FeatureCollection");
+        assert_eq!(
+            collection["features"][0]["properties"]["package_name"],
+            "Ottawa River A"
+        );
+        let lon = collection["features"][0]["geometry"]["coordinates"][0][0][0]
+            .as_f64()
+            .unwrap();
+        assert!((-76.0..-75.0).contains(&lon));
+
+        let response = query("/api/packages/query", "application/geo+json")
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "application/geo+json");
+
+        let response = query("/api/packages/query?format=shapefile", "*/*")
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-type"], "application/zip");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec())).unwrap();
+        assert_eq!(archive.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_ogc_items_are_paged_geojson() {
+        let index = package_index::tests::fixture_index().await;
+        let state = routes::AppState::with_index(Arc::new(index));
+        let app = create_router_with_state(state, None);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/ogc/collections/packages/items?limit=2&offset=1")
+                    .header("host", "example.com")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/geo+json");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let result: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(result["type"], "FeatureCollection");
+        assert_eq!(result["numberMatched"], 5);
+        assert_eq!(result["numberReturned"], 2);
+        assert_eq!(result["features"][0]["id"], "Cochrane B");
+        let lon = result["features"][0]["geometry"]["coordinates"][0][0][0]
+            .as_f64()
+            .unwrap();
+        assert!((-90.0..-80.0).contains(&lon));
+        let rels: Vec<_> = result["links"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|l| l["rel"].as_str().unwrap())
+            .collect();
+        assert_eq!(rels, vec!["self", "next", "prev"]);
+        assert_eq!(
+            result["links"][1]["href"],
+            "http://example.com/ogc/collections/packages/items?limit=2&offset=3"
+        );
+
+        // Around Ottawa, in CRS84.
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/ogc/collections/packages/items?bbox=-76.0,45.3,-75.8,45.4")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let result: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(result["numberMatched"], 1);
+        assert_eq!(result["features"][0]["id"], "Ottawa River A");
+    }
+
+    #[tokio::test]
+    async fn test_ogc_metadata_documents() {
+        let index = package_index::tests::fixture_index().await;
+        let state = routes::AppState::with_index(Arc::new(index));
+        let app = create_router_with_state(state, None);
+
+        let get_json = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+
+        let (_, conformance) = get_json("/ogc/conformance").await;
+        assert!(conformance["conformsTo"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|c| c.as_str().unwrap().ends_with("/conf/geojson")));
+
+        let (_, collections) = get_json("/ogc/collections").await;
+        assert_eq!(collections["collections"][0]["id"], "packages");
+        let bbox = &collections["collections"][0]["extent"]["spatial"]["bbox"][0];
+        assert!(bbox[0].as_f64().unwrap() < bbox[2].as_f64().unwrap());
+
+        let (status, error) = get_json("/ogc/collections/lakes").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error["code"], "NotFound");
+
+        let (status, _) = get_json("/ogc/collections/packages/items/Ottawa%20River%20A").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_expired_download_reports_expired_status() {
+        let index = package_index::tests::fixture_index().await;
+        let mut state = routes::AppState::with_index(Arc::new(index));
+        state
+            .expired
+            .insert("old-job".to_string(), std::time::Instant::now());
+        let app = create_router_with_state(state, None);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/download/old-job/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let status: api_types::DownloadStatus = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status.status, api_types::JobStatus::Expired);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/download/old-job/file")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(std::str::from_utf8(&body).unwrap().contains("expired"));
+    }
+
+    #[tokio::test]
+    async fn test_elevation_reports_uncached_packages() {
+        let index = package_index::tests::fixture_index().await;
+        let state = routes::AppState::with_index(Arc::new(index));
+        let app = create_router_with_state(state, None);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/elevation?x=-8440000&y=5690000")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let elevation: api_types::ElevationResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(elevation.elevation, None);
+        assert_eq!(elevation.uncached_packages, vec!["Ottawa River A"]);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/profile")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        r#"{"line":{"type":"Point","coordinates":[-8440000,5690000]}}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(std::str::from_utf8(&body).unwrap().contains("LineString"));
+    }
+
     fn create_temp_frontend_dist() -> PathBuf {
         let unique = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -0,0 +1,362 @@
+//! OGC API - Features view of the package index.
+//!
+//! Serves the package footprints as a single `packages` collection under
+//! `/ogc`, so GIS clients such as QGIS can browse them directly. Items come
+//! from the local index when it is populated and from the live sources
+//! otherwise, with geometries reprojected to WGS84 (CRS84).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+
+use crate::api_types::{BoundingBox, Package, PackageFilter};
+use crate::export::package_to_feature;
+use crate::projection::{lon_lat_to_mercator, mercator_to_lon_lat};
+use crate::routes::{find_packages, AppState};
+
+pub const COLLECTION_ID: &str = "packages";
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 10_000;
+
+/// Approximate extent of Ontario, advertised until the index has been synced.
+const ONTARIO_EXTENT: [f64; 4] = [-95.2, 41.6, -74.3, 56.9];
+
+/// Web Mercator bounds, used to request every package when no bbox is given.
+const WORLD_MERCATOR: f64 = 20_037_508.342_789_244;
+
+const CONFORMANCE_CLASSES: [&str; 2] = [
+    "http://www.opengis.net/spec/ogcapi-features-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-features-1/1.0/conf/geojson",
+];
+
+const CRS84: &str = "http://www.opengis.net/def/crs/OGC/1.3/CRS84";
+const GEOJSON: &str = "application/geo+json";
+
+/// An OGC API exception, returned as `{"code": ..., "description": ...}`.
+#[derive(Debug)]
+pub struct OgcError {
+    status: StatusCode,
+    code: &'static str,
+    description: String,
+}
+
+impl OgcError {
+    fn invalid_parameter(description: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            code: "InvalidParameterValue",
+            description: description.into(),
+        }
+    }
+
+    fn not_found(description: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            code: "NotFound",
+            description: description.into(),
+        }
+    }
+}
+
+impl IntoResponse for OgcError {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            Json(json!({"code": self.code, "description": self.description})),
+        )
+            .into_response()
+    }
+}
+
+/// Landing page: links to the conformance and collections documents.
+pub async fn landing_page(headers: HeaderMap) -> Json<Value> {
+    let base = base_url(&headers);
+    Json(json!({
+        "title": "Ontario DTM package index",
+        "description": "Footprints of downloadable elevation packages",
+        "links": [
+            link(&base, "self", "application/json", "This document"),
+            link(&format!("{}/conformance", base), "conformance", "application/json", "Conformance classes"),
+            link(&format!("{}/collections", base), "data", "application/json", "Collections"),
+        ],
+    }))
+}
+
+pub async fn conformance() -> Json<Value> {
+    Json(json!({ "conformsTo": CONFORMANCE_CLASSES }))
+}
+
+pub async fn collections(
+    State(state): State<Arc<RwLock<AppState>>>,
+    headers: HeaderMap,
+) -> Json<Value> {
+    let base = base_url(&headers);
+    let collection = collection_document(&state, &base).await;
+    Json(json!({
+        "collections": [collection],
+        "links": [link(&format!("{}/collections", base), "self", "application/json", "Collections")],
+    }))
+}
+
+pub async fn collection(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(collection_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Value>, OgcError> {
+    check_collection(&collection_id)?;
+    Ok(Json(collection_document(&state, &base_url(&headers)).await))
+}
+
+/// `GET /collections/packages/items` with optional `bbox`, `limit` and `offset`.
+pub async fn items(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path(collection_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, OgcError> {
+    check_collection(&collection_id)?;
+
+    let bbox = params.get("bbox").map(|b| parse_bbox(b)).transpose()?;
+    let limit = parse_count(&params, "limit", DEFAULT_LIMIT)?.clamp(1, MAX_LIMIT);
+    let offset = parse_count(&params, "offset", 0)?;
+
+    let packages = all_packages(&state, bbox.as_ref()).await?;
+    let matched = packages.len();
+    let features: Vec<Value> = packages
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(package_to_feature)
+        .collect();
+
+    let items_url = format!("{}/collections/{}/items", base_url(&headers), COLLECTION_ID);
+    let page_url = |offset: usize| {
+        let mut url = format!("{}?limit={}&offset={}", items_url, limit, offset);
+        if let Some(bbox) = params.get("bbox") {
+            url.push_str(&format!("&bbox={}", bbox));
+        }
+        url
+    };
+
+    let mut links = vec![link(&page_url(offset), "self", GEOJSON, "This page")];
+    if offset + features.len() < matched {
+        links.push(link(
+            &page_url(offset + limit),
+            "next",
+            GEOJSON,
+            "Next page",
+        ));
+    }
+    if offset > 0 {
+        links.push(link(
+            &page_url(offset.saturating_sub(limit)),
+            "prev",
+            GEOJSON,
+            "Previous page",
+        ));
+    }
+
+    Ok(geojson_response(json!({
+        "type": "FeatureCollection",
+        "numberMatched": matched,
+        "numberReturned": features.len(),
+        "features": features,
+        "links": links,
+    })))
+}
+
+/// `GET /collections/packages/items/{featureId}`, where the id is the package name.
+pub async fn item(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Path((collection_id, feature_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, OgcError> {
+    check_collection(&collection_id)?;
+
+    let packages = all_packages(&state, None).await?;
+    let package = packages
+        .iter()
+        .find(|pkg| pkg.package_name == feature_id)
+        .ok_or_else(|| OgcError::not_found(format!("Feature '{}' not found", feature_id)))?;
+
+    let items_url = format!("{}/collections/{}/items", base_url(&headers), COLLECTION_ID);
+    let mut feature = package_to_feature(package);
+    feature["links"] = json!([
+        link(
+            &format!("{}/{}", items_url, encode_path_segment(&feature_id)),
+            "self",
+            GEOJSON,
+            "This feature"
+        ),
+        link(&items_url, "collection", GEOJSON, "Package footprints"),
+    ]);
+    Ok(geojson_response(feature))
+}
+
+async fn collection_document(state: &Arc<RwLock<AppState>>, base: &str) -> Value {
+    let index = state.read().await.index.clone();
+    let extent = match index.extent().await {
+        Some(bbox) => {
+            let [xmin, ymin] = mercator_to_lon_lat(bbox.xmin, bbox.ymin);
+            let [xmax, ymax] = mercator_to_lon_lat(bbox.xmax, bbox.ymax);
+            [xmin, ymin, xmax, ymax]
+        }
+        None => ONTARIO_EXTENT,
+    };
+    let collection_url = format!("{}/collections/{}", base, COLLECTION_ID);
+
+    json!({
+        "id": COLLECTION_ID,
+        "title": "Packages",
+        "description": "Footprints of downloadable DTM packages from all configured sources",
+        "itemType": "feature",
+        "crs": [CRS84],
+        "extent": {
+            "spatial": { "bbox": [extent], "crs": CRS84 },
+        },
+        "links": [
+            link(&collection_url, "self", "application/json", "This collection"),
+            link(&format!("{}/items", collection_url), "items", GEOJSON, "Package footprints"),
+        ],
+    })
+}
+
+/// Packages intersecting `bbox` (or all of them), in a stable order for paging.
+async fn all_packages(
+    state: &Arc<RwLock<AppState>>,
+    bbox: Option<&BoundingBox>,
+) -> Result<Vec<Package>, OgcError> {
+    let world = BoundingBox::new(
+        -WORLD_MERCATOR,
+        -WORLD_MERCATOR,
+        WORLD_MERCATOR,
+        WORLD_MERCATOR,
+        3857,
+    );
+    let mut packages = find_packages(state, bbox.unwrap_or(&world), &PackageFilter::default())
+        .await
+        .map_err(|description| OgcError {
+            status: StatusCode::BAD_GATEWAY,
+            code: "ServerError",
+            description,
+        })?;
+    packages.sort_by(|a, b| {
+        a.package_name
+            .cmp(&b.package_name)
+            .then_with(|| a.source.cmp(&b.source))
+    });
+    Ok(packages)
+}
+
+fn check_collection(collection_id: &str) -> Result<(), OgcError> {
+    if collection_id == COLLECTION_ID {
+        Ok(())
+    } else {
+        Err(OgcError::not_found(format!(
+            "Collection '{}' not found",
+            collection_id
+        )))
+    }
+}
+
+/// Parse a CRS84 `bbox` of four (or six, with heights) numbers into Web Mercator.
+fn parse_bbox(value: &str) -> Result<BoundingBox, OgcError> {
+    let invalid = || OgcError::invalid_parameter(format!("Invalid bbox '{}'", value));
+    let numbers = value
+        .split(',')
+        .map(|n| n.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    let [min_lon, min_lat, max_lon, max_lat] = match numbers.as_slice() {
+        [a, b, c, d] | [a, b, _, c, d, _] => [*a, *b, *c, *d],
+        _ => return Err(invalid()),
+    };
+    if min_lat > max_lat {
+        return Err(invalid());
+    }
+
+    let [xmin, ymin] = lon_lat_to_mercator(min_lon, min_lat);
+    let [xmax, ymax] = lon_lat_to_mercator(max_lon, max_lat);
+    Ok(BoundingBox::new(xmin, ymin, xmax, ymax, 3857))
+}
+
+fn parse_count(
+    params: &HashMap<String, String>,
+    name: &str,
+    default: usize,
+) -> Result<usize, OgcError> {
+    match params.get(name) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| OgcError::invalid_parameter(format!("Invalid {} '{}'", name, value))),
+        None => Ok(default),
+    }
+}
+
+fn geojson_response(body: Value) -> Response {
+    ([(header::CONTENT_TYPE, GEOJSON)], body.to_string()).into_response()
+}
+
+fn link(href: &str, rel: &str, media_type: &str, title: &str) -> Value {
+    json!({"href": href, "rel": rel, "type": media_type, "title": title})
+}
+
+/// Absolute URL of the API root, taken from the request's `Host` header.
+fn base_url(headers: &HeaderMap) -> String {
+    let header_value = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(',').next().unwrap_or(v).trim().to_string())
+    };
+    let host = header_value("x-forwarded-host")
+        .or_else(|| header_value(header::HOST.as_str()))
+        .unwrap_or_else(|| "localhost:3000".to_string());
+    let scheme = header_value("x-forwarded-proto").unwrap_or_else(|| "http".to_string());
+    format!("{}://{}/ogc", scheme, host)
+}
+
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bbox() {
+        let bbox = parse_bbox("-80,43,-79,44").unwrap();
+        assert!((bbox.xmin - -8_905_559.26).abs() < 0.01);
+        assert!(bbox.ymin < bbox.ymax);
+
+        let with_heights = parse_bbox("-80,43,0,-79,44,100").unwrap();
+        assert_eq!(with_heights.xmax, bbox.xmax);
+
+        assert!(parse_bbox("-80,43,-79").is_err());
+        assert!(parse_bbox("-80,44,-79,43").is_err());
+        assert!(parse_bbox("a,b,c,d").is_err());
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(encode_path_segment("Ottawa River A"), "Ottawa%20River%20A");
+        assert_eq!(encode_path_segment("a/b"), "a%2Fb");
+    }
+}
@@ -1,18 +1,30 @@
 //! Client for querying the Ontario DTM Package Index via ArcGIS REST API.
 
 use crate::api_types::{
-    extract_download_url, extract_year_range, ArcGISQueryResponse, BoundingBox, GeoJSONGeometry,
-    Package,
+    extract_download_url, extract_year_range, AcquisitionPeriod, ArcGISAttributes,
+    ArcGISErrorResponse, ArcGISLayerInfo, ArcGISObjectIdsResponse, ArcGISQueryResponse,
+    BoundingBox, GeoJSONGeometry, Package, PackageFilter,
 };
 use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
 use thiserror::Error;
+use tokio::sync::OnceCell;
+
+/// Source identifier of the Ontario DTM Package Index.
+pub const ONTARIO_DTM_SOURCE_ID: &str = "ontario-dtm";
 
 /// Base URL for the Ontario DTM Package Index Feature Server.
-const BASE_URL: &str = "https://services1.arcgis.com/TJH5KDher0W13Kgo/arcgis/rest/services/Ontario_Digital_Terrain_Model_Lidar_Derived_WFL1/FeatureServer/0";
+pub const BASE_URL: &str = "https://services1.arcgis.com/TJH5KDher0W13Kgo/arcgis/rest/services/Ontario_Digital_Terrain_Model_Lidar_Derived_WFL1/FeatureServer/0";
 
 /// Maximum records per request (ArcGIS limit).
 const MAX_RECORD_COUNT: usize = 2000;
 
+/// Object IDs requested per page when the server does not support offsets.
+const OBJECT_ID_PAGE_SIZE: usize = 500;
+
 /// Errors that can occur when querying the package index.
 #[derive(Debug, Error)]
 pub enum PackageClientError {
@@ -22,6 +34,9 @@ pub enum PackageClientError {
     #[error("Failed to parse JSON response: {0}")]
     JsonParseError(#[from] serde_json::Error),
 
+    #[error("ArcGIS error {code}: {message}")]
+    ArcGis { code: i64, message: String },
+
     #[error("Missing required field: {0}")]
     MissingField(String),
 
@@ -29,11 +44,94 @@ pub enum PackageClientError {
     InvalidGeometry,
 }
 
-/// Client for querying the Ontario DTM Package Index.
+/// Names of the layer attributes that hold each package property.
+///
+/// Defaults to the Ontario DTM Package Index schema. Optional fields that are
+/// `None` are not requested and left at their defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldMapping {
+    pub package_name: String,
+    pub download_link: String,
+    pub size_gb: Option<String>,
+    pub resolution: Option<String>,
+    pub project: Option<String>,
+    pub area: Option<String>,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self {
+            package_name: "Package".to_string(),
+            download_link: "DownloadLink".to_string(),
+            size_gb: Some("Size_GB".to_string()),
+            resolution: Some("Resolution".to_string()),
+            project: Some("Project".to_string()),
+            area: Some("Shape__Area".to_string()),
+        }
+    }
+}
+
+impl FieldMapping {
+    /// The `outFields` query parameter for this mapping.
+    pub fn out_fields(&self) -> String {
+        [
+            Some(&self.package_name),
+            self.size_gb.as_ref(),
+            self.resolution.as_ref(),
+        ]
+        .into_iter()
+        .chain([
+            Some(&self.download_link),
+            self.project.as_ref(),
+            self.area.as_ref(),
+        ])
+        .flatten()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+
+    pub(crate) fn extract(&self, attributes: &Map<String, Value>) -> ArcGISAttributes {
+        let text = |field: Option<&String>| {
+            field
+                .and_then(|name| attributes.get(name))
+                .and_then(|value| match value {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+        };
+        let number = |field: Option<&String>| {
+            field
+                .and_then(|name| attributes.get(name))
+                .and_then(|value| match value {
+                    Value::Number(n) => n.as_f64(),
+                    Value::String(s) => s.trim().parse().ok(),
+                    _ => None,
+                })
+        };
+
+        ArcGISAttributes {
+            package: text(Some(&self.package_name)),
+            size_gb: number(self.size_gb.as_ref()),
+            resolution: number(self.resolution.as_ref()),
+            download_link: text(Some(&self.download_link)),
+            project: text(self.project.as_ref()),
+            shape_area: number(self.area.as_ref()),
+        }
+    }
+}
+
+/// Client for querying an ArcGIS FeatureServer package index layer.
 #[derive(Debug, Clone)]
 pub struct PackageClient {
     client: Client,
     base_url: String,
+    source_id: String,
+    fields: FieldMapping,
+    /// The layer's `supportsPagination`, read once; `None` when not reported.
+    supports_pagination: Arc<OnceCell<Option<bool>>>,
 }
 
 impl Default for PackageClient {
@@ -45,10 +143,7 @@ impl Default for PackageClient {
 impl PackageClient {
     /// Create a new package client with default settings.
     pub fn new() -> Self {
-        Self {
-            client: Client::new(),
-            base_url: BASE_URL.to_string(),
-        }
+        Self::with_base_url(BASE_URL.to_string())
     }
 
     /// Create a client with a custom base URL (for testing).
@@ -56,9 +151,23 @@ impl PackageClient {
         Self {
             client: Client::new(),
             base_url,
+            source_id: ONTARIO_DTM_SOURCE_ID.to_string(),
+            fields: FieldMapping::default(),
+            supports_pagination: Arc::new(OnceCell::new()),
         }
     }
 
+    /// Use another layer schema, tagging packages with `source_id`.
+    pub fn with_fields(mut self, source_id: impl Into<String>, fields: FieldMapping) -> Self {
+        self.source_id = source_id.into();
+        self.fields = fields;
+        self
+    }
+
+    pub fn source_id(&self) -> &str {
+        &self.source_id
+    }
+
     /// Query packages that intersect with the given bounding box.
     ///
     /// # Arguments
@@ -70,140 +179,248 @@ impl PackageClient {
         &self,
         bbox: &BoundingBox,
     ) -> Result<Vec<Package>, PackageClientError> {
-        let mut all_packages = Vec::new();
-        let mut offset = 0;
-
-        loop {
-            let packages = self.query_page(bbox, offset).await?;
-            let count = packages.len();
-            all_packages.extend(packages);
-
-            // If we got fewer than MAX_RECORD_COUNT, we've reached the end
-            if count < MAX_RECORD_COUNT {
-                break;
-            }
-            offset += count;
-        }
-
-        Ok(all_packages)
+        self.query_by_extent_filtered(bbox, &PackageFilter::default())
+            .await
     }
 
-    /// Query a single page of results.
-    async fn query_page(
+    /// Query packages that intersect the bounding box and pass `filter`.
+    ///
+    /// Project and resolution filters on mapped attributes are sent in the
+    /// `where` clause; every per-package filter is also applied to the results.
+    pub async fn query_by_extent_filtered(
         &self,
         bbox: &BoundingBox,
-        offset: usize,
+        filter: &PackageFilter,
     ) -> Result<Vec<Package>, PackageClientError> {
         let geometry = bbox.to_esri_geometry();
         println!("ArcGIS query geometry: {}", geometry);
 
-        let params = [
-            ("f", "json"),
-            ("where", "1=1"),
-            (
-                "outFields",
-                "Package,Size_GB,Resolution,DownloadLink,Project,Shape__Area",
-            ),
-            ("geometryType", "esriGeometryEnvelope"),
-            ("geometry", &geometry),
-            ("spatialRel", "esriSpatialRelIntersects"),
-            ("inSR", &bbox.srid.to_string()),
-            ("outSR", &bbox.srid.to_string()),
-            ("returnGeometry", "true"),
-            ("resultOffset", &offset.to_string()),
-            ("resultRecordCount", &MAX_RECORD_COUNT.to_string()),
+        let srid = bbox.srid.to_string();
+        let params = vec![
+            ("where", filter.to_where_clause(&self.fields)),
+            ("geometryType", "esriGeometryEnvelope".to_string()),
+            ("geometry", geometry),
+            ("spatialRel", "esriSpatialRelIntersects".to_string()),
+            ("inSR", srid.clone()),
+            ("outSR", srid),
         ];
 
-        let url = format!("{}/query", self.base_url);
-        let response = self
-            .client
-            .post(&url)
-            .form(&params)
-            .send()
-            .await?
-            .error_for_status()?;
+        let mut packages = self.query_features(&params).await?;
+        packages.retain(|pkg| filter.matches(pkg));
+        Ok(packages)
+    }
 
-        let text = response.text().await?;
-        println!("ArcGIS response length: {} bytes", text.len());
+    /// Query all packages (no spatial filter), with geometries in Web Mercator.
+    ///
+    /// Use with caution as this may return a large number of results.
+    pub async fn query_all(&self) -> Result<Vec<Package>, PackageClientError> {
+        let params = vec![("where", "1=1".to_string()), ("outSR", "3857".to_string())];
+        self.query_features(&params).await
+    }
 
-        if text.contains("\"error\"") {
-            eprintln!("ArcGIS error response: {}", text);
+    /// Run a query with offset paging, or with object ID paging when the
+    /// layer does not support offsets.
+    ///
+    /// Support is read from the layer's `advancedQueryCapabilities`; layers
+    /// that do not report it are tried with offsets first, and only an error
+    /// saying that pagination is unsupported switches to object IDs.
+    async fn query_features(
+        &self,
+        params: &[(&str, String)],
+    ) -> Result<Vec<Package>, PackageClientError> {
+        if self.supports_pagination().await == Some(false) {
+            return self.query_by_object_ids(params).await;
         }
-
-        let arcgis_response: ArcGISQueryResponse = serde_json::from_str(&text)?;
-        let raw_count = arcgis_response.features.len();
-        println!("ArcGIS returned {} raw features", raw_count);
-
-        // Convert ArcGIS features to our Package type
-        let packages = arcgis_response
-            .features
-            .into_iter()
-            .filter_map(|f| self.feature_to_package(f).transpose())
-            .collect::<Result<Vec<_>, _>>()?;
-
-        if packages.len() != raw_count {
-            println!(
-                "Filtered: {} features removed (missing fields)",
-                raw_count - packages.len()
-            );
+        match self.query_by_offset(params).await? {
+            Some(packages) => Ok(packages),
+            None => {
+                println!("Layer rejected resultOffset, paging by object ID");
+                self.query_by_object_ids(params).await
+            }
         }
+    }
 
-        Ok(packages)
+    async fn supports_pagination(&self) -> Option<bool> {
+        *self
+            .supports_pagination
+            .get_or_init(|| async {
+                match self.layer_info().await {
+                    Ok(info) => info
+                        .advanced_query_capabilities
+                        .and_then(|caps| caps.supports_pagination),
+                    Err(e) => {
+                        eprintln!("Could not read layer capabilities: {}", e);
+                        None
+                    }
+                }
+            })
+            .await
+    }
+
+    async fn layer_info(&self) -> Result<ArcGISLayerInfo, PackageClientError> {
+        let text = self
+            .client
+            .get(&self.base_url)
+            .query(&[("f", "json")])
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        parse_arcgis_body(&text)
     }
 
-    /// Query all packages (no spatial filter).
+    /// Page through results with `resultOffset` until the server stops
+    /// reporting `exceededTransferLimit`.
     ///
-    /// Use with caution as this may return a large number of results.
-    pub async fn query_all(&self) -> Result<Vec<Package>, PackageClientError> {
+    /// Returns `None` when the first page is rejected because the layer
+    /// does not support pagination.
+    async fn query_by_offset(
+        &self,
+        params: &[(&str, String)],
+    ) -> Result<Option<Vec<Package>>, PackageClientError> {
         let mut all_packages = Vec::new();
         let mut offset = 0;
 
         loop {
-            let packages = self.query_all_page(offset).await?;
-            let count = packages.len();
-            all_packages.extend(packages);
+            let mut page_params = params.to_vec();
+            page_params.extend([
+                ("resultOffset", offset.to_string()),
+                ("resultRecordCount", MAX_RECORD_COUNT.to_string()),
+            ]);
+            let page = match self.query_page(&page_params).await {
+                Err(e) if offset == 0 && is_pagination_unsupported(&e) => return Ok(None),
+                Err(PackageClientError::ArcGis { code, message }) if offset > 0 => {
+                    return Err(PackageClientError::ArcGis {
+                        code,
+                        message: format!("{} (at offset {})", message, offset),
+                    });
+                }
+                result => result?,
+            };
+
+            let raw_count = page.features.len();
+            let exceeded_transfer_limit = page.exceeded_transfer_limit;
+            all_packages.extend(self.features_to_packages(page)?);
 
-            if count < MAX_RECORD_COUNT {
+            if !exceeded_transfer_limit || raw_count == 0 {
                 break;
             }
-            offset += count;
+            offset += raw_count;
+        }
+
+        Ok(Some(all_packages))
+    }
+
+    /// Fetch the matching object IDs, then request the features in batches.
+    async fn query_by_object_ids(
+        &self,
+        params: &[(&str, String)],
+    ) -> Result<Vec<Package>, PackageClientError> {
+        let mut id_params = params.to_vec();
+        id_params.push(("returnIdsOnly", "true".to_string()));
+        let ids: ArcGISObjectIdsResponse = self.post_query(&id_params).await?;
+        let mut object_ids = ids.object_ids.unwrap_or_default();
+        object_ids.sort_unstable();
+        println!("Paging {} object IDs", object_ids.len());
+
+        let out_sr = params
+            .iter()
+            .find(|(key, _)| *key == "outSR")
+            .map(|(_, value)| value.clone());
+
+        let mut all_packages = Vec::new();
+        let mut page_size = OBJECT_ID_PAGE_SIZE;
+        let mut remaining = object_ids.as_slice();
+        while !remaining.is_empty() {
+            let chunk = &remaining[..page_size.min(remaining.len())];
+            let ids = chunk
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let mut page_params = vec![("objectIds", ids)];
+            page_params.extend(out_sr.clone().map(|sr| ("outSR", sr)));
+            let page = self.query_page(&page_params).await?;
+
+            // The server's record limit is below the batch size: retry the
+            // batch at the size it did return.
+            if page.exceeded_transfer_limit && page.features.len() < chunk.len() {
+                if page.features.is_empty() {
+                    return Err(PackageClientError::ArcGis {
+                        code: 0,
+                        message: "Server returned no features for an object ID batch".to_string(),
+                    });
+                }
+                page_size = page.features.len();
+                println!("Object ID batches capped by server, using {}", page_size);
+                continue;
+            }
+
+            all_packages.extend(self.features_to_packages(page)?);
+            remaining = &remaining[chunk.len()..];
         }
 
         Ok(all_packages)
     }
 
-    /// Query a single page without spatial filter.
-    async fn query_all_page(&self, offset: usize) -> Result<Vec<Package>, PackageClientError> {
-        let params = [
-            ("f", "json"),
-            ("where", "1=1"),
-            (
-                "outFields",
-                "Package,Size_GB,Resolution,DownloadLink,Project,Shape__Area",
-            ),
-            ("returnGeometry", "true"),
-            ("resultOffset", &offset.to_string()),
-            ("resultRecordCount", &MAX_RECORD_COUNT.to_string()),
-        ];
+    /// Query a single page of features.
+    async fn query_page(
+        &self,
+        params: &[(&str, String)],
+    ) -> Result<ArcGISQueryResponse, PackageClientError> {
+        let mut params = params.to_vec();
+        params.extend([
+            ("outFields", self.fields.out_fields()),
+            ("returnGeometry", "true".to_string()),
+        ]);
+        let response: ArcGISQueryResponse = self.post_query(&params).await?;
+        println!("ArcGIS returned {} raw features", response.features.len());
+        Ok(response)
+    }
+
+    /// POST to the layer's query endpoint and decode the JSON body, mapping
+    /// ArcGIS error payloads to [`PackageClientError::ArcGis`].
+    async fn post_query<T: DeserializeOwned>(
+        &self,
+        params: &[(&str, String)],
+    ) -> Result<T, PackageClientError> {
+        let mut form = params.to_vec();
+        form.push(("f", "json".to_string()));
 
         let url = format!("{}/query", self.base_url);
         let response = self
             .client
             .post(&url)
-            .form(&params)
+            .form(&form)
             .send()
             .await?
             .error_for_status()?;
 
         let text = response.text().await?;
-        let arcgis_response: ArcGISQueryResponse = serde_json::from_str(&text)?;
+        println!("ArcGIS response length: {} bytes", text.len());
+        parse_arcgis_body(&text)
+    }
 
-        let packages = arcgis_response
+    /// Convert a page of ArcGIS features, skipping incomplete ones.
+    fn features_to_packages(
+        &self,
+        response: ArcGISQueryResponse,
+    ) -> Result<Vec<Package>, PackageClientError> {
+        let raw_count = response.features.len();
+        let packages = response
             .features
             .into_iter()
             .filter_map(|f| self.feature_to_package(f).transpose())
             .collect::<Result<Vec<_>, _>>()?;
 
+        if packages.len() != raw_count {
+            println!(
+                "Filtered: {} features removed (missing fields)",
+                raw_count - packages.len()
+            );
+        }
+
         Ok(packages)
     }
 
@@ -212,7 +429,7 @@ impl PackageClient {
         &self,
         feature: crate::api_types::ArcGISFeature,
     ) -> Result<Option<Package>, PackageClientError> {
-        let attrs = feature.attributes;
+        let attrs = self.fields.extract(&feature.attributes);
 
         let package_name = match &attrs.package {
             Some(name) if !name.is_empty() => name.clone(),
@@ -227,16 +444,18 @@ impl PackageClient {
         };
 
         let download_url = match &attrs.download_link {
-            Some(html) if !html.is_empty() => match extract_download_url(html) {
-                Some(url) => url,
-                None => {
-                    println!(
-                        "Skipping package '{}' - could not extract URL from: {}",
-                        package_name, html
-                    );
-                    return Ok(None);
+            Some(html) if !html.is_empty() => {
+                match extract_download_url(html).or_else(|| plain_url(html)) {
+                    Some(url) => url,
+                    None => {
+                        println!(
+                            "Skipping package '{}' - could not extract URL from: {}",
+                            package_name, html
+                        );
+                        return Ok(None);
+                    }
                 }
-            },
+            }
             Some(_) => {
                 println!("Skipping package '{}' - empty download link", package_name);
                 return Ok(None);
@@ -257,6 +476,7 @@ impl PackageClient {
 
         let project = attrs.project.clone().unwrap_or_default();
         let year_range = attrs.project.as_ref().and_then(|p| extract_year_range(p));
+        let acquisition_period = AcquisitionPeriod::from_names(&project, &package_name);
 
         let coverage_km2 = attrs
             .shape_area
@@ -270,12 +490,47 @@ impl PackageClient {
             download_url,
             project,
             year_range,
+            acquisition_period,
+            source: self.source_id.clone(),
             coverage_km2,
             geometry,
         }))
     }
 }
 
+/// Download links that are a bare URL rather than an HTML anchor.
+fn plain_url(text: &str) -> Option<String> {
+    let text = text.trim();
+    (text.starts_with("https://") || text.starts_with("http://")).then(|| text.to_string())
+}
+
+/// Whether an error says the layer cannot page with `resultOffset`.
+fn is_pagination_unsupported(error: &PackageClientError) -> bool {
+    match error {
+        PackageClientError::ArcGis { message, .. } => {
+            let message = message.to_lowercase();
+            message.contains("pagination") || message.contains("resultoffset")
+        }
+        _ => false,
+    }
+}
+
+/// Decode an ArcGIS JSON body, which reports errors as `{"error": {...}}`.
+fn parse_arcgis_body<T: DeserializeOwned>(text: &str) -> Result<T, PackageClientError> {
+    if let Ok(ArcGISErrorResponse { error }) = serde_json::from_str(text) {
+        eprintln!("ArcGIS error response: {}", text);
+        let mut message = error.message;
+        if !error.details.is_empty() {
+            message = format!("{} ({})", message, error.details.join("; "));
+        }
+        return Err(PackageClientError::ArcGis {
+            code: error.code,
+            message,
+        });
+    }
+    Ok(serde_json::from_str(text)?)
+}
+
 // ============================================================
 // Unit Tests
 // ============================================================
@@ -283,6 +538,14 @@ impl PackageClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
+
+    fn attributes(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        }
+    }
 
     #[test]
     fn test_client_creation() {
@@ -299,16 +562,14 @@ mod tests {
     #[test]
     fn test_feature_to_package_conversion() {
         let feature = crate::api_types::ArcGISFeature {
-            attributes: crate::api_types::ArcGISAttributes {
-                package: Some("Test Package".to_string()),
-                size_gb: Some(2.5),
-                resolution: Some(0.5),
-                download_link: Some(
-                    r#"<a href="https://example.com/test.zip">Test</a>"#.to_string(),
-                ),
-                project: Some("Test Project 2016-18".to_string()),
-                shape_area: Some(1_000_000_000.0),
-            },
+            attributes: attributes(serde_json::json!({
+                "Package": "Test Package",
+                "Size_GB": 2.5,
+                "Resolution": 0.5,
+                "DownloadLink": r#"<a href="https://example.com/test.zip">Test</a>"#,
+                "Project": "Test Project 2016-18",
+                "Shape__Area": 1_000_000_000.0,
+            })),
             geometry: Some(crate::api_types::ArcGISPolygonGeometry {
                 rings: vec![vec![
                     vec![0.0, 0.0],
@@ -330,22 +591,26 @@ mod tests {
         assert_eq!(package.download_url, "https://example.com/test.zip");
         assert_eq!(package.project, "Test Project 2016-18");
         assert_eq!(package.year_range, Some("2016-18".to_string()));
+        assert_eq!(
+            package.acquisition_period,
+            Some(AcquisitionPeriod {
+                start_year: 2016,
+                end_year: 2018
+            })
+        );
         assert!((package.coverage_km2 - 1000.0).abs() < 0.01);
     }
 
     #[test]
     fn test_feature_to_package_skips_missing_name() {
         let feature = crate::api_types::ArcGISFeature {
-            attributes: crate::api_types::ArcGISAttributes {
-                package: None,
-                size_gb: Some(2.5),
-                resolution: Some(0.5),
-                download_link: Some(
-                    r#"<a href="https://example.com/test.zip">Test</a>"#.to_string(),
-                ),
-                project: Some("Test Project".to_string()),
-                shape_area: Some(1_000_000.0),
-            },
+            attributes: attributes(serde_json::json!({
+                "Size_GB": 2.5,
+                "Resolution": 0.5,
+                "DownloadLink": r#"<a href="https://example.com/test.zip">Test</a>"#,
+                "Project": "Test Project",
+                "Shape__Area": 1_000_000.0,
+            })),
             geometry: Some(crate::api_types::ArcGISPolygonGeometry {
                 rings: vec![vec![]],
             }),
@@ -359,14 +624,13 @@ mod tests {
     #[test]
     fn test_feature_to_package_skips_invalid_url() {
         let feature = crate::api_types::ArcGISFeature {
-            attributes: crate::api_types::ArcGISAttributes {
-                package: Some("Test".to_string()),
-                size_gb: Some(2.5),
-                resolution: Some(0.5),
-                download_link: Some("no link here".to_string()),
-                project: Some("Test Project".to_string()),
-                shape_area: Some(1_000_000.0),
-            },
+            attributes: attributes(serde_json::json!({
+                "Package": "Test",
+                "Size_GB": 2.5,
+                "Resolution": 0.5,
+                "DownloadLink": "no link here",
+                "Project": "Test Project",
+            })),
             geometry: Some(crate::api_types::ArcGISPolygonGeometry {
                 rings: vec![vec![]],
             }),
@@ -377,6 +641,225 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[test]
+    fn test_arcgis_error_payload_is_mapped() {
+        let body = r#"{"error":{"code":400,"message":"Unable to complete operation.","details":["Invalid where clause"]}}"#;
+        let err = parse_arcgis_body::<ArcGISQueryResponse>(body).unwrap_err();
+        match err {
+            PackageClientError::ArcGis { code, message } => {
+                assert_eq!(code, 400);
+                assert_eq!(
+                    message,
+                    "Unable to complete operation. (Invalid where clause)"
+                );
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    fn mock_feature(id: i64) -> serde_json::Value {
+        serde_json::json!({
+            "attributes": {
+                "OBJECTID": id,
+                "Package": format!("Package {}", id),
+                "Size_GB": 1.0,
+                "Resolution": 0.5,
+                "DownloadLink": format!("<a href=\"https://example.com/{}.zip\">Download</a>", id),
+                "Project": "OMAFRA Lidar 2022",
+                "Shape__Area": 1_000_000.0
+            },
+            "geometry": {"rings": [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0]]]}
+        })
+    }
+
+    /// Serve `handler` as a FeatureServer layer's `/query` endpoint.
+    async fn spawn_mock_layer(
+        handler: fn(&HashMap<String, String>) -> serde_json::Value,
+    ) -> PackageClient {
+        spawn_mock_layer_with_info(None, handler).await
+    }
+
+    /// Like [`spawn_mock_layer`], also serving `info` as the layer metadata.
+    async fn spawn_mock_layer_with_info(
+        info: Option<serde_json::Value>,
+        handler: fn(&HashMap<String, String>) -> serde_json::Value,
+    ) -> PackageClient {
+        let mut app = axum::Router::new().route(
+            "/query",
+            axum::routing::post(
+                move |axum::Form(params): axum::Form<HashMap<String, String>>| async move {
+                    axum::Json(handler(&params))
+                },
+            ),
+        );
+        if let Some(info) = info {
+            app = app.route(
+                "/",
+                axum::routing::get(move || async move { axum::Json(info) }),
+            );
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        PackageClient::with_base_url(format!("http://{}", addr))
+    }
+
+    #[tokio::test]
+    async fn test_pagination_follows_exceeded_transfer_limit() {
+        // The server caps pages at 3 rows, well below MAX_RECORD_COUNT.
+        let client = spawn_mock_layer(|params| {
+            let offset: i64 = params["resultOffset"].parse().unwrap();
+            let end = (offset + 3).min(7);
+            let features: Vec<_> = (offset..end).map(mock_feature).collect();
+            serde_json::json!({"features": features, "exceededTransferLimit": end < 7})
+        })
+        .await;
+
+        let packages = client.query_all().await.unwrap();
+        assert_eq!(packages.len(), 7);
+        assert_eq!(packages[6].package_name, "Package 6");
+    }
+
+    #[tokio::test]
+    async fn test_filtered_query_uses_field_mapping() {
+        let client = spawn_mock_layer(|params| {
+            assert_eq!(params["where"], "survey IN ('HRDEM 2021') AND gsd_m <= 1");
+            let feature = |name: &str, gsd: f64| {
+                serde_json::json!({
+                    "attributes": {
+                        "name": name,
+                        "url": format!("https://example.com/{}.tif", name),
+                        "survey": "HRDEM 2021",
+                        "gsd_m": gsd
+                    },
+                    "geometry": {"rings": [[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0]]]}
+                })
+            };
+            // A service ignoring part of the clause is still filtered client-side.
+            serde_json::json!({"features": [feature("fine", 1.0), feature("coarse", 2.0)]})
+        })
+        .await
+        .with_fields(
+            "hrdem",
+            FieldMapping {
+                package_name: "name".to_string(),
+                download_link: "url".to_string(),
+                size_gb: None,
+                resolution: Some("gsd_m".to_string()),
+                project: Some("survey".to_string()),
+                area: None,
+            },
+        );
+
+        let filter = PackageFilter {
+            projects: vec!["HRDEM 2021".to_string()],
+            max_resolution: Some(1.0),
+            ..Default::default()
+        };
+        let bbox = BoundingBox::new(0.0, 0.0, 1.0, 1.0, 3857);
+        let packages = client
+            .query_by_extent_filtered(&bbox, &filter)
+            .await
+            .unwrap();
+        let names: Vec<_> = packages.iter().map(|p| p.package_name.as_str()).collect();
+        assert_eq!(names, vec!["fine"]);
+        assert_eq!(packages[0].source, "hrdem");
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_object_id_paging() {
+        let client = spawn_mock_layer(|params| {
+            if params.contains_key("resultOffset") {
+                return serde_json::json!({
+                    "error": {"code": 400, "message": "Pagination is not supported."}
+                });
+            }
+            if params.get("returnIdsOnly").map(String::as_str) == Some("true") {
+                return serde_json::json!({"objectIdFieldName": "OBJECTID", "objectIds": [3, 1, 2]});
+            }
+            let features: Vec<_> = params["objectIds"]
+                .split(',')
+                .map(|id| mock_feature(id.parse().unwrap()))
+                .collect();
+            serde_json::json!({"features": features})
+        })
+        .await;
+
+        let packages = client.query_all().await.unwrap();
+        let names: Vec<_> = packages.iter().map(|p| p.package_name.as_str()).collect();
+        assert_eq!(names, vec!["Package 1", "Package 2", "Package 3"]);
+    }
+
+    #[tokio::test]
+    async fn test_layer_without_pagination_pages_by_object_id() {
+        let info = serde_json::json!({"advancedQueryCapabilities": {"supportsPagination": false}});
+        let client = spawn_mock_layer_with_info(Some(info), |params| {
+            assert!(!params.contains_key("resultOffset"));
+            if params.contains_key("returnIdsOnly") {
+                return serde_json::json!({"objectIds": [1, 2]});
+            }
+            let features: Vec<_> = params["objectIds"]
+                .split(',')
+                .map(|id| mock_feature(id.parse().unwrap()))
+                .collect();
+            serde_json::json!({"features": features})
+        })
+        .await;
+
+        assert_eq!(client.query_all().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_other_errors_do_not_fall_back() {
+        let client = spawn_mock_layer(|params| {
+            if params.contains_key("returnIdsOnly") {
+                return serde_json::json!({"objectIds": [1]});
+            }
+            serde_json::json!({
+                "error": {"code": 400, "message": "Unable to complete operation.", "details": ["Invalid where clause"]}
+            })
+        })
+        .await;
+
+        match client.query_all().await {
+            Err(PackageClientError::ArcGis { message, .. }) => {
+                assert!(message.contains("Invalid where clause"))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_object_id_pages_honor_exceeded_transfer_limit() {
+        let info = serde_json::json!({"advancedQueryCapabilities": {"supportsPagination": false}});
+        // The server returns at most 2 features per request.
+        let client = spawn_mock_layer_with_info(Some(info), |params| {
+            if params.contains_key("returnIdsOnly") {
+                return serde_json::json!({"objectIds": [5, 4, 3, 2, 1]});
+            }
+            let ids: Vec<i64> = params["objectIds"]
+                .split(',')
+                .map(|id| id.parse().unwrap())
+                .collect();
+            let features: Vec<_> = ids.iter().take(2).map(|&id| mock_feature(id)).collect();
+            serde_json::json!({"features": features, "exceededTransferLimit": ids.len() > 2})
+        })
+        .await;
+
+        let packages = client.query_all().await.unwrap();
+        let names: Vec<_> = packages.iter().map(|p| p.package_name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Package 1",
+                "Package 2",
+                "Package 3",
+                "Package 4",
+                "Package 5"
+            ]
+        );
+    }
+
     // Integration test - requires network access
     // Run with: cargo test -- --ignored
     #[tokio::test]
@@ -404,8 +887,60 @@ mod tests {
         let client = PackageClient::new();
 
         // Query first page only to keep test fast
-        let packages = client.query_all_page(0).await.unwrap();
-        assert!(!packages.is_empty());
-        assert!(packages.len() <= MAX_RECORD_COUNT);
+        let params = [
+            ("where", "1=1".to_string()),
+            ("resultOffset", "0".to_string()),
+            ("resultRecordCount", MAX_RECORD_COUNT.to_string()),
+        ];
+        let page = client.query_page(&params).await.unwrap();
+        assert!(!page.features.is_empty());
+        assert!(page.features.len() <= MAX_RECORD_COUNT);
+    }
+    #[tokio::test]
+    #[ignore]
+    async fn test_real_multipart_footprints_integration() {
+        use geo::{Area, LineString, Polygon};
+
+        let client = PackageClient::new();
+        let params = [
+            ("where", "1=1".to_string()),
+            ("resultOffset", "0".to_string()),
+            ("resultRecordCount", MAX_RECORD_COUNT.to_string()),
+        ];
+        let page = client.query_page(&params).await.unwrap();
+
+        let mut multipart = 0;
+        for feature in page.features {
+            let Some(geometry) = feature.geometry else {
+                continue;
+            };
+            // ESRI rings keep their order and winding: clockwise exteriors,
+            // counterclockwise holes.
+            let (mut parts, mut holes) = (0, 0);
+            for ring in &geometry.rings {
+                let line: LineString<f64> = ring.iter().map(|p| (p[0], p[1])).collect();
+                let area = Polygon::new(line, Vec::new()).signed_area();
+                if area < 0.0 {
+                    parts += 1;
+                } else if area > 0.0 {
+                    holes += 1;
+                }
+            }
+            if parts < 2 {
+                continue;
+            }
+            multipart += 1;
+
+            let footprint = GeoJSONGeometry::from_esri_rings(geometry.rings).to_multi_polygon();
+            let interiors: usize = footprint.iter().map(|p| p.interiors().len()).sum();
+            assert_eq!(
+                footprint.0.len(),
+                parts,
+                "parts of {:?}",
+                feature.attributes
+            );
+            assert_eq!(interiors, holes, "holes of {:?}", feature.attributes);
+        }
+        assert!(multipart > 0, "no multi-part footprints in the first page");
     }
 }
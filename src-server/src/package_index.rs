@@ -0,0 +1,503 @@
+//! Local snapshot of the package index.
+//!
+//! The snapshot lets package queries be answered without the live ArcGIS
+//! FeatureServer. It is stored as a GeoJSON FeatureCollection (package
+//! attributes as feature properties) and refreshed in the background with
+//! [`PackageClient::query_all`].
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+use crate::api_types::{AcquisitionPeriod, BoundingBox, IndexStatus, Package};
+use crate::package_client::{PackageClientError, ONTARIO_DTM_SOURCE_ID};
+use crate::package_source::SourceRegistry;
+use crate::spatial_index::SpatialIndex;
+
+/// Default time between background refreshes of the snapshot.
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often the background task checks whether the snapshot is stale.
+const REFRESH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Errors that can occur while loading or syncing the package index.
+#[derive(Debug, Error)]
+pub enum IndexError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Failed to parse index snapshot: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+
+    #[error("Failed to query package index: {0}")]
+    ClientError(#[from] PackageClientError),
+
+    #[error("Index sync already in progress")]
+    SyncInProgress,
+}
+
+#[derive(Debug, Default)]
+struct IndexState {
+    packages: Vec<Package>,
+    spatial: SpatialIndex,
+    synced_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+/// Clears the syncing flag when a sync ends, including when its future is
+/// dropped half way.
+struct SyncGuard<'a>(&'a AtomicBool);
+
+impl Drop for SyncGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// In-memory package index backed by a GeoJSON snapshot file.
+#[derive(Debug)]
+pub struct PackageIndex {
+    path: PathBuf,
+    refresh_interval: Duration,
+    state: RwLock<IndexState>,
+    syncing: AtomicBool,
+}
+
+impl PackageIndex {
+    /// Create an empty index persisted at `path`.
+    ///
+    /// A zero `refresh_interval` disables background refreshes.
+    pub fn new(path: PathBuf, refresh_interval: Duration) -> Self {
+        Self {
+            path,
+            refresh_interval,
+            state: RwLock::new(IndexState::default()),
+            syncing: AtomicBool::new(false),
+        }
+    }
+
+    /// Create an index configured from `DTM_INDEX_PATH` and `DTM_INDEX_REFRESH_SECS`.
+    pub fn from_env(cache_root: &Path) -> Self {
+        let path = std::env::var("DTM_INDEX_PATH")
+            .ok()
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| cache_root.join("index").join("packages.geojson"));
+        let refresh_interval = std::env::var("DTM_INDEX_REFRESH_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_REFRESH_INTERVAL);
+        Self::new(path, refresh_interval)
+    }
+
+    /// Load the snapshot file into memory, returning the number of packages.
+    pub async fn load(&self) -> Result<usize, IndexError> {
+        let text = tokio::fs::read_to_string(&self.path).await?;
+        let (packages, synced_at) = parse_snapshot(&text)?;
+        let count = packages.len();
+
+        let spatial = SpatialIndex::new(&packages);
+        let mut state = self.state.write().await;
+        state.packages = packages;
+        state.spatial = spatial;
+        state.synced_at = synced_at;
+        Ok(count)
+    }
+
+    /// Download all packages from the live sources and replace the snapshot.
+    ///
+    /// A source that fails keeps its packages from the previous snapshot, so
+    /// a transient outage does not drop them from the index. The sync only
+    /// fails when every source fails.
+    pub async fn sync(&self, sources: &SourceRegistry) -> Result<usize, IndexError> {
+        if self.syncing.swap(true, Ordering::SeqCst) {
+            return Err(IndexError::SyncInProgress);
+        }
+        let _guard = SyncGuard(&self.syncing);
+
+        let results = sources.query_all().await;
+        let previous = self.state.read().await.packages.clone();
+        let merged = merge_sync_results(results, previous);
+        let result = match merged {
+            Ok((packages, failures)) => self.store(&packages).await.map(|synced_at| {
+                let spatial = SpatialIndex::new(&packages);
+                (packages, spatial, synced_at, failures)
+            }),
+            Err(e) => Err(e),
+        };
+
+        let mut state = self.state.write().await;
+        match result {
+            Ok((packages, spatial, synced_at, failures)) => {
+                let count = packages.len();
+                state.packages = packages;
+                state.spatial = spatial;
+                state.synced_at = Some(synced_at);
+                state.last_error = (!failures.is_empty()).then(|| failures.join("; "));
+                Ok(count)
+            }
+            Err(e) => {
+                state.last_error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    async fn store(&self, packages: &[Package]) -> Result<u64, IndexError> {
+        let synced_at = unix_now();
+        let text = serialize_snapshot(packages, synced_at)?;
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp_path = self.path.with_extension("geojson.tmp");
+        tokio::fs::write(&temp_path, text).await?;
+        tokio::fs::rename(&temp_path, &self.path).await?;
+
+        Ok(synced_at)
+    }
+
+    /// Packages whose footprint intersects `bbox`.
+    ///
+    /// Returns `None` when the index holds no packages, so callers can fall
+    /// back to the live service.
+    pub async fn query(&self, bbox: &BoundingBox) -> Option<Vec<Package>> {
+        let state = self.state.read().await;
+        if state.packages.is_empty() {
+            return None;
+        }
+
+        Some(
+            state
+                .spatial
+                .intersecting_bbox(bbox)
+                .into_iter()
+                .map(|i| state.packages[i].clone())
+                .collect(),
+        )
+    }
+
+    /// Bounding box of every indexed footprint, in Web Mercator.
+    pub async fn extent(&self) -> Option<BoundingBox> {
+        self.state.read().await.spatial.extent()
+    }
+
+    /// Whether the snapshot is missing or older than the refresh interval.
+    pub async fn is_stale(&self) -> bool {
+        let state = self.state.read().await;
+        match state.synced_at {
+            Some(synced_at) => {
+                unix_now().saturating_sub(synced_at) >= self.refresh_interval.as_secs()
+            }
+            None => true,
+        }
+    }
+
+    pub async fn status(&self) -> IndexStatus {
+        let state = self.state.read().await;
+        IndexStatus {
+            path: self.path.to_string_lossy().to_string(),
+            package_count: state.packages.len(),
+            synced_at: state.synced_at,
+            age_seconds: state
+                .synced_at
+                .map(|synced_at| unix_now().saturating_sub(synced_at)),
+            refresh_interval_seconds: self.refresh_interval.as_secs(),
+            syncing: self.syncing.load(Ordering::SeqCst),
+            last_error: state.last_error.clone(),
+        }
+    }
+}
+
+/// Load the snapshot from disk and keep it fresh in the background.
+///
+/// Does nothing beyond the initial load when the refresh interval is zero.
+pub fn spawn_refresh_task(index: Arc<PackageIndex>, sources: Arc<SourceRegistry>) {
+    tokio::spawn(async move {
+        match index.load().await {
+            Ok(count) => println!("Loaded {} packages from index snapshot", count),
+            Err(e) => println!("No usable index snapshot ({}), will sync", e),
+        }
+
+        if index.refresh_interval.is_zero() {
+            return;
+        }
+
+        loop {
+            if index.is_stale().await {
+                match index.sync(&sources).await {
+                    Ok(count) => println!("Synced {} packages into local index", count),
+                    Err(e) => eprintln!("Index sync failed: {}", e),
+                }
+            }
+            tokio::time::sleep(REFRESH_CHECK_INTERVAL.min(index.refresh_interval)).await;
+        }
+    });
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFile {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    synced_at: Option<u64>,
+    features: Vec<SnapshotFeature>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnapshotFeature {
+    #[serde(rename = "type")]
+    kind: String,
+    geometry: Value,
+    properties: Map<String, Value>,
+}
+
+fn parse_snapshot(text: &str) -> Result<(Vec<Package>, Option<u64>), IndexError> {
+    let snapshot: SnapshotFile = serde_json::from_str(text)?;
+    let packages = snapshot
+        .features
+        .into_iter()
+        .map(|feature| {
+            let mut properties = feature.properties;
+            properties.insert("geometry".to_string(), feature.geometry);
+            let mut package: Package = serde_json::from_value(Value::Object(properties))?;
+            // Snapshots written before sources and acquisition periods were stored.
+            if package.source.is_empty() {
+                package.source = ONTARIO_DTM_SOURCE_ID.to_string();
+            }
+            if package.acquisition_period.is_none() {
+                package.acquisition_period =
+                    AcquisitionPeriod::from_names(&package.project, &package.package_name);
+            }
+            Ok(package)
+        })
+        .collect::<Result<Vec<Package>, serde_json::Error>>()?;
+    Ok((packages, snapshot.synced_at))
+}
+
+fn serialize_snapshot(packages: &[Package], synced_at: u64) -> Result<String, IndexError> {
+    let features = packages
+        .iter()
+        .map(|pkg| {
+            let mut properties = match serde_json::to_value(pkg)? {
+                Value::Object(map) => map,
+                _ => Map::new(),
+            };
+            let geometry = properties.remove("geometry").unwrap_or(Value::Null);
+            Ok(SnapshotFeature {
+                kind: "Feature".to_string(),
+                geometry,
+                properties,
+            })
+        })
+        .collect::<Result<Vec<_>, serde_json::Error>>()?;
+
+    Ok(serde_json::to_string(&SnapshotFile {
+        kind: "FeatureCollection".to_string(),
+        synced_at: Some(synced_at),
+        features,
+    })?)
+}
+
+/// Combine per-source sync results, keeping `previous` packages of failed sources.
+///
+/// Returns the packages and a message per failed source.
+fn merge_sync_results(
+    results: Vec<(String, Result<Vec<Package>, PackageClientError>)>,
+    previous: Vec<Package>,
+) -> Result<(Vec<Package>, Vec<String>), IndexError> {
+    let mut packages = Vec::new();
+    let mut failed = Vec::new();
+    let mut first_error = None;
+    let total = results.len();
+
+    for (id, result) in results {
+        match result {
+            Ok(found) => packages.extend(found),
+            Err(e) => {
+                eprintln!("Package source '{}' failed during sync: {}", id, e);
+                failed.push((id, e.to_string()));
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if let Some(e) = first_error.filter(|_| failed.len() == total) {
+        return Err(e.into());
+    }
+
+    packages.extend(
+        previous
+            .into_iter()
+            .filter(|pkg| failed.iter().any(|(id, _)| *id == pkg.source)),
+    );
+    let messages = failed
+        .into_iter()
+        .map(|(id, e)| format!("Source '{}' failed, kept previous packages: {}", id, e))
+        .collect();
+    Ok((packages, messages))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const FIXTURE: &str = include_str!("../fixtures/package_index.geojson");
+
+    /// Packages of the fixture snapshot.
+    pub(crate) fn fixture_packages() -> Vec<Package> {
+        parse_snapshot(FIXTURE).unwrap().0
+    }
+
+    /// An index seeded from the fixture snapshot, with background refresh disabled.
+    pub(crate) async fn fixture_index() -> PackageIndex {
+        let path = std::env::temp_dir().join(format!(
+            "dtm-index-fixture-{}-{}.geojson",
+            std::process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        std::fs::write(&path, FIXTURE).unwrap();
+        let index = PackageIndex::new(path.clone(), Duration::ZERO);
+        index.load().await.unwrap();
+        let _ = std::fs::remove_file(path);
+        index
+    }
+
+    #[test]
+    fn test_parse_fixture_snapshot() {
+        let (packages, synced_at) = parse_snapshot(FIXTURE).unwrap();
+        assert_eq!(packages.len(), 5);
+        assert_eq!(synced_at, Some(1767225600));
+        assert_eq!(packages[0].package_name, "Cochrane A");
+        assert_eq!(packages[0].project, "OMAFRA Lidar 2016-18");
+        assert_eq!(packages[0].source, ONTARIO_DTM_SOURCE_ID);
+        assert_eq!(
+            packages[0].acquisition_period,
+            Some(AcquisitionPeriod {
+                start_year: 2016,
+                end_year: 2018
+            })
+        );
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let (packages, _) = parse_snapshot(FIXTURE).unwrap();
+        let text = serialize_snapshot(&packages, 42).unwrap();
+        let (reparsed, synced_at) = parse_snapshot(&text).unwrap();
+        assert_eq!(reparsed, packages);
+        assert_eq!(synced_at, Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_query_from_fixture() {
+        let index = fixture_index().await;
+        let bbox = BoundingBox::new(-9310000.0, 6380000.0, -9290000.0, 6390000.0, 3857);
+        let packages = index.query(&bbox).await.unwrap();
+        let mut names: Vec<_> = packages.iter().map(|p| p.package_name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["Cochrane A", "Cochrane B"]);
+    }
+
+    #[tokio::test]
+    async fn test_empty_index_defers_to_live_service() {
+        let index = PackageIndex::new(PathBuf::from("/nonexistent/index.geojson"), Duration::ZERO);
+        assert!(index.load().await.is_err());
+        let bbox = BoundingBox::new(0.0, 0.0, 1.0, 1.0, 3857);
+        assert!(index.query(&bbox).await.is_none());
+        assert!(index.is_stale().await);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_sync_does_not_block_the_next_one() {
+        use crate::api_types::PackageFilter;
+        use crate::package_client::PackageClientError;
+        use crate::package_source::tests::static_registry;
+        use crate::package_source::PackageSource;
+
+        /// A source that never answers.
+        struct HangingSource;
+
+        #[async_trait::async_trait]
+        impl PackageSource for HangingSource {
+            fn id(&self) -> &str {
+                "hanging"
+            }
+
+            async fn query_by_extent(
+                &self,
+                _bbox: &BoundingBox,
+                _filter: &PackageFilter,
+            ) -> Result<Vec<Package>, PackageClientError> {
+                std::future::pending().await
+            }
+
+            async fn query_all(&self) -> Result<Vec<Package>, PackageClientError> {
+                std::future::pending().await
+            }
+        }
+
+        let index = fixture_index().await;
+        let hanging = SourceRegistry::new(vec![Arc::new(HangingSource) as Arc<dyn PackageSource>]);
+        let aborted = tokio::time::timeout(Duration::from_millis(20), index.sync(&hanging)).await;
+        assert!(aborted.is_err());
+        assert!(!index.status().await.syncing);
+
+        let registry = static_registry(&[(ONTARIO_DTM_SOURCE_ID, false)]);
+        assert_eq!(index.sync(&registry).await.unwrap(), 1);
+        let _ = std::fs::remove_file(&index.path);
+    }
+
+    #[tokio::test]
+    async fn test_sync_keeps_packages_of_failed_source() {
+        use crate::package_source::tests::static_registry;
+
+        let index = fixture_index().await;
+        let registry = static_registry(&[(ONTARIO_DTM_SOURCE_ID, true), ("ontario-dsm", false)]);
+        let count = index.sync(&registry).await.unwrap();
+        // The five fixture packages survive the failed source, next to the new one.
+        assert_eq!(count, 6);
+
+        let reloaded = PackageIndex::new(index.path.clone(), Duration::ZERO);
+        assert_eq!(reloaded.load().await.unwrap(), 6);
+        let _ = std::fs::remove_file(&index.path);
+
+        let status = index.status().await;
+        assert!(status
+            .last_error
+            .unwrap()
+            .contains(&format!("'{}'", ONTARIO_DTM_SOURCE_ID)));
+
+        // With every source down the previous snapshot stays as it was.
+        let registry = static_registry(&[(ONTARIO_DTM_SOURCE_ID, true), ("ontario-dsm", true)]);
+        assert!(index.sync(&registry).await.is_err());
+        assert_eq!(index.status().await.package_count, 6);
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_snapshot() {
+        let index = fixture_index().await;
+        let status = index.status().await;
+        assert_eq!(status.package_count, 5);
+        assert_eq!(status.synced_at, Some(1767225600));
+        assert!(!status.syncing);
+        assert_eq!(status.refresh_interval_seconds, 0);
+    }
+}
@@ -0,0 +1,384 @@
+//! Package sources: catalogs that packages can be queried from.
+//!
+//! The Ontario DTM Package Index is always available. Further sources, such
+//! as other ArcGIS FeatureServer layers with their own attribute schema or
+//! STAC API catalogs, are configured in a JSON file named by
+//! `DTM_SOURCES_CONFIG`.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::api_types::{BoundingBox, Package, PackageFilter};
+use crate::package_client::{FieldMapping, PackageClient, PackageClientError};
+use crate::stac_client::StacClient;
+
+/// A catalog of downloadable packages.
+#[async_trait]
+pub trait PackageSource: Send + Sync {
+    /// Stable identifier stored in [`Package::source`].
+    fn id(&self) -> &str;
+
+    /// Packages that intersect `bbox` and pass `filter`.
+    async fn query_by_extent(
+        &self,
+        bbox: &BoundingBox,
+        filter: &PackageFilter,
+    ) -> Result<Vec<Package>, PackageClientError>;
+
+    /// Every package in the catalog, with geometries in Web Mercator.
+    async fn query_all(&self) -> Result<Vec<Package>, PackageClientError>;
+}
+
+#[async_trait]
+impl PackageSource for PackageClient {
+    fn id(&self) -> &str {
+        self.source_id()
+    }
+
+    async fn query_by_extent(
+        &self,
+        bbox: &BoundingBox,
+        filter: &PackageFilter,
+    ) -> Result<Vec<Package>, PackageClientError> {
+        self.query_by_extent_filtered(bbox, filter).await
+    }
+
+    async fn query_all(&self) -> Result<Vec<Package>, PackageClientError> {
+        PackageClient::query_all(self).await
+    }
+}
+
+/// Errors that can occur while loading the source configuration.
+#[derive(Debug, Error)]
+pub enum SourceConfigError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Failed to parse source configuration: {0}")]
+    JsonParseError(#[from] serde_json::Error),
+
+    #[error("Duplicate source id: {0}")]
+    DuplicateId(String),
+}
+
+/// One entry of the sources configuration file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SourceConfig {
+    /// An ArcGIS FeatureServer layer, e.g. `.../FeatureServer/0`.
+    Arcgis {
+        id: String,
+        url: String,
+        #[serde(default)]
+        fields: FieldMapping,
+        #[serde(default = "default_enabled")]
+        enabled: bool,
+    },
+    /// A STAC API root, searched through `{url}/search`.
+    Stac {
+        id: String,
+        url: String,
+        #[serde(default)]
+        collections: Vec<String>,
+        /// Asset keys to download, in order of preference.
+        #[serde(default)]
+        asset_keys: Vec<String>,
+        #[serde(default)]
+        datetime: Option<String>,
+        #[serde(default = "default_enabled")]
+        enabled: bool,
+    },
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl SourceConfig {
+    pub fn id(&self) -> &str {
+        match self {
+            SourceConfig::Arcgis { id, .. } | SourceConfig::Stac { id, .. } => id,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        match self {
+            SourceConfig::Arcgis { enabled, .. } | SourceConfig::Stac { enabled, .. } => *enabled,
+        }
+    }
+
+    fn build(&self) -> Arc<dyn PackageSource> {
+        match self {
+            SourceConfig::Arcgis {
+                id, url, fields, ..
+            } => Arc::new(
+                PackageClient::with_base_url(url.trim_end_matches('/').to_string())
+                    .with_fields(id.clone(), fields.clone()),
+            ),
+            SourceConfig::Stac {
+                id,
+                url,
+                collections,
+                asset_keys,
+                datetime,
+                ..
+            } => Arc::new(
+                StacClient::new(id.clone(), url.clone())
+                    .with_collections(collections.clone())
+                    .with_asset_keys(asset_keys.clone())
+                    .with_datetime(datetime.clone()),
+            ),
+        }
+    }
+}
+
+/// The enabled package sources, queried together.
+#[derive(Clone)]
+pub struct SourceRegistry {
+    sources: Vec<Arc<dyn PackageSource>>,
+}
+
+impl SourceRegistry {
+    pub fn new(sources: Vec<Arc<dyn PackageSource>>) -> Self {
+        Self { sources }
+    }
+
+    /// The Ontario DTM Package Index plus the sources listed in
+    /// `DTM_SOURCES_CONFIG`, if set. An invalid file is reported and ignored.
+    pub fn from_env() -> Self {
+        let mut registry = Self::new(vec![Arc::new(PackageClient::new())]);
+
+        if let Ok(path) = std::env::var("DTM_SOURCES_CONFIG") {
+            let path = path.trim();
+            if !path.is_empty() {
+                if let Err(e) = registry.load_config(Path::new(path)) {
+                    eprintln!("Ignoring source configuration {}: {}", path, e);
+                }
+            }
+        }
+
+        registry
+    }
+
+    /// Add the enabled sources from a JSON array of [`SourceConfig`].
+    ///
+    /// An entry whose id matches an existing source replaces it, so the
+    /// built-in Ontario DTM source can be reconfigured or disabled.
+    pub fn load_config(&mut self, path: &Path) -> Result<(), SourceConfigError> {
+        let text = std::fs::read_to_string(path)?;
+        let configs: Vec<SourceConfig> = serde_json::from_str(&text)?;
+        self.apply_config(&configs)
+    }
+
+    fn apply_config(&mut self, configs: &[SourceConfig]) -> Result<(), SourceConfigError> {
+        for (i, config) in configs.iter().enumerate() {
+            if configs[..i].iter().any(|other| other.id() == config.id()) {
+                return Err(SourceConfigError::DuplicateId(config.id().to_string()));
+            }
+        }
+
+        for config in configs {
+            self.sources.retain(|source| source.id() != config.id());
+            if config.enabled() {
+                self.sources.push(config.build());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.sources.iter().map(|s| s.id().to_string()).collect()
+    }
+
+    /// Query every source concurrently and merge the results.
+    ///
+    /// A failing source is logged and skipped; the query only fails when
+    /// every source fails.
+    pub async fn query_by_extent(
+        &self,
+        bbox: &BoundingBox,
+        filter: &PackageFilter,
+    ) -> Result<Vec<Package>, PackageClientError> {
+        let results = join_all(
+            self.sources
+                .iter()
+                .map(|source| source.query_by_extent(bbox, filter)),
+        )
+        .await;
+        self.merge_results(results)
+    }
+
+    /// Every package from every source, with each source's own result.
+    ///
+    /// Unlike [`Self::query_by_extent`], failures are not merged away, so a
+    /// caller replacing a snapshot can keep what it had for a failed source.
+    pub async fn query_all(&self) -> Vec<(String, Result<Vec<Package>, PackageClientError>)> {
+        let results = join_all(self.sources.iter().map(|source| source.query_all())).await;
+        self.ids().into_iter().zip(results).collect()
+    }
+
+    fn merge_results(
+        &self,
+        results: Vec<Result<Vec<Package>, PackageClientError>>,
+    ) -> Result<Vec<Package>, PackageClientError> {
+        let mut packages = Vec::new();
+        let mut first_error = None;
+        let mut succeeded = false;
+
+        for (source, result) in self.sources.iter().zip(results) {
+            match result {
+                Ok(found) => {
+                    succeeded = true;
+                    packages.extend(found);
+                }
+                Err(e) => {
+                    eprintln!("Package source '{}' failed: {}", source.id(), e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        match first_error {
+            Some(e) if !succeeded => Err(e),
+            _ => Ok(packages),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::test_support::PackageBuilder;
+
+    /// A source answering with one package, or failing.
+    pub(crate) struct StaticSource {
+        pub id: &'static str,
+        pub fail: bool,
+    }
+
+    #[async_trait]
+    impl PackageSource for StaticSource {
+        fn id(&self) -> &str {
+            self.id
+        }
+
+        async fn query_by_extent(
+            &self,
+            _bbox: &BoundingBox,
+            _filter: &PackageFilter,
+        ) -> Result<Vec<Package>, PackageClientError> {
+            self.query_all().await
+        }
+
+        async fn query_all(&self) -> Result<Vec<Package>, PackageClientError> {
+            if self.fail {
+                return Err(PackageClientError::MissingField("Package".to_string()));
+            }
+            Ok(vec![PackageBuilder::new(&format!("{} package", self.id))
+                .resolution(1.0)
+                .download_url(&format!("https://example.com/{}.zip", self.id))
+                .source(self.id)
+                .build()])
+        }
+    }
+
+    pub(crate) fn static_registry(sources: &[(&'static str, bool)]) -> SourceRegistry {
+        SourceRegistry::new(
+            sources
+                .iter()
+                .map(|&(id, fail)| Arc::new(StaticSource { id, fail }) as Arc<dyn PackageSource>)
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_tags_packages_by_source() {
+        let registry = static_registry(&[("dtm", false), ("dsm", false)]);
+        let results = registry.query_all().await;
+        let sources: Vec<_> = results
+            .iter()
+            .flat_map(|(_, found)| found.as_ref().unwrap())
+            .map(|p| p.source.as_str())
+            .collect();
+        assert_eq!(sources, vec!["dtm", "dsm"]);
+    }
+
+    #[tokio::test]
+    async fn test_failing_source_is_skipped_unless_all_fail() {
+        let bbox = BoundingBox::new(0.0, 0.0, 1.0, 1.0, 3857);
+        let filter = PackageFilter::default();
+        let registry = static_registry(&[("dtm", true), ("dsm", false)]);
+        assert_eq!(
+            registry
+                .query_by_extent(&bbox, &filter)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let registry = static_registry(&[("dtm", true), ("dsm", true)]);
+        assert!(registry.query_by_extent(&bbox, &filter).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_query_all_reports_each_source() {
+        let registry = static_registry(&[("dtm", true), ("dsm", false)]);
+        let results = registry.query_all().await;
+        assert_eq!(results[0].0, "dtm");
+        assert!(results[0].1.is_err());
+        assert_eq!(results[1].0, "dsm");
+        assert_eq!(results[1].1.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_config_adds_replaces_and_disables_sources() {
+        let configs: Vec<SourceConfig> = serde_json::from_str(
+            r#"[
+                {
+                    "kind": "arcgis",
+                    "id": "ontario-dsm",
+                    "url": "https://example.com/FeatureServer/0/",
+                    "fields": {"package_name": "Tile", "download_link": "URL", "size_gb": null}
+                },
+                {"kind": "arcgis", "id": "ontario-dtm", "url": "https://example.com", "enabled": false}
+            ]"#,
+        )
+        .unwrap();
+        let mut registry = SourceRegistry::new(vec![Arc::new(PackageClient::new())]);
+        registry.apply_config(&configs).unwrap();
+        assert_eq!(registry.ids(), vec!["ontario-dsm".to_string()]);
+
+        let SourceConfig::Arcgis { fields, .. } = &configs[0] else {
+            panic!("expected an ArcGIS source");
+        };
+        assert_eq!(fields.package_name, "Tile");
+        assert_eq!(fields.size_gb, None);
+        assert_eq!(fields.project.as_deref(), Some("Project"));
+        assert_eq!(
+            fields.out_fields(),
+            "Tile,Resolution,URL,Project,Shape__Area"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_source_ids_are_rejected() {
+        let configs: Vec<SourceConfig> = serde_json::from_str(
+            r#"[
+                {"kind": "arcgis", "id": "a", "url": "https://example.com/1"},
+                {"kind": "arcgis", "id": "a", "url": "https://example.com/2"}
+            ]"#,
+        )
+        .unwrap();
+        let mut registry = SourceRegistry::new(Vec::new());
+        assert!(matches!(
+            registry.apply_config(&configs),
+            Err(SourceConfigError::DuplicateId(id)) if id == "a"
+        ));
+    }
+}
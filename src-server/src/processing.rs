@@ -1,9 +1,16 @@
-use crate::api_types::{ProcessingProgressEvent, ProgressEvent};
+use crate::api_types::{ClipExtentRequest, ProcessingProgressEvent, ProgressEvent};
+use crate::disk_space::InsufficientDiskSpace;
 use crate::download::ProgressSender;
+use crate::vertical_datum::{apply_vertical_shift, VerticalDatumTransform};
+use regex::Regex;
 use serde_json::Value;
 use std::io;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::Duration;
 use thiserror::Error;
+use tokio::io::AsyncReadExt;
 
 #[derive(Debug, Error)]
 pub enum ProcessingError {
@@ -15,6 +22,70 @@ pub enum ProcessingError {
     NoInputFiles,
     #[error("IO error: {0}")]
     IoError(#[from] io::Error),
+    #[error("Vertical datum grid unavailable: {0}")]
+    VerticalGridUnavailable(String),
+    #[error("Processing was cancelled")]
+    Cancelled,
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error(transparent)]
+    DiskSpace(#[from] InsufficientDiskSpace),
+    #[cfg(feature = "native-gdal")]
+    #[error("GDAL error: {0}")]
+    Native(#[from] gdal::errors::GdalError),
+}
+
+/// How GDAL operations are executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingBackend {
+    /// Spawn the GDAL command line tools (`gdalwarp`, `gdal_translate`, `gdalinfo`).
+    Cli,
+    /// Call GDAL in-process through its C API.
+    #[cfg(feature = "native-gdal")]
+    Native,
+}
+
+impl ProcessingBackend {
+    /// The backend selected by `DTM_PROCESSING_BACKEND` (`cli` or `native`).
+    ///
+    /// Defaults to the CLI; `native` only takes effect when it is compiled in.
+    pub fn current() -> Self {
+        static BACKEND: OnceLock<ProcessingBackend> = OnceLock::new();
+        *BACKEND.get_or_init(|| {
+            Self::from_name(
+                std::env::var("DTM_PROCESSING_BACKEND")
+                    .ok()
+                    .as_deref()
+                    .unwrap_or(""),
+            )
+        })
+    }
+
+    fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            #[cfg(feature = "native-gdal")]
+            "native" => ProcessingBackend::Native,
+            _ => ProcessingBackend::Cli,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessingBackend::Cli => "cli",
+            #[cfg(feature = "native-gdal")]
+            ProcessingBackend::Native => "native",
+        }
+    }
+
+    /// The backend to run a job on. Void filling and vertical datum shifts
+    /// use the GDAL Python utilities, so such jobs always run on the CLI.
+    pub fn for_job(self, void_fill: bool, vertical_shift: bool) -> Self {
+        if void_fill || vertical_shift {
+            ProcessingBackend::Cli
+        } else {
+            self
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -26,6 +97,7 @@ pub enum CompressionType {
 }
 
 impl CompressionType {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s.to_lowercase().as_str() {
             "zstd" => CompressionType::Zstd,
@@ -44,35 +116,136 @@ impl CompressionType {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ClipExtent {
     pub min_x: f64,
     pub min_y: f64,
     pub max_x: f64,
     pub max_y: f64,
+    /// EPSG code of the coordinates, passed to GDAL as `-te_srs`.
+    pub srid: u32,
+}
+
+impl From<&ClipExtentRequest> for ClipExtent {
+    fn from(extent: &ClipExtentRequest) -> Self {
+        Self {
+            min_x: extent.min_x,
+            min_y: extent.min_y,
+            max_x: extent.max_x,
+            max_y: extent.max_y,
+            srid: extent.srid,
+        }
+    }
+}
+
+/// Nodata value written to the output when neither the request nor the inputs define one.
+pub const DEFAULT_OUTPUT_NODATA: f64 = -9999.0;
+
+/// Settings for interpolating nodata voids after the merge.
+#[derive(Debug, Clone, Copy)]
+pub struct VoidFillOptions {
+    /// Maximum search distance in pixels for the inverse-distance interpolation.
+    pub max_distance: u32,
+    /// Number of 3x3 smoothing passes applied to the filled pixels.
+    pub smoothing_iterations: u32,
+}
+
+/// Options controlling how the input rasters are merged into the output COG.
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub clip_extent: Option<ClipExtent>,
+    pub compression: CompressionType,
+    /// Explicit output nodata value; detected from the inputs when `None`.
+    pub output_nodata: Option<f64>,
+    /// When set, nodata voids are filled and a mask band marks the filled pixels.
+    pub void_fill: Option<VoidFillOptions>,
+    /// Optional conversion of heights to another vertical datum.
+    pub vertical_datum: Option<VerticalDatumTransform>,
+    /// Set to abort processing; checked between stages and, with the native
+    /// backend, on every progress callback.
+    pub cancel: Arc<AtomicBool>,
+}
+
+impl MergeOptions {
+    pub fn new(clip_extent: Option<ClipExtent>, compression: CompressionType) -> Self {
+        Self {
+            clip_extent,
+            compression,
+            output_nodata: None,
+            void_fill: None,
+            vertical_datum: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// GDAL virtual file system prefix for files read over HTTP.
+const VSICURL_PREFIX: &str = "/vsicurl/";
+
+/// Whether remote COGs are read in place instead of being downloaded first.
+///
+/// Enabled unless `DTM_STREAM_COGS` is `0` or `false`.
+pub fn stream_remote_cogs() -> bool {
+    std::env::var("DTM_STREAM_COGS")
+        .map(|v| !matches!(v.trim().to_lowercase().as_str(), "0" | "false"))
+        .unwrap_or(true)
+}
+
+/// Input path for [`merge_to_cog`] that reads a remote COG with HTTP range
+/// requests, through the shared block cache in `block_cache_dir`.
+///
+/// gdalwarp then only fetches the tiles that intersect the clip extent.
+pub async fn remote_cog_input(
+    url: &str,
+    block_cache_dir: &std::path::Path,
+) -> Result<String, ProcessingError> {
+    let addr = crate::cog_cache::shared_proxy(block_cache_dir).await?;
+    Ok(format!(
+        "{}{}",
+        VSICURL_PREFIX,
+        crate::cog_cache::proxied_url(addr, url)
+    ))
 }
 
 pub async fn merge_to_cog(
     input_files: &[String],
     output_path: &str,
-    clip_extent: Option<ClipExtent>,
-    compression: CompressionType,
+    options: &MergeOptions,
     sender: &ProgressSender,
 ) -> Result<(), ProcessingError> {
     if input_files.is_empty() {
         return Err(ProcessingError::NoInputFiles);
     }
 
+    let backend = ProcessingBackend::current().for_job(
+        options.void_fill.is_some(),
+        options
+            .vertical_datum
+            .as_ref()
+            .is_some_and(|transform| transform.requires_shift()),
+    );
     sender.send(ProgressEvent::Processing(ProcessingProgressEvent {
         stage: "merging".to_string(),
         percentage: 0,
-        message: "Starting merge process...".to_string(),
+        message: format!("Starting merge process ({} backend)...", backend.name()),
     }));
 
-    let compress_opt = format!("COMPRESS={}", compression.to_gdal_string());
-    let predictor_opt = detect_predictor_option(input_files.first().map(|s| s.as_str()))
-        .map(|p| format!("PREDICTOR={}", p));
-    let temp_path = format!("{}.temp.tif", output_path.trim_end_matches(".tif"));
+    let compress_opt = format!("COMPRESS={}", options.compression.to_gdal_string());
+    let inputs = input_files.to_vec();
+    let (predictor, source_nodata) = run_blocking(move || {
+        Ok((
+            detect_predictor_option(inputs.first().map(|s| s.as_str())),
+            detect_source_nodata(&inputs),
+        ))
+    })
+    .await?;
+    let predictor_opt = predictor.map(|p| format!("PREDICTOR={}", p));
+    let output_nodata = options
+        .output_nodata
+        .or(source_nodata)
+        .unwrap_or(DEFAULT_OUTPUT_NODATA);
+    let output_stem = output_path.trim_end_matches(".tif");
+    let temp_path = format!("{}.temp.tif", output_stem);
 
     sender.send(ProgressEvent::Processing(ProcessingProgressEvent {
         stage: "merging".to_string(),
@@ -80,45 +253,109 @@ pub async fn merge_to_cog(
         message: "Merging and clipping rasters...".to_string(),
     }));
 
-    let mut warp_cmd = Command::new("gdalwarp");
-    warp_cmd
-        .arg("-of")
-        .arg("GTiff")
-        .arg("-co")
-        .arg(&compress_opt)
-        .arg("-co")
-        .arg("BIGTIFF=YES")
-        .arg("-co")
-        .arg("NUM_THREADS=ALL_CPUS")
-        .arg("-r")
-        .arg("near");
+    let mut warp_args = vec![
+        "-of".to_string(),
+        "GTiff".to_string(),
+        "-co".to_string(),
+        compress_opt.clone(),
+        "-co".to_string(),
+        "BIGTIFF=YES".to_string(),
+        "-co".to_string(),
+        "NUM_THREADS=ALL_CPUS".to_string(),
+        "-r".to_string(),
+        "near".to_string(),
+    ];
     if let Some(predictor) = &predictor_opt {
-        warp_cmd.arg("-co").arg(predictor);
+        warp_args.extend(["-co".to_string(), predictor.clone()]);
+    }
+    if let Some(nodata) = source_nodata {
+        warp_args.extend(["-srcnodata".to_string(), format_nodata(nodata)]);
     }
+    warp_args.extend(["-dstnodata".to_string(), format_nodata(output_nodata)]);
 
-    if let Some(extent) = clip_extent {
-        warp_cmd
-            .arg("-te")
-            .arg(extent.min_x.to_string())
-            .arg(extent.min_y.to_string())
-            .arg(extent.max_x.to_string())
-            .arg(extent.max_y.to_string())
-            .arg("-te_srs")
-            .arg("EPSG:3857");
+    if let Some(extent) = options.clip_extent {
+        warp_args.extend([
+            "-te".to_string(),
+            extent.min_x.to_string(),
+            extent.min_y.to_string(),
+            extent.max_x.to_string(),
+            extent.max_y.to_string(),
+            "-te_srs".to_string(),
+            format!("EPSG:{}", extent.srid),
+        ]);
     }
 
-    for file in input_files {
-        warp_cmd.arg(file);
+    run_warp(
+        backend,
+        input_files,
+        &temp_path,
+        warp_args,
+        options,
+        StageProgress::new(sender, "merging", "Merging and clipping rasters...", 10, 50),
+    )
+    .await?;
+
+    let mut intermediate_files = vec![temp_path.clone()];
+    let mut cog_source = temp_path.clone();
+    let mut output_metadata = Vec::new();
+
+    if let Some(transform) = &options.vertical_datum {
+        if transform.requires_shift() {
+            if let Err(e) = check_cancelled(options) {
+                remove_files(&intermediate_files);
+                return Err(e);
+            }
+            sender.send(ProgressEvent::Processing(ProcessingProgressEvent {
+                stage: "vertical_datum".to_string(),
+                percentage: 50,
+                message: format!("Converting heights {}...", transform.describe()),
+            }));
+
+            let shifted_path = format!("{}.vdatum.tif", output_stem);
+            intermediate_files.push(shifted_path.clone());
+            intermediate_files.push(format!("{}.vdatum_grid.tif", output_stem));
+            let (source, target, stem, transform) = (
+                cog_source.clone(),
+                shifted_path.clone(),
+                output_stem.to_string(),
+                transform.clone(),
+            );
+            let shifted = run_blocking(move || {
+                apply_vertical_shift(&source, &target, &stem, &transform, output_nodata)
+            })
+            .await;
+            if let Err(e) = shifted {
+                remove_files(&intermediate_files);
+                return Err(e);
+            }
+            cog_source = shifted_path;
+        }
+        output_metadata.extend(transform.metadata());
     }
-    warp_cmd.arg(&temp_path);
 
-    let warp_output = warp_cmd.output()?;
-    if !warp_output.status.success() {
-        let stderr = String::from_utf8_lossy(&warp_output.stderr);
-        return Err(ProcessingError::GdalError(format!(
-            "gdalwarp failed: {}",
-            stderr
-        )));
+    if let Some(fill) = options.void_fill {
+        if let Err(e) = check_cancelled(options) {
+            remove_files(&intermediate_files);
+            return Err(e);
+        }
+        sender.send(ProgressEvent::Processing(ProcessingProgressEvent {
+            stage: "filling_voids".to_string(),
+            percentage: 55,
+            message: "Filling nodata voids...".to_string(),
+        }));
+
+        let (source, stem) = (cog_source.clone(), output_stem.to_string());
+        let result = run_blocking(move || fill_voids(&source, &stem, output_nodata, fill)).await;
+        match result {
+            Ok(files) => {
+                cog_source = files.combined_path.clone();
+                intermediate_files.extend(files.into_paths());
+            }
+            Err(e) => {
+                remove_files(&intermediate_files);
+                return Err(e);
+            }
+        }
     }
 
     sender.send(ProgressEvent::Processing(ProcessingProgressEvent {
@@ -127,36 +364,52 @@ pub async fn merge_to_cog(
         message: "Creating Cloud Optimized GeoTIFF...".to_string(),
     }));
 
-    let translate_output = Command::new("gdal_translate")
-        .arg(&temp_path)
-        .arg(output_path)
-        .arg("-of")
-        .arg("COG")
-        .arg("-co")
-        .arg(&compress_opt)
-        .args(
-            predictor_opt
-                .as_ref()
-                .map(|p| vec!["-co", p.as_str()])
-                .unwrap_or_default(),
-        )
-        .arg("-co")
-        .arg("BIGTIFF=YES")
-        .arg("-co")
-        .arg("BLOCKSIZE=512")
-        .arg("-co")
-        .arg("NUM_THREADS=ALL_CPUS")
-        .output()?;
-
-    if !translate_output.status.success() {
-        let stderr = String::from_utf8_lossy(&translate_output.stderr);
-        return Err(ProcessingError::GdalError(format!(
-            "gdal_translate failed: {}",
-            stderr
-        )));
+    let mut translate_args = vec![
+        "-of".to_string(),
+        "COG".to_string(),
+        "-co".to_string(),
+        compress_opt.clone(),
+    ];
+    if let Some(predictor) = &predictor_opt {
+        translate_args.extend(["-co".to_string(), predictor.clone()]);
+    }
+    translate_args.extend([
+        "-co".to_string(),
+        "BIGTIFF=YES".to_string(),
+        "-co".to_string(),
+        "BLOCKSIZE=512".to_string(),
+        "-co".to_string(),
+        "NUM_THREADS=ALL_CPUS".to_string(),
+    ]);
+    if options.void_fill.is_some() {
+        // `-a_nodata` would apply to the Byte mask band too; the combined
+        // VRT already carries nodata on band 1 only.
+        output_metadata.push("FILL_MASK_BAND=2".to_string());
+    } else {
+        translate_args.extend(["-a_nodata".to_string(), format_nodata(output_nodata)]);
+    }
+    for item in output_metadata {
+        translate_args.extend(["-mo".to_string(), item]);
     }
 
-    let _ = std::fs::remove_file(&temp_path);
+    let translate_result = run_translate(
+        backend,
+        &cog_source,
+        output_path,
+        translate_args,
+        options,
+        StageProgress::new(
+            sender,
+            "creating_cog",
+            "Creating Cloud Optimized GeoTIFF...",
+            60,
+            99,
+        ),
+    )
+    .await;
+
+    remove_files(&intermediate_files);
+    translate_result?;
 
     sender.send(ProgressEvent::Processing(ProcessingProgressEvent {
         stage: "completed".to_string(),
@@ -167,16 +420,484 @@ pub async fn merge_to_cog(
     Ok(())
 }
 
+/// Run blocking GDAL work on the blocking thread pool.
+///
+/// Inputs may be `/vsicurl/` URLs served by the COG proxy on this same
+/// runtime, so reading them on an async worker could stall the proxy, and
+/// deadlock outright on a single-worker runtime.
+async fn run_blocking<T, F>(work: F) -> Result<T, ProcessingError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ProcessingError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| ProcessingError::GdalError(e.to_string()))?
+}
+
+/// Maps a stage's fractional completion onto the overall `start..=end` percentage range.
+#[derive(Clone)]
+struct StageProgress {
+    sender: ProgressSender,
+    stage: &'static str,
+    message: &'static str,
+    start: u8,
+    end: u8,
+}
+
+impl StageProgress {
+    fn new(
+        sender: &ProgressSender,
+        stage: &'static str,
+        message: &'static str,
+        start: u8,
+        end: u8,
+    ) -> Self {
+        Self {
+            sender: sender.clone(),
+            stage,
+            message,
+            start,
+            end,
+        }
+    }
+
+    fn percentage(&self, complete: f64) -> u8 {
+        let span = f64::from(self.end - self.start);
+        self.start + (complete.clamp(0.0, 1.0) * span).round() as u8
+    }
+
+    /// Returns a callback that emits an event whenever the overall percentage changes.
+    fn reporter(self) -> impl FnMut(f64) {
+        let mut last = self.start;
+        move |complete| {
+            let percentage = self.percentage(complete);
+            if percentage != last {
+                last = percentage;
+                self.sender
+                    .send(ProgressEvent::Processing(ProcessingProgressEvent {
+                        stage: self.stage.to_string(),
+                        percentage,
+                        message: self.message.to_string(),
+                    }));
+            }
+        }
+    }
+}
+
+fn check_cancelled(options: &MergeOptions) -> Result<(), ProcessingError> {
+    if options.cancel.load(Ordering::Relaxed) {
+        return Err(ProcessingError::Cancelled);
+    }
+    Ok(())
+}
+
+async fn run_warp(
+    backend: ProcessingBackend,
+    input_files: &[String],
+    output_path: &str,
+    args: Vec<String>,
+    options: &MergeOptions,
+    progress: StageProgress,
+) -> Result<(), ProcessingError> {
+    check_cancelled(options)?;
+    match backend {
+        ProcessingBackend::Cli => {
+            let mut command_args = args;
+            command_args.extend(input_files.iter().cloned());
+            command_args.push(output_path.to_string());
+            run_gdal_command("gdalwarp", &command_args, &options.cancel, progress).await
+        }
+        #[cfg(feature = "native-gdal")]
+        ProcessingBackend::Native => {
+            let input_files = input_files.to_vec();
+            let output_path = output_path.to_string();
+            let cancel = options.cancel.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut report = progress.reporter();
+                crate::gdal_native::warp(&input_files, &output_path, &args, &cancel, &mut report)
+            })
+            .await
+            .map_err(|e| ProcessingError::GdalError(e.to_string()))?
+        }
+    }
+}
+
+async fn run_translate(
+    backend: ProcessingBackend,
+    input_path: &str,
+    output_path: &str,
+    args: Vec<String>,
+    options: &MergeOptions,
+    progress: StageProgress,
+) -> Result<(), ProcessingError> {
+    check_cancelled(options)?;
+    match backend {
+        ProcessingBackend::Cli => {
+            let mut command_args = vec![input_path.to_string(), output_path.to_string()];
+            command_args.extend(args);
+            run_gdal_command("gdal_translate", &command_args, &options.cancel, progress).await
+        }
+        #[cfg(feature = "native-gdal")]
+        ProcessingBackend::Native => {
+            let input_path = input_path.to_string();
+            let output_path = output_path.to_string();
+            let cancel = options.cancel.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut report = progress.reporter();
+                crate::gdal_native::translate(
+                    &input_path,
+                    &output_path,
+                    &args,
+                    &cancel,
+                    &mut report,
+                )
+            })
+            .await
+            .map_err(|e| ProcessingError::GdalError(e.to_string()))?
+        }
+    }
+}
+
+/// Run a GDAL command line tool, streaming its `0...10...20` progress output
+/// from stdout into `progress`. The child is killed when `cancel` is set.
+async fn run_gdal_command(
+    program: &str,
+    args: &[String],
+    cancel: &AtomicBool,
+    progress: StageProgress,
+) -> Result<(), ProcessingError> {
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stderr = child.stderr.take();
+    let stderr_task = tokio::spawn(async move {
+        let mut text = String::new();
+        if let Some(stderr) = stderr.as_mut() {
+            let _ = stderr.read_to_string(&mut text).await;
+        }
+        text
+    });
+
+    let mut report = progress.reporter();
+    let mut parser = GdalProgressParser::default();
+    if let Some(mut stdout) = child.stdout.take() {
+        let mut buf = [0u8; 1024];
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                let _ = child.kill().await;
+                return Err(ProcessingError::Cancelled);
+            }
+            match tokio::time::timeout(Duration::from_millis(500), stdout.read(&mut buf)).await {
+                Ok(Ok(0)) => break,
+                Ok(Ok(n)) => {
+                    if let Some(complete) = parser.feed(&String::from_utf8_lossy(&buf[..n])) {
+                        report(complete);
+                    }
+                }
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => continue,
+            }
+        }
+    }
+
+    let status = child.wait().await?;
+    let stderr = stderr_task.await.unwrap_or_default();
+    if !status.success() {
+        return Err(ProcessingError::GdalError(format!(
+            "{} failed: {}",
+            program, stderr
+        )));
+    }
+    Ok(())
+}
+
+static INPUT_FILE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[(\d+)/(\d+)\]$").unwrap());
+
+/// Incremental parser for the progress GDAL tools print to stdout, e.g.
+/// `0...10...20...30...40...50...60...70...80...90...100 - done.`
+///
+/// Ticks must arrive in order (0, 10, ..., 100) so numbers inside messages
+/// interleaved with the progress are ignored. Each dot after a tick is 2.5%.
+/// When gdalwarp processes several inputs it prefixes each with
+/// `Processing <file> [i/n] : ` and restarts progress; the parser folds that
+/// into one overall fraction.
+#[derive(Debug, Default)]
+struct GdalProgressParser {
+    digits: String,
+    recent: String,
+    next_tick: u32,
+    current_tick: Option<u32>,
+    dots: u32,
+    after_tick: bool,
+    file_index: u32,
+    file_count: u32,
+    last_reported: Option<f64>,
+}
+
+impl GdalProgressParser {
+    /// Feed a chunk of stdout; returns the overall completion (0.0 - 1.0) when it advanced.
+    fn feed(&mut self, chunk: &str) -> Option<f64> {
+        for c in chunk.chars() {
+            self.push_recent(c);
+            if c.is_ascii_digit() {
+                self.digits.push(c);
+                continue;
+            }
+
+            if !self.digits.is_empty() {
+                let number = self.digits.parse::<u32>().ok();
+                self.digits.clear();
+                self.after_tick = false;
+                // The first tick may be followed directly by a message ("0Using ...").
+                let terminated = c == '.' || c == ' ' || number == Some(0);
+                if terminated && number == Some(self.next_tick) && self.next_tick <= 100 {
+                    self.current_tick = number;
+                    self.next_tick += 10;
+                    self.dots = 0;
+                    self.after_tick = true;
+                }
+            }
+
+            match c {
+                '.' if self.after_tick => self.dots += 1,
+                ']' => self.check_input_marker(),
+                '.' => {}
+                _ => self.after_tick = false,
+            }
+        }
+
+        let overall = self.overall()?;
+        if self.last_reported.is_some_and(|last| overall <= last) {
+            return None;
+        }
+        self.last_reported = Some(overall);
+        Some(overall)
+    }
+
+    fn push_recent(&mut self, c: char) {
+        const MAX_RECENT: usize = 32;
+        self.recent.push(c);
+        if self.recent.len() > MAX_RECENT {
+            let cut = self.recent.len() - MAX_RECENT;
+            let cut = (cut..self.recent.len())
+                .find(|i| self.recent.is_char_boundary(*i))
+                .unwrap_or(cut);
+            self.recent.drain(..cut);
+        }
+    }
+
+    fn check_input_marker(&mut self) {
+        let Some(caps) = INPUT_FILE_RE.captures(&self.recent) else {
+            return;
+        };
+        let (Ok(index), Ok(count)) = (caps[1].parse::<u32>(), caps[2].parse::<u32>()) else {
+            return;
+        };
+        self.file_index = index.saturating_sub(1);
+        self.file_count = count;
+        self.next_tick = 0;
+        self.current_tick = None;
+        self.dots = 0;
+    }
+
+    fn overall(&self) -> Option<f64> {
+        let tick = self.current_tick?;
+        let percent = if tick >= 100 {
+            100.0
+        } else {
+            f64::from(tick) + f64::from(self.dots.min(3)) * 2.5
+        };
+        let count = f64::from(self.file_count.max(1));
+        Some((f64::from(self.file_index) + percent / 100.0) / count)
+    }
+}
+
+/// Intermediate rasters produced by the void-fill pass.
+struct FilledRaster {
+    filled_path: String,
+    mask_path: String,
+    combined_path: String,
+}
+
+impl FilledRaster {
+    fn into_paths(self) -> Vec<String> {
+        vec![self.filled_path, self.mask_path, self.combined_path]
+    }
+}
+
+/// Interpolates nodata voids in `input_path` and stacks the result with a
+/// byte mask band where 1 marks pixels that were filled.
+fn fill_voids(
+    input_path: &str,
+    output_stem: &str,
+    nodata: f64,
+    options: VoidFillOptions,
+) -> Result<FilledRaster, ProcessingError> {
+    let files = FilledRaster {
+        filled_path: format!("{}.filled.tif", output_stem),
+        mask_path: format!("{}.fillmask.tif", output_stem),
+        combined_path: format!("{}.combined.vrt", output_stem),
+    };
+
+    let fill_output = Command::new("gdal_fillnodata.py")
+        .arg("-md")
+        .arg(options.max_distance.to_string())
+        .arg("-si")
+        .arg(options.smoothing_iterations.to_string())
+        .arg("-b")
+        .arg("1")
+        .arg("-of")
+        .arg("GTiff")
+        .arg(input_path)
+        .arg(&files.filled_path)
+        .output()?;
+    if !fill_output.status.success() {
+        let stderr = String::from_utf8_lossy(&fill_output.stderr);
+        return Err(ProcessingError::GdalError(format!(
+            "gdal_fillnodata failed: {}",
+            stderr
+        )));
+    }
+
+    let mask_output = Command::new("gdal_calc.py")
+        .arg("-A")
+        .arg(input_path)
+        .arg("-B")
+        .arg(&files.filled_path)
+        .arg(format!("--calc={}", filled_mask_expression(nodata)))
+        .arg("--type=Byte")
+        .arg("--hideNoData")
+        .arg("--NoDataValue=none")
+        .arg("--overwrite")
+        .arg(format!("--outfile={}", files.mask_path))
+        .output()?;
+    if !mask_output.status.success() {
+        let stderr = String::from_utf8_lossy(&mask_output.stderr);
+        return Err(ProcessingError::GdalError(format!(
+            "gdal_calc failed: {}",
+            stderr
+        )));
+    }
+
+    let vrt_output = Command::new("gdalbuildvrt")
+        .arg("-separate")
+        .arg("-vrtnodata")
+        .arg(combined_nodata(nodata))
+        .arg(&files.combined_path)
+        .arg(&files.filled_path)
+        .arg(&files.mask_path)
+        .output()?;
+    if !vrt_output.status.success() {
+        let stderr = String::from_utf8_lossy(&vrt_output.stderr);
+        return Err(ProcessingError::GdalError(format!(
+            "gdalbuildvrt failed: {}",
+            stderr
+        )));
+    }
+
+    Ok(files)
+}
+
+/// Per-band `-vrtnodata` for the filled raster and its mask: the mask's
+/// 0 ("not filled") must stay a valid value, so only band 1 gets nodata.
+fn combined_nodata(nodata: f64) -> String {
+    format!("{} None", format_nodata(nodata))
+}
+
+/// Builds the `gdal_calc.py` expression that flags pixels which were nodata
+/// in the merged raster (`A`) but hold a value after filling (`B`).
+fn filled_mask_expression(nodata: f64) -> String {
+    if nodata.is_nan() {
+        "isnan(A)*logical_not(isnan(B))".to_string()
+    } else {
+        let value = format_nodata(nodata);
+        format!("(A=={value})*(B!={value})")
+    }
+}
+
+pub(crate) fn remove_files(paths: &[String]) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Formats a nodata value the way the GDAL command line tools expect it.
+pub(crate) fn format_nodata(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Detects a nodata value shared by the inputs.
+///
+/// One file is sampled per source directory, since each package extracts to
+/// its own directory; remote COGs are one package each and are all sampled.
+/// Returns `None` when no input defines nodata or when the
+/// packages disagree, in which case gdalwarp falls back to each dataset's own
+/// nodata value.
+fn detect_source_nodata(input_files: &[String]) -> Option<f64> {
+    let mut sampled_dirs = Vec::new();
+    let mut detected: Option<f64> = None;
+
+    for file in input_files {
+        let dir = if file.starts_with(VSICURL_PREFIX) {
+            Some(std::path::PathBuf::from(file))
+        } else {
+            std::path::Path::new(file).parent().map(|p| p.to_path_buf())
+        };
+        if sampled_dirs.contains(&dir) {
+            continue;
+        }
+        sampled_dirs.push(dir);
+
+        let nodata = inspect_raster(file).ok()?.nodata?;
+        match detected {
+            Some(existing) if !nodata_equals(existing, nodata) => return None,
+            _ => detected = Some(nodata),
+        }
+    }
+
+    detected
+}
+
+pub(crate) fn nodata_equals(a: f64, b: f64) -> bool {
+    (a.is_nan() && b.is_nan()) || a == b
+}
+
 fn detect_predictor_option(input_file: Option<&str>) -> Option<u8> {
     let input_file = input_file?;
-    let data_type = detect_raster_data_type(input_file).ok()?;
-    if is_float_raster_type(&data_type) {
+    let info = inspect_raster(input_file).ok()?;
+    if is_float_raster_type(&info.data_type) {
         return Some(3);
     }
     None
 }
 
-fn detect_raster_data_type(path: &str) -> Result<String, ProcessingError> {
+/// Raster properties read from `gdalinfo -json`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RasterInfo {
+    pub data_type: String,
+    pub nodata: Option<f64>,
+    /// Raster size as `[columns, rows]`.
+    pub size: Option<[u64; 2]>,
+    /// Extent as `[min_x, min_y, max_x, max_y]` in the raster's own CRS.
+    pub extent: Option<[f64; 4]>,
+    pub srs_wkt: Option<String>,
+}
+
+pub(crate) fn inspect_raster(path: &str) -> Result<RasterInfo, ProcessingError> {
+    #[cfg(feature = "native-gdal")]
+    if ProcessingBackend::current() == ProcessingBackend::Native {
+        return crate::gdal_native::raster_info(path);
+    }
+
     let output = Command::new("gdalinfo").arg("-json").arg(path).output()?;
 
     if !output.status.success() {
@@ -188,20 +909,59 @@ fn detect_raster_data_type(path: &str) -> Result<String, ProcessingError> {
     }
 
     let json_text = String::from_utf8_lossy(&output.stdout);
-    parse_band_data_type(&json_text).ok_or_else(|| {
+    parse_raster_info(&json_text).ok_or_else(|| {
         ProcessingError::GdalError("gdalinfo output missing band data type".to_string())
     })
 }
 
-fn parse_band_data_type(gdalinfo_json: &str) -> Option<String> {
+fn parse_raster_info(gdalinfo_json: &str) -> Option<RasterInfo> {
     let value: Value = serde_json::from_str(gdalinfo_json).ok()?;
-    value
-        .get("bands")?
-        .as_array()?
-        .first()?
-        .get("type")?
-        .as_str()
-        .map(|s| s.to_string())
+    let band = value.get("bands")?.as_array()?.first()?;
+    let data_type = band.get("type")?.as_str()?.to_string();
+    let nodata = band.get("noDataValue").and_then(parse_nodata_value);
+    let size = value.get("size").and_then(|size| {
+        let size = size.as_array()?;
+        Some([size.first()?.as_u64()?, size.get(1)?.as_u64()?])
+    });
+    let extent = value
+        .get("cornerCoordinates")
+        .and_then(parse_corner_coordinates);
+    let srs_wkt = value
+        .get("coordinateSystem")
+        .and_then(|crs| crs.get("wkt"))
+        .and_then(|wkt| wkt.as_str())
+        .filter(|wkt| !wkt.is_empty())
+        .map(|wkt| wkt.to_string());
+    Some(RasterInfo {
+        data_type,
+        nodata,
+        size,
+        extent,
+        srs_wkt,
+    })
+}
+
+fn parse_corner_coordinates(corners: &Value) -> Option<[f64; 4]> {
+    let point = |name: &str| -> Option<(f64, f64)> {
+        let coords = corners.get(name)?.as_array()?;
+        Some((coords.first()?.as_f64()?, coords.get(1)?.as_f64()?))
+    };
+    let (min_x, min_y) = point("lowerLeft")?;
+    let (max_x, max_y) = point("upperRight")?;
+    Some([min_x, min_y, max_x, max_y])
+}
+
+/// gdalinfo reports finite nodata values as numbers and NaN/infinity as strings.
+fn parse_nodata_value(value: &Value) -> Option<f64> {
+    if let Some(number) = value.as_f64() {
+        return Some(number);
+    }
+    match value.as_str()?.to_lowercase().as_str() {
+        "nan" => Some(f64::NAN),
+        "inf" | "infinity" => Some(f64::INFINITY),
+        "-inf" | "-infinity" => Some(f64::NEG_INFINITY),
+        other => other.parse().ok(),
+    }
 }
 
 fn is_float_raster_type(data_type: &str) -> bool {
@@ -232,13 +992,159 @@ mod tests {
     #[test]
     fn test_parse_band_data_type() {
         let json = r#"{"bands":[{"band":1,"type":"Float32"}]}"#;
-        assert_eq!(parse_band_data_type(json), Some("Float32".to_string()));
+        let info = parse_raster_info(json).unwrap();
+        assert_eq!(info.data_type, "Float32");
+        assert_eq!(info.nodata, None);
     }
 
     #[test]
     fn test_parse_band_data_type_missing() {
         let json = r#"{"bands":[]}"#;
-        assert_eq!(parse_band_data_type(json), None);
+        assert_eq!(parse_raster_info(json), None);
+    }
+
+    #[test]
+    fn test_parse_raster_info_nodata() {
+        let json = r#"{"bands":[{"band":1,"type":"Float32","noDataValue":-9999.0}]}"#;
+        assert_eq!(parse_raster_info(json).unwrap().nodata, Some(-9999.0));
+
+        let json = r#"{"bands":[{"band":1,"type":"Float32","noDataValue":"nan"}]}"#;
+        assert!(parse_raster_info(json).unwrap().nodata.unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_parse_raster_info_georeferencing() {
+        let json = r#"{
+            "size": [2000, 1000],
+            "coordinateSystem": {"wkt": "PROJCRS[\"NAD83(CSRS) / UTM zone 17N\"]"},
+            "cornerCoordinates": {
+                "upperLeft": [500000.0, 4801000.0],
+                "lowerLeft": [500000.0, 4800000.0],
+                "lowerRight": [502000.0, 4800000.0],
+                "upperRight": [502000.0, 4801000.0]
+            },
+            "bands": [{"band": 1, "type": "Float32"}]
+        }"#;
+        let info = parse_raster_info(json).unwrap();
+        assert_eq!(info.size, Some([2000, 1000]));
+        assert_eq!(
+            info.extent,
+            Some([500000.0, 4800000.0, 502000.0, 4801000.0])
+        );
+        assert!(info.srs_wkt.unwrap().contains("UTM zone 17N"));
+    }
+
+    #[test]
+    fn test_combined_nodata_leaves_mask_band_without_nodata() {
+        assert_eq!(combined_nodata(-9999.0), "-9999 None");
+        assert_eq!(combined_nodata(f64::NAN), "nan None");
+    }
+
+    #[test]
+    fn test_filled_mask_expression() {
+        assert_eq!(filled_mask_expression(-9999.0), "(A==-9999)*(B!=-9999)");
+        assert_eq!(
+            filled_mask_expression(f64::NAN),
+            "isnan(A)*logical_not(isnan(B))"
+        );
+    }
+
+    #[test]
+    fn test_nodata_equals_handles_nan() {
+        assert!(nodata_equals(f64::NAN, f64::NAN));
+        assert!(nodata_equals(-9999.0, -9999.0));
+        assert!(!nodata_equals(-9999.0, 0.0));
+    }
+
+    #[test]
+    fn test_backend_from_name() {
+        assert_eq!(ProcessingBackend::from_name("cli"), ProcessingBackend::Cli);
+        assert_eq!(
+            ProcessingBackend::from_name(" CLI "),
+            ProcessingBackend::Cli
+        );
+        assert_eq!(ProcessingBackend::from_name(""), ProcessingBackend::Cli);
+        assert_eq!(ProcessingBackend::from_name("gpu"), ProcessingBackend::Cli);
+        #[cfg(not(feature = "native-gdal"))]
+        assert_eq!(
+            ProcessingBackend::from_name("native"),
+            ProcessingBackend::Cli
+        );
+        #[cfg(feature = "native-gdal")]
+        assert_eq!(
+            ProcessingBackend::from_name(" Native "),
+            ProcessingBackend::Native
+        );
+    }
+
+    #[test]
+    fn test_backend_for_job_falls_back_to_cli() {
+        let cli = ProcessingBackend::Cli;
+        assert_eq!(cli.for_job(true, true), ProcessingBackend::Cli);
+        #[cfg(feature = "native-gdal")]
+        {
+            let native = ProcessingBackend::Native;
+            assert_eq!(native.for_job(false, false), ProcessingBackend::Native);
+            assert_eq!(native.for_job(true, false), ProcessingBackend::Cli);
+            assert_eq!(native.for_job(false, true), ProcessingBackend::Cli);
+        }
+    }
+
+    #[test]
+    fn test_stage_progress_maps_into_range() {
+        let (tx, _) = tokio::sync::broadcast::channel(4);
+        let progress = StageProgress::new(&ProgressSender::new(tx), "merging", "", 10, 30);
+        assert_eq!(progress.percentage(0.0), 10);
+        assert_eq!(progress.percentage(0.5), 20);
+        assert_eq!(progress.percentage(1.0), 30);
+        assert_eq!(progress.percentage(2.0), 30);
+    }
+
+    #[test]
+    fn test_progress_parser_single_stream() {
+        let mut parser = GdalProgressParser::default();
+        assert_eq!(parser.feed("Input file size is 50, 50\n"), None);
+        assert_eq!(parser.feed("0"), None);
+        assert_eq!(parser.feed("..."), Some(0.075));
+        assert_eq!(parser.feed("10...20."), Some(0.225));
+        assert_eq!(parser.feed(".."), Some(0.275));
+        assert_eq!(
+            parser.feed("30...40...50...60...70...80...90...100 - done.\n"),
+            Some(1.0)
+        );
+        assert_eq!(parser.feed(""), None);
+    }
+
+    #[test]
+    fn test_progress_parser_ignores_numbers_in_messages() {
+        let mut parser = GdalProgressParser::default();
+        assert_eq!(
+            parser.feed("Creating output file that is 2000P x 1500L.\n"),
+            None
+        );
+        assert_eq!(
+            parser.feed("0Using internal nodata values (e.g. -3.40282e+38) for image a.tif.\n"),
+            Some(0.0)
+        );
+        assert_eq!(
+            parser
+                .feed("Copying nodata values from source a.tif to destination b.tif.\n...10...20"),
+            Some(0.175)
+        );
+    }
+
+    #[test]
+    fn test_progress_parser_multiple_inputs() {
+        let mut parser = GdalProgressParser::default();
+        assert_eq!(
+            parser.feed("Processing /data/tile_50.tif [1/2] : 0...10...20...30...40...50..."),
+            Some(0.2875)
+        );
+        assert_eq!(parser.feed("60...70...80...90...100 - done.\n"), Some(0.5));
+        assert_eq!(
+            parser.feed("Processing /data/tile_2016.tif [2/2] : 0...10...20...30...40...50..."),
+            Some(0.7875)
+        );
     }
 
     #[test]
@@ -0,0 +1,477 @@
+//! Coordinate reprojection between the coordinate systems used in Ontario.
+//!
+//! Supported are WGS84 longitude/latitude, Web Mercator, and UTM zones 15N-18N
+//! and MTM zones 8-17 on NAD83(CSRS). NAD83(CSRS) is treated as identical to
+//! WGS84; the two differ by less than two metres in Ontario, which is well
+//! below the pixel size of the packages.
+
+use thiserror::Error;
+
+use crate::api_types::{BoundingBox, GeoJSONGeometry};
+use crate::processing::ClipExtent;
+
+/// WGS84 semi-major axis, the sphere radius used by Web Mercator (EPSG:3857).
+const EARTH_RADIUS_M: f64 = 6_378_137.0;
+
+/// Latitude limit of the Web Mercator projection.
+const MAX_MERCATOR_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// GRS80 ellipsoid of NAD83(CSRS).
+const GRS80_A: f64 = 6_378_137.0;
+const GRS80_F: f64 = 1.0 / 298.257_222_101;
+
+/// Points added along each edge when reprojecting a bounding box.
+const DENSIFY_POINTS: usize = 21;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ProjectionError {
+    #[error("Unsupported spatial reference: EPSG:{0}")]
+    UnsupportedCrs(u32),
+}
+
+/// A supported coordinate reference system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crs {
+    /// WGS84 longitude/latitude in degrees (EPSG:4326, axis order lon/lat).
+    Wgs84,
+    /// Web Mercator (EPSG:3857).
+    WebMercator,
+    /// NAD83(CSRS) / UTM zone `n`N, for zones 15-18.
+    Utm(u8),
+    /// NAD83(CSRS) / MTM zone `n`, for the Ontario zones 8-17.
+    Mtm(u8),
+}
+
+impl Crs {
+    pub fn from_epsg(code: u32) -> Result<Self, ProjectionError> {
+        let crs = match code {
+            4326 | 4617 => Crs::Wgs84,
+            3857 | 102100 => Crs::WebMercator,
+            3159 => Crs::Utm(15),
+            3160 => Crs::Utm(16),
+            2958 => Crs::Utm(17),
+            2959 => Crs::Utm(18),
+            2950..=2952 => Crs::Mtm((code - 2950 + 8) as u8),
+            26891..=26897 => Crs::Mtm((code - 26891 + 11) as u8),
+            _ => return Err(ProjectionError::UnsupportedCrs(code)),
+        };
+        Ok(crs)
+    }
+
+    pub fn epsg(&self) -> u32 {
+        match self {
+            Crs::Wgs84 => 4326,
+            Crs::WebMercator => 3857,
+            Crs::Utm(15) => 3159,
+            Crs::Utm(16) => 3160,
+            Crs::Utm(zone) => 2958 + u32::from(*zone) - 17,
+            Crs::Mtm(zone @ 8..=10) => 2950 + u32::from(*zone) - 8,
+            Crs::Mtm(zone) => 26891 + u32::from(*zone) - 11,
+        }
+    }
+
+    /// Transverse Mercator parameters: central meridian, scale factor and false easting.
+    fn transverse_mercator(&self) -> Option<TransverseMercator> {
+        match *self {
+            Crs::Utm(zone) => Some(TransverseMercator {
+                central_meridian: -183.0 + 6.0 * f64::from(zone),
+                scale: 0.9996,
+                false_easting: 500_000.0,
+            }),
+            Crs::Mtm(zone) => Some(TransverseMercator {
+                central_meridian: mtm_central_meridian(zone),
+                scale: 0.9999,
+                false_easting: 304_800.0,
+            }),
+            Crs::Wgs84 | Crs::WebMercator => None,
+        }
+    }
+
+    /// Convert coordinates in this system to `[lon, lat]` degrees.
+    pub fn to_lon_lat(&self, x: f64, y: f64) -> [f64; 2] {
+        match self {
+            Crs::Wgs84 => [x, y],
+            Crs::WebMercator => mercator_to_lon_lat(x, y),
+            _ => self.transverse_mercator().unwrap().inverse(x, y),
+        }
+    }
+
+    /// Convert `[lon, lat]` degrees to coordinates in this system.
+    pub fn from_lon_lat(&self, lon: f64, lat: f64) -> [f64; 2] {
+        match self {
+            Crs::Wgs84 => [lon, lat],
+            Crs::WebMercator => lon_lat_to_mercator(lon, lat),
+            _ => self.transverse_mercator().unwrap().forward(lon, lat),
+        }
+    }
+}
+
+/// Central meridian of an Ontario MTM zone. Zones 8-11 run east to west in
+/// 3 degree steps; zone 12 lies between 11 and 13.
+fn mtm_central_meridian(zone: u8) -> f64 {
+    match zone {
+        8..=11 => -73.5 - 3.0 * f64::from(zone - 8),
+        12 => -81.0,
+        _ => -84.0 - 3.0 * f64::from(zone.saturating_sub(13)),
+    }
+}
+
+/// Transform one point between coordinate systems.
+pub fn transform_point(from: Crs, to: Crs, x: f64, y: f64) -> [f64; 2] {
+    if from == to {
+        return [x, y];
+    }
+    let [lon, lat] = from.to_lon_lat(x, y);
+    to.from_lon_lat(lon, lat)
+}
+
+/// Transform a bounding box to `to`, returning the envelope of its edges.
+///
+/// Edges are densified before reprojection, since straight edges become
+/// curves in the target system and the corners alone underestimate the extent.
+pub fn transform_bbox(bbox: &BoundingBox, to: Crs) -> Result<BoundingBox, ProjectionError> {
+    let from = Crs::from_epsg(bbox.srid)?;
+    let [xmin, ymin, xmax, ymax] =
+        transform_envelope(from, to, [bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax]);
+    Ok(BoundingBox::new(xmin, ymin, xmax, ymax, to.epsg()))
+}
+
+/// Transform a clip extent to `to`, see [`transform_bbox`].
+pub fn transform_clip_extent(extent: &ClipExtent, to: Crs) -> Result<ClipExtent, ProjectionError> {
+    let from = Crs::from_epsg(extent.srid)?;
+    let [min_x, min_y, max_x, max_y] = transform_envelope(
+        from,
+        to,
+        [extent.min_x, extent.min_y, extent.max_x, extent.max_y],
+    );
+    Ok(ClipExtent {
+        min_x,
+        min_y,
+        max_x,
+        max_y,
+        srid: to.epsg(),
+    })
+}
+
+fn transform_envelope(from: Crs, to: Crs, [xmin, ymin, xmax, ymax]: [f64; 4]) -> [f64; 4] {
+    if from == to {
+        return [xmin, ymin, xmax, ymax];
+    }
+    let steps = DENSIFY_POINTS + 1;
+    let mut envelope = [
+        f64::INFINITY,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NEG_INFINITY,
+    ];
+    for i in 0..=steps {
+        let t = i as f64 / steps as f64;
+        let x = xmin + (xmax - xmin) * t;
+        let y = ymin + (ymax - ymin) * t;
+        for [px, py] in [[x, ymin], [x, ymax], [xmin, y], [xmax, y]] {
+            let [tx, ty] = transform_point(from, to, px, py);
+            envelope = [
+                envelope[0].min(tx),
+                envelope[1].min(ty),
+                envelope[2].max(tx),
+                envelope[3].max(ty),
+            ];
+        }
+    }
+    envelope
+}
+
+/// Transform every position of a GeoJSON geometry between coordinate systems.
+pub fn transform_geometry(geometry: &GeoJSONGeometry, from: Crs, to: Crs) -> GeoJSONGeometry {
+    map_positions(geometry, |x, y| transform_point(from, to, x, y))
+}
+
+/// Transverse Mercator on the GRS80 ellipsoid, using the Krüger series to
+/// fourth order in the third flattening (sub-millimetre within a zone).
+struct TransverseMercator {
+    central_meridian: f64,
+    scale: f64,
+    false_easting: f64,
+}
+
+struct KruegerSeries {
+    rectifying_radius: f64,
+    alpha: [f64; 4],
+    beta: [f64; 4],
+    delta: [f64; 4],
+    /// Eccentricity, used in the conformal latitude.
+    e: f64,
+}
+
+fn krueger_series() -> KruegerSeries {
+    let n = GRS80_F / (2.0 - GRS80_F);
+    let (n2, n3, n4) = (n * n, n * n * n, n * n * n * n);
+    KruegerSeries {
+        rectifying_radius: GRS80_A / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0),
+        alpha: [
+            n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0 + 41.0 * n4 / 180.0,
+            13.0 * n2 / 48.0 - 3.0 * n3 / 5.0 + 557.0 * n4 / 1440.0,
+            61.0 * n3 / 240.0 - 103.0 * n4 / 140.0,
+            49561.0 * n4 / 161_280.0,
+        ],
+        beta: [
+            n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0 - n4 / 360.0,
+            n2 / 48.0 + n3 / 15.0 - 437.0 * n4 / 1440.0,
+            17.0 * n3 / 480.0 - 37.0 * n4 / 840.0,
+            4397.0 * n4 / 161_280.0,
+        ],
+        delta: [
+            2.0 * n - 2.0 * n2 / 3.0 - 2.0 * n3 + 116.0 * n4 / 45.0,
+            7.0 * n2 / 3.0 - 8.0 * n3 / 5.0 - 227.0 * n4 / 45.0,
+            56.0 * n3 / 15.0 - 136.0 * n4 / 35.0,
+            4279.0 * n4 / 630.0,
+        ],
+        e: (GRS80_F * (2.0 - GRS80_F)).sqrt(),
+    }
+}
+
+impl TransverseMercator {
+    fn forward(&self, lon: f64, lat: f64) -> [f64; 2] {
+        let k = krueger_series();
+        let phi = lat.to_radians();
+        let lambda = (lon - self.central_meridian).to_radians();
+
+        let t = (phi.sin().atanh() - k.e * (k.e * phi.sin()).atanh()).sinh();
+        let xi_prime = t.atan2(lambda.cos());
+        let eta_prime = (lambda.sin() / (1.0 + t * t).sqrt()).atanh();
+
+        let (mut xi, mut eta) = (xi_prime, eta_prime);
+        for (j, alpha) in k.alpha.iter().enumerate() {
+            let m = 2.0 * (j + 1) as f64;
+            xi += alpha * (m * xi_prime).sin() * (m * eta_prime).cosh();
+            eta += alpha * (m * xi_prime).cos() * (m * eta_prime).sinh();
+        }
+
+        let scale = self.scale * k.rectifying_radius;
+        [self.false_easting + scale * eta, scale * xi]
+    }
+
+    fn inverse(&self, x: f64, y: f64) -> [f64; 2] {
+        let k = krueger_series();
+        let scale = self.scale * k.rectifying_radius;
+        let xi = y / scale;
+        let eta = (x - self.false_easting) / scale;
+
+        let (mut xi_prime, mut eta_prime) = (xi, eta);
+        for (j, beta) in k.beta.iter().enumerate() {
+            let m = 2.0 * (j + 1) as f64;
+            xi_prime -= beta * (m * xi).sin() * (m * eta).cosh();
+            eta_prime -= beta * (m * xi).cos() * (m * eta).sinh();
+        }
+
+        let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+        let phi = chi
+            + k.delta
+                .iter()
+                .enumerate()
+                .map(|(j, delta)| delta * (2.0 * (j + 1) as f64 * chi).sin())
+                .sum::<f64>();
+        let lambda = eta_prime.sinh().atan2(xi_prime.cos());
+
+        [
+            self.central_meridian + lambda.to_degrees(),
+            phi.to_degrees(),
+        ]
+    }
+}
+
+/// Convert Web Mercator metres to WGS84 degrees, returned as `[lon, lat]`.
+pub fn mercator_to_lon_lat(x: f64, y: f64) -> [f64; 2] {
+    let lon = (x / EARTH_RADIUS_M).to_degrees();
+    let lat = (2.0 * (y / EARTH_RADIUS_M).exp().atan() - std::f64::consts::FRAC_PI_2).to_degrees();
+    [lon, lat]
+}
+
+/// Convert WGS84 degrees to Web Mercator metres, returned as `[x, y]`.
+pub fn lon_lat_to_mercator(lon: f64, lat: f64) -> [f64; 2] {
+    let lat = lat.clamp(-MAX_MERCATOR_LATITUDE, MAX_MERCATOR_LATITUDE);
+    let x = EARTH_RADIUS_M * lon.to_radians();
+    let y = EARTH_RADIUS_M
+        * (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.0)
+            .tan()
+            .ln();
+    [x, y]
+}
+
+/// Reproject a WGS84 GeoJSON geometry to Web Mercator.
+pub fn geometry_to_mercator(geometry: &GeoJSONGeometry) -> GeoJSONGeometry {
+    map_positions(geometry, lon_lat_to_mercator)
+}
+
+/// Reproject a Web Mercator GeoJSON geometry to WGS84.
+pub fn geometry_to_lon_lat(geometry: &GeoJSONGeometry) -> GeoJSONGeometry {
+    map_positions(geometry, mercator_to_lon_lat)
+}
+
+/// Apply `convert` to the horizontal part of every position, keeping any Z value.
+fn map_positions(
+    geometry: &GeoJSONGeometry,
+    convert: impl Fn(f64, f64) -> [f64; 2],
+) -> GeoJSONGeometry {
+    let position = |p: &Vec<f64>| match p.as_slice() {
+        [x, y, rest @ ..] => {
+            let mut out = convert(*x, *y).to_vec();
+            out.extend_from_slice(rest);
+            out
+        }
+        _ => p.clone(),
+    };
+    let line = |l: &Vec<Vec<f64>>| l.iter().map(position).collect::<Vec<_>>();
+    let polygon = |r: &Vec<Vec<Vec<f64>>>| r.iter().map(line).collect::<Vec<_>>();
+
+    match geometry {
+        GeoJSONGeometry::Point(p) => GeoJSONGeometry::Point(position(p)),
+        GeoJSONGeometry::MultiPoint(ps) => GeoJSONGeometry::MultiPoint(line(ps)),
+        GeoJSONGeometry::LineString(l) => GeoJSONGeometry::LineString(line(l)),
+        GeoJSONGeometry::MultiLineString(ls) => GeoJSONGeometry::MultiLineString(polygon(ls)),
+        GeoJSONGeometry::Polygon(rings) => GeoJSONGeometry::Polygon(polygon(rings)),
+        GeoJSONGeometry::MultiPolygon(polys) => {
+            GeoJSONGeometry::MultiPolygon(polys.iter().map(polygon).collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_toronto() {
+        let [x, y] = lon_lat_to_mercator(-79.3832, 43.6532);
+        assert!((x - -8_836_840.0).abs() < 100.0);
+        assert!((y - 5_411_920.0).abs() < 100.0);
+
+        let [lon, lat] = mercator_to_lon_lat(x, y);
+        assert!((lon - -79.3832).abs() < 1e-9);
+        assert!((lat - 43.6532).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_transverse_mercator_on_central_meridian() {
+        // GRS80 meridian arc to 45 degrees north is 4 984 944.378 m.
+        let [x, y] = Crs::Utm(17).from_lon_lat(-81.0, 45.0);
+        assert!((x - 500_000.0).abs() < 1e-6);
+        assert!((y - 0.9996 * 4_984_944.378).abs() < 0.01);
+
+        let [x, y] = Crs::Mtm(10).from_lon_lat(-79.5, 45.0);
+        assert!((x - 304_800.0).abs() < 1e-6);
+        assert!((y - 0.9999 * 4_984_944.378).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_transverse_mercator_round_trip_across_zone() {
+        for crs in [
+            Crs::Utm(15),
+            Crs::Utm(18),
+            Crs::Mtm(8),
+            Crs::Mtm(12),
+            Crs::Mtm(17),
+        ] {
+            let cm = crs.transverse_mercator().unwrap().central_meridian;
+            for (dlon, lat) in [(-3.0, 42.0), (0.0, 49.5), (2.5, 56.8)] {
+                let [x, y] = crs.from_lon_lat(cm + dlon, lat);
+                let [lon, lat_back] = crs.to_lon_lat(x, y);
+                assert!((lon - (cm + dlon)).abs() < 1e-9, "{:?}", crs);
+                assert!((lat_back - lat).abs() < 1e-9, "{:?}", crs);
+            }
+        }
+    }
+
+    #[test]
+    fn test_epsg_codes_round_trip() {
+        for code in [
+            4326, 3857, 3159, 3160, 2958, 2959, 2950, 2951, 2952, 26891, 26897,
+        ] {
+            assert_eq!(Crs::from_epsg(code).unwrap().epsg(), code);
+        }
+        assert_eq!(Crs::from_epsg(2952).unwrap(), Crs::Mtm(10));
+        assert_eq!(Crs::from_epsg(26892).unwrap(), Crs::Mtm(12));
+        assert_eq!(
+            Crs::from_epsg(32617),
+            Err(ProjectionError::UnsupportedCrs(32617))
+        );
+        assert_eq!(mtm_central_meridian(13), -84.0);
+        assert_eq!(mtm_central_meridian(17), -96.0);
+    }
+
+    #[test]
+    fn test_bbox_transform_densifies_edges() {
+        // A UTM 17N box straddling the central meridian: its northern edge
+        // reaches furthest north at the meridian, between the corners.
+        let bbox = BoundingBox::new(400_000.0, 4_800_000.0, 600_000.0, 4_900_000.0, 2958);
+        let mercator = transform_bbox(&bbox, Crs::WebMercator).unwrap();
+        assert_eq!(mercator.srid, 3857);
+
+        let corner_max_y = [400_000.0, 600_000.0]
+            .iter()
+            .map(|&x| transform_point(Crs::Utm(17), Crs::WebMercator, x, 4_900_000.0)[1])
+            .fold(f64::NEG_INFINITY, f64::max);
+        assert!(mercator.ymax > corner_max_y);
+
+        let back = transform_bbox(&mercator, Crs::Utm(17)).unwrap();
+        assert!(back.xmin <= 400_000.0 && back.xmax >= 600_000.0);
+        assert!(back.ymin <= 4_800_000.0 && back.ymax >= 4_900_000.0);
+
+        let unsupported = BoundingBox::new(0.0, 0.0, 1.0, 1.0, 32617);
+        assert!(transform_bbox(&unsupported, Crs::Wgs84).is_err());
+    }
+
+    #[test]
+    fn test_clip_extent_transform() {
+        let utm = ClipExtent {
+            min_x: 400_000.0,
+            min_y: 4_800_000.0,
+            max_x: 600_000.0,
+            max_y: 4_900_000.0,
+            srid: 2958,
+        };
+        let mercator = transform_clip_extent(&utm, Crs::WebMercator).unwrap();
+        assert_eq!(mercator.srid, 3857);
+        let bbox = transform_bbox(
+            &BoundingBox::new(utm.min_x, utm.min_y, utm.max_x, utm.max_y, utm.srid),
+            Crs::WebMercator,
+        )
+        .unwrap();
+        assert_eq!(
+            [
+                mercator.min_x,
+                mercator.min_y,
+                mercator.max_x,
+                mercator.max_y
+            ],
+            [bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax]
+        );
+
+        assert_eq!(transform_clip_extent(&utm, Crs::Utm(17)).unwrap(), utm);
+        let unsupported = ClipExtent { srid: 32617, ..utm };
+        assert!(transform_clip_extent(&unsupported, Crs::WebMercator).is_err());
+    }
+
+    #[test]
+    fn test_geometry_to_mercator_keeps_structure() {
+        let geometry = GeoJSONGeometry::Polygon(vec![vec![
+            vec![0.0, 0.0],
+            vec![1.0, 0.0],
+            vec![1.0, 1.0],
+            vec![0.0, 0.0],
+        ]]);
+        let GeoJSONGeometry::Polygon(rings) = geometry_to_mercator(&geometry) else {
+            panic!("expected a polygon");
+        };
+        assert_eq!(rings[0].len(), 4);
+        assert!(rings[0][0].iter().all(|v| v.abs() < 1e-6));
+        assert!((rings[0][1][0] - 111_319.49).abs() < 0.01);
+
+        let GeoJSONGeometry::Polygon(rings) = geometry_to_lon_lat(&GeoJSONGeometry::Polygon(rings))
+        else {
+            panic!("expected a polygon");
+        };
+        assert!((rings[0][2][0] - 1.0).abs() < 1e-9);
+        assert!((rings[0][2][1] - 1.0).abs() < 1e-9);
+    }
+}
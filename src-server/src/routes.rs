@@ -1,85 +1,373 @@
 use axum::{
-    extract::{Path, State},
-    response::{sse::Event, IntoResponse, Sse},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, Method},
+    response::{sse::Event, IntoResponse, Response, Sse},
     Json,
 };
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 use futures::stream::Stream;
+use geo::BoundingRect;
+use thiserror::Error;
 use tokio::sync::{broadcast, RwLock};
 
 use crate::api_types::{
-    DownloadRequest, DownloadStartResponse, Package, ProgressEvent, QueryRequest, QueryResult,
+    project_names, sort_chronologically, BoundingBox, ClipExtentRequest, CoverageReport,
+    CoverageRequest, DownloadEstimate, DownloadProgressEvent, DownloadRequest,
+    DownloadStartResponse, DownloadStatus, ElevationQuery, ElevationResponse, GeoJSONGeometry,
+    IndexStatus, JobStatus, Package, PackageFilter, ProcessingProgressEvent, ProfilePoint,
+    ProfileRequest, ProfileResponse, ProgressEvent, QueryFormat, QueryFormatParams, QueryRequest,
+    QueryResult, SelectionRequest, SelectionResult, StatisticsRequest, TileParams, ZonalStatistics,
 };
-use crate::download::{extract_zip, DownloadManager, ProgressSender};
-use crate::package_client::PackageClient;
-use crate::processing::{merge_to_cog, ClipExtent, CompressionType};
+use crate::byte_range::{serve_file, ByteCoverage};
+use crate::coverage::{compute_coverage, extent_to_polygon};
+use crate::disk_space::{ensure_job_space, DiskMonitor};
+use crate::download::{extract_zip, is_geotiff_url, DownloadManager, ProgressSender};
+use crate::elevation::{
+    profile_points, sample_packages, Samples, DEFAULT_PROFILE_SAMPLES, MAX_PROFILE_SAMPLES,
+};
+use crate::export::{feature_collection, shapefile_zip};
+use crate::janitor::RetentionPolicy;
+use crate::package_index::PackageIndex;
+use crate::package_source::SourceRegistry;
+use crate::processing::{
+    merge_to_cog, remote_cog_input, stream_remote_cogs, ClipExtent, CompressionType, MergeOptions,
+    ProcessingError, VoidFillOptions,
+};
+use crate::projection::{transform_clip_extent, transform_point, Crs};
+use crate::selection::select_packages;
+use crate::statistics::zonal_statistics;
+use crate::tiles::{valid_tile, TileCache};
+use crate::vertical_datum::{VerticalDatumTransform, SOURCE_VERTICAL_DATUM};
 
 pub struct DownloadJob {
     pub output_path: String,
     pub filename: String,
+    pub work_dir: PathBuf,
     pub sender: broadcast::Sender<ProgressEvent>,
+    pub cancel: Arc<AtomicBool>,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub finished_at: Option<Instant>,
+    /// When every byte of the output had been downloaded.
+    pub downloaded_at: Option<Instant>,
+    /// Bytes of the output served so far, across requests.
+    pub served: ByteCoverage,
+    /// Zonal statistics of the output, when the job asked for them.
+    pub statistics: Option<ZonalStatistics>,
+}
+
+/// Why a download job stopped before completing.
+#[derive(Debug, Error)]
+enum JobError {
+    #[error("{}", ProcessingError::Cancelled)]
+    Cancelled,
+    #[error("{0}")]
+    Failed(String),
+}
+
+impl JobError {
+    fn failed(e: impl std::fmt::Display) -> Self {
+        JobError::Failed(e.to_string())
+    }
+}
+
+impl From<ProcessingError> for JobError {
+    fn from(e: ProcessingError) -> Self {
+        match e {
+            ProcessingError::Cancelled => JobError::Cancelled,
+            e => JobError::failed(e),
+        }
+    }
+}
+
+impl DownloadJob {
+    fn finish(&mut self, result: Result<Option<ZonalStatistics>, JobError>) {
+        self.finished_at = Some(Instant::now());
+        match result {
+            Ok(statistics) => {
+                self.status = JobStatus::Completed;
+                self.statistics = statistics;
+            }
+            Err(e) => {
+                self.status = match e {
+                    JobError::Cancelled => JobStatus::Cancelled,
+                    JobError::Failed(_) => JobStatus::Failed,
+                };
+                self.error = Some(e.to_string());
+            }
+        }
+    }
 }
 
 pub struct AppState {
     pub downloads: HashMap<String, Arc<RwLock<Option<DownloadJob>>>>,
+    /// Jobs removed by the janitor, with the time they expired.
+    pub expired: HashMap<String, Instant>,
+    pub tiles: Arc<TileCache>,
+    pub index: Arc<PackageIndex>,
+    pub sources: Arc<SourceRegistry>,
 }
 
 impl AppState {
     pub fn new() -> Self {
+        Self::with_index(Arc::new(PackageIndex::from_env(&cache_root_dir())))
+    }
+
+    pub fn with_index(index: Arc<PackageIndex>) -> Self {
         Self {
             downloads: HashMap::new(),
+            expired: HashMap::new(),
+            tiles: Arc::new(TileCache::from_env()),
+            index,
+            sources: Arc::new(SourceRegistry::from_env()),
         }
     }
 }
 
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub async fn health() -> &'static str {
     "OK"
 }
 
-pub async fn query_packages(Json(req): Json<QueryRequest>) -> Result<Json<QueryResult>, String> {
+/// Packages in an extent, as [`QueryResult`] or, selected with `?format=`
+/// or the `Accept` header, as GeoJSON or a zipped Shapefile.
+pub async fn query_packages(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Query(params): Query<QueryFormatParams>,
+    headers: HeaderMap,
+    Json(req): Json<QueryRequest>,
+) -> Result<Response, String> {
     println!(
         "Query request: min_x={}, min_y={}, max_x={}, max_y={}",
         req.min_x, req.min_y, req.max_x, req.max_y
     );
 
-    let client = PackageClient::new();
-    let bbox = crate::api_types::BoundingBox::new(req.min_x, req.min_y, req.max_x, req.max_y, 3857);
-
-    let packages = client.query_by_extent(&bbox).await.map_err(|e| {
-        eprintln!("Query error: {}", e);
-        format!("Failed to query ArcGIS API: {}", e)
-    })?;
+    let bbox = BoundingBox::new(req.min_x, req.min_y, req.max_x, req.max_y, 3857);
+    let mut packages = find_packages(&state, &bbox, &req.filter).await?;
 
     println!("Found {} packages", packages.len());
 
-    let mut projects: Vec<String> = packages.iter().map(|p| p.project.clone()).collect();
-    projects.sort();
-    projects.dedup();
+    sort_chronologically(&mut packages);
+    let truncated = req.filter.limit_total_size(&mut packages);
+    if truncated {
+        println!("Kept {} packages within max_total_size_gb", packages.len());
+    }
+    let projects = project_names(&packages);
 
     let total_size_gb: f64 = packages.iter().map(|p| p.size_gb).sum();
 
-    Ok(Json(QueryResult {
-        packages,
-        projects,
-        total_size_gb,
-    }))
+    let format = params.format.unwrap_or_else(|| {
+        headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(QueryFormat::from_accept)
+            .unwrap_or_default()
+    });
+
+    match format {
+        QueryFormat::Json => Ok(Json(QueryResult {
+            packages,
+            projects,
+            total_size_gb,
+            truncated,
+        })
+        .into_response()),
+        QueryFormat::Geojson => Ok((
+            [(header::CONTENT_TYPE, "application/geo+json")],
+            feature_collection(&packages).to_string(),
+        )
+            .into_response()),
+        QueryFormat::Shapefile => {
+            let zip = shapefile_zip(&packages, "packages").map_err(|e| e.to_string())?;
+            Ok((
+                [
+                    (header::CONTENT_TYPE, "application/zip"),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        "attachment; filename=\"packages.zip\"",
+                    ),
+                ],
+                zip,
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Packages intersecting `bbox` that pass `filter`, from the local index when
+/// it is populated and the enabled live sources otherwise.
+pub(crate) async fn find_packages(
+    state: &Arc<RwLock<AppState>>,
+    bbox: &BoundingBox,
+    filter: &PackageFilter,
+) -> Result<Vec<Package>, String> {
+    let (index, sources) = {
+        let state = state.read().await;
+        (state.index.clone(), state.sources.clone())
+    };
+
+    match index.query(bbox).await {
+        Some(mut packages) => {
+            packages.retain(|pkg| filter.matches(pkg));
+            Ok(packages)
+        }
+        None => sources.query_by_extent(bbox, filter).await.map_err(|e| {
+            eprintln!("Query error: {}", e);
+            format!("Failed to query ArcGIS API: {}", e)
+        }),
+    }
+}
+
+pub async fn index_status(State(state): State<Arc<RwLock<AppState>>>) -> Json<IndexStatus> {
+    let index = state.read().await.index.clone();
+    Json(index.status().await)
+}
+
+/// Refresh the local package index from the live service.
+pub async fn sync_index(
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Json<IndexStatus>, String> {
+    let (index, sources) = {
+        let state = state.read().await;
+        (state.index.clone(), state.sources.clone())
+    };
+    index
+        .sync(&sources)
+        .await
+        .map_err(|e| format!("Failed to sync package index: {}", e))?;
+    Ok(Json(index.status().await))
+}
+
+pub async fn coverage_report(
+    Json(req): Json<CoverageRequest>,
+) -> Result<Json<CoverageReport>, String> {
+    let area = area_of_interest(req.polygon, req.extent)?;
+    Ok(Json(compute_coverage(&area, &req.packages)))
+}
+
+/// Pick the packages that cover an area of interest using a selection strategy.
+pub async fn select_coverage(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Json(req): Json<SelectionRequest>,
+) -> Result<Json<SelectionResult>, String> {
+    let area = area_of_interest(req.polygon, req.extent)?;
+
+    let packages = match req.packages {
+        Some(packages) => packages,
+        None => {
+            let rect = area
+                .to_multi_polygon()
+                .bounding_rect()
+                .ok_or("Area of interest is empty")?;
+            let bbox =
+                BoundingBox::new(rect.min().x, rect.min().y, rect.max().x, rect.max().y, 3857);
+            find_packages(&state, &bbox, &PackageFilter::default()).await?
+        }
+    };
+
+    select_packages(
+        &area,
+        &packages,
+        req.strategy,
+        req.selected_project.as_deref(),
+    )
+    .map(Json)
+    .map_err(|e| e.to_string())
+}
+
+fn area_of_interest(
+    polygon: Option<GeoJSONGeometry>,
+    extent: Option<ClipExtentRequest>,
+) -> Result<GeoJSONGeometry, String> {
+    match (polygon, extent) {
+        (Some(polygon), _) => Ok(polygon),
+        (None, Some(extent)) => Ok(extent_to_polygon(&extent_to_web_mercator(&extent)?)),
+        (None, None) => Err("Either an extent or a polygon is required".to_string()),
+    }
+}
+
+/// Reproject an extent given in another coordinate system to Web Mercator.
+fn extent_to_web_mercator(extent: &ClipExtentRequest) -> Result<ClipExtentRequest, String> {
+    let extent = transform_clip_extent(&ClipExtent::from(extent), Crs::WebMercator)
+        .map_err(|e| e.to_string())?;
+    Ok(ClipExtentRequest {
+        min_x: extent.min_x,
+        min_y: extent.min_y,
+        max_x: extent.max_x,
+        max_y: extent.max_y,
+        srid: extent.srid,
+    })
+}
+
+/// Estimate download size, disk needs and output size before starting a job.
+pub async fn estimate_download(
+    Json(mut req): Json<DownloadRequest>,
+) -> Result<Json<DownloadEstimate>, String> {
+    req.clip_extent = req
+        .clip_extent
+        .as_ref()
+        .map(extent_to_web_mercator)
+        .transpose()?;
+    let estimate = tokio::task::spawn_blocking(move || {
+        crate::estimate::estimate_download(&req, &cache_root_dir(), &work_root_dir())
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(Json(estimate))
 }
 
 pub async fn start_download(
     State(state): State<Arc<RwLock<AppState>>>,
-    Json(req): Json<DownloadRequest>,
+    Json(mut req): Json<DownloadRequest>,
 ) -> Result<Json<DownloadStartResponse>, String> {
+    // GDAL clips in the request's own coordinate system; the Web Mercator
+    // envelope is only used for coverage and size estimates.
+    let clip = req.clip_extent.as_ref().map(ClipExtent::from);
+    req.clip_extent = req
+        .clip_extent
+        .as_ref()
+        .map(extent_to_web_mercator)
+        .transpose()?;
+    let source_datum = req.source_vertical_datum.unwrap_or(SOURCE_VERTICAL_DATUM);
+    let vertical_datum = req
+        .target_vertical_datum
+        .map(|target| VerticalDatumTransform::resolve(source_datum, target))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    let cache_root = cache_root_dir();
+    let work_root = work_root_dir();
+    let estimate = {
+        let (req, cache_root, work_root) = (req.clone(), cache_root.clone(), work_root.clone());
+        tokio::task::spawn_blocking(move || {
+            crate::estimate::estimate_download(&req, &cache_root, &work_root)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+    };
+    ensure_job_space(
+        &cache_root,
+        estimate.download_bytes + estimate.extraction_bytes,
+        &work_root,
+        estimate.work_bytes,
+    )
+    .map_err(|e| ProcessingError::from(e).to_string())?;
+
     let download_id = uuid::Uuid::new_v4().to_string();
-    let work_dir = std::env::temp_dir()
-        .join("dtm-downloads")
-        .join(&download_id);
+    let work_dir = work_root.join(&download_id);
     std::fs::create_dir_all(&work_dir).map_err(|e| e.to_string())?;
-    let cache_root = cache_root_dir();
     let zip_cache_dir = cache_root.join("zips");
     let extract_cache_dir = cache_root.join("extracts");
     std::fs::create_dir_all(&zip_cache_dir).map_err(|e| e.to_string())?;
@@ -92,11 +380,20 @@ pub async fn start_download(
         .to_string();
 
     let (tx, _) = broadcast::channel::<ProgressEvent>(64);
+    let cancel = Arc::new(AtomicBool::new(false));
 
     let job = DownloadJob {
         output_path: output_path.clone(),
         filename: output_filename.clone(),
+        work_dir: work_dir.clone(),
         sender: tx.clone(),
+        cancel: cancel.clone(),
+        status: JobStatus::Running,
+        error: None,
+        finished_at: None,
+        downloaded_at: None,
+        served: ByteCoverage::default(),
+        statistics: None,
     };
 
     let job_state: Arc<RwLock<Option<DownloadJob>>> = Arc::new(RwLock::new(Some(job)));
@@ -108,26 +405,28 @@ pub async fn start_download(
             .insert(download_id.clone(), job_state.clone());
     }
 
-    let packages = req.packages.clone();
-    let clip_extent = req.clip_extent.clone();
-    let compression = req.compression.clone();
-    let zip_cache_dir_str = zip_cache_dir.to_string_lossy().to_string();
-    let extract_cache_dir_str = extract_cache_dir.to_string_lossy().to_string();
-
     tokio::spawn(async move {
         tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-        if let Err(e) = run_download_job(
-            packages,
-            zip_cache_dir_str,
-            extract_cache_dir_str,
+        let result = run_download_job(
+            req,
+            clip,
+            vertical_datum,
+            cache_root,
             output_path,
-            clip_extent,
-            compression,
-            tx,
+            tx.clone(),
+            cancel,
         )
-        .await
-        {
+        .await;
+        if let Err(e) = &result {
             eprintln!("Download job error: {}", e);
+            // Partial outputs go; cached ZIPs stay so the next attempt can resume.
+            let _ = std::fs::remove_dir_all(&work_dir);
+            let _ = tx.send(ProgressEvent::Error {
+                message: e.to_string(),
+            });
+        }
+        if let Some(job) = job_state.write().await.as_mut() {
+            job.finish(result);
         }
     });
 
@@ -135,24 +434,86 @@ pub async fn start_download(
 }
 
 async fn run_download_job(
-    packages: Vec<Package>,
-    zip_cache_dir: String,
-    extract_cache_dir: String,
+    req: DownloadRequest,
+    clip: Option<ClipExtent>,
+    vertical_datum: Option<VerticalDatumTransform>,
+    cache_root: PathBuf,
     output_path: String,
-    clip_extent: Option<crate::api_types::ClipExtentRequest>,
-    compression: String,
     sender: broadcast::Sender<ProgressEvent>,
-) -> Result<(), String> {
+    cancel: Arc<AtomicBool>,
+) -> Result<Option<ZonalStatistics>, JobError> {
     let progress_sender = ProgressSender::new(sender.clone());
 
+    if let Some(extent) = &req.clip_extent {
+        let report = compute_coverage(&extent_to_polygon(extent), &req.packages);
+        if !report.is_complete {
+            progress_sender.send(ProgressEvent::Warning {
+                message: format!(
+                    "Selected packages cover only {:.1}% of the clip extent; uncovered areas will be nodata",
+                    report.covered_percentage
+                ),
+            });
+        }
+    }
+
+    let zip_cache_dir = cache_root.join("zips").to_string_lossy().to_string();
+    let extract_cache_dir = cache_root.join("extracts").to_string_lossy().to_string();
+    let block_cache_dir = cache_root.join("cog-blocks");
+    let stream_cogs = stream_remote_cogs();
+
+    let work_dir = PathBuf::from(&output_path)
+        .parent()
+        .map(|dir| dir.to_path_buf())
+        .unwrap_or_else(work_root_dir);
+    let monitor = DiskMonitor::spawn(vec![cache_root.clone(), work_dir], cancel.clone());
+    let stopped = |monitor: &DiskMonitor| match monitor.failure() {
+        Some(e) => JobError::failed(ProcessingError::DiskSpace(e)),
+        None => JobError::Cancelled,
+    };
+
     let manager = DownloadManager::new();
     let mut all_tiff_files = Vec::new();
 
-    for pkg in &packages {
+    for pkg in &req.packages {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(stopped(&monitor));
+        }
         let cache_key = package_cache_key(pkg);
         let zip_path = format!("{}/{}.zip", zip_cache_dir, cache_key);
         let extract_dir = format!("{}/{}", extract_cache_dir, cache_key);
 
+        if is_geotiff_url(&pkg.download_url) && stream_cogs {
+            let input = remote_cog_input(&pkg.download_url, &block_cache_dir)
+                .await
+                .map_err(JobError::failed)?;
+            progress_sender.send(ProgressEvent::Download(DownloadProgressEvent {
+                package_name: pkg.package_name.clone(),
+                bytes_downloaded: 0,
+                total_bytes: 0,
+                percentage: 100.0,
+                speed_bps: 0.0,
+                eta_seconds: None,
+                status: "streaming".to_string(),
+            }));
+            all_tiff_files.push(input);
+            continue;
+        }
+
+        if is_geotiff_url(&pkg.download_url) {
+            let tiff_path = format!("{}/{}.tif", extract_dir, cache_key);
+            manager
+                .download_with_progress(
+                    &pkg.download_url,
+                    &tiff_path,
+                    &pkg.package_name,
+                    &progress_sender,
+                )
+                .await
+                .map_err(JobError::failed)?;
+            all_tiff_files.push(tiff_path);
+            continue;
+        }
+
         manager
             .download_with_progress(
                 &pkg.download_url,
@@ -161,31 +522,58 @@ async fn run_download_job(
                 &progress_sender,
             )
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(JobError::failed)?;
 
         let tiff_files = extract_zip(&zip_path, &extract_dir, &pkg.package_name, &progress_sender)
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(JobError::failed)?;
         all_tiff_files.extend(tiff_files);
     }
 
-    let clip = clip_extent.map(|c| ClipExtent {
-        min_x: c.min_x,
-        min_y: c.min_y,
-        max_x: c.max_x,
-        max_y: c.max_y,
+    let comp = CompressionType::from_str(&req.compression);
+    let mut options = MergeOptions::new(clip, comp);
+    options.output_nodata = req.output_nodata;
+    options.void_fill = req.void_fill.map(|f| VoidFillOptions {
+        max_distance: f.max_distance,
+        smoothing_iterations: f.smoothing_iterations,
     });
-    let comp = CompressionType::from_str(&compression);
+    options.vertical_datum = vertical_datum;
+    options.cancel = cancel;
 
-    merge_to_cog(&all_tiff_files, &output_path, clip, comp, &progress_sender)
+    merge_to_cog(&all_tiff_files, &output_path, &options, &progress_sender)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| match (e, monitor.failure()) {
+            (_, Some(full)) => JobError::failed(ProcessingError::DiskSpace(full)),
+            (e, None) => JobError::from(e),
+        })?;
+
+    let statistics = match &req.statistics {
+        Some(request) => {
+            progress_sender.send(ProgressEvent::Processing(ProcessingProgressEvent {
+                stage: "statistics".to_string(),
+                percentage: 100,
+                message: "Computing zonal statistics...".to_string(),
+            }));
+            // The output is still usable, so a failure here only warns.
+            match zonal_statistics(std::path::Path::new(&output_path), request).await {
+                Ok(statistics) => Some(statistics),
+                Err(e) => {
+                    progress_sender.send(ProgressEvent::Warning {
+                        message: format!("Zonal statistics failed: {}", e),
+                    });
+                    None
+                }
+            }
+        }
+        None => None,
+    };
 
     let _ = sender.send(ProgressEvent::Complete {
         output_filename: "dtm_output.tif".to_string(),
+        statistics: statistics.clone(),
     });
 
-    Ok(())
+    Ok(statistics)
 }
 
 fn cache_root_dir() -> PathBuf {
@@ -213,6 +601,11 @@ fn cache_root_dir() -> PathBuf {
     std::env::temp_dir().join("dtm-download-cache")
 }
 
+/// Parent of the per-job work directories.
+pub(crate) fn work_root_dir() -> PathBuf {
+    std::env::temp_dir().join("dtm-downloads")
+}
+
 fn sanitize_for_path(input: &str) -> String {
     input
         .chars()
@@ -226,7 +619,7 @@ fn sanitize_for_path(input: &str) -> String {
         .collect()
 }
 
-fn package_cache_key(pkg: &Package) -> String {
+pub(crate) fn package_cache_key(pkg: &Package) -> String {
     let mut hasher = std::collections::hash_map::DefaultHasher::new();
     pkg.download_url.hash(&mut hasher);
     let url_hash = hasher.finish();
@@ -234,16 +627,30 @@ fn package_cache_key(pkg: &Package) -> String {
     format!("{}_{:016x}", package_name, url_hash)
 }
 
+/// Look up a job, telling expired jobs apart from unknown ones.
+async fn find_job(
+    state: &Arc<RwLock<AppState>>,
+    id: &str,
+) -> Result<Arc<RwLock<Option<DownloadJob>>>, String> {
+    let state = state.read().await;
+    if let Some(job_state) = state.downloads.get(id) {
+        return Ok(job_state.clone());
+    }
+    if state.expired.contains_key(id) {
+        return Err(format!(
+            "Download {} has expired and its output was deleted",
+            id
+        ));
+    }
+    Err("Download not found".to_string())
+}
+
 pub async fn download_progress(
     Path(id): Path<String>,
     State(state): State<Arc<RwLock<AppState>>>,
 ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, String> {
     let sender = {
-        let state = state.read().await;
-        let job_state = state
-            .downloads
-            .get(&id)
-            .ok_or_else(|| "Download not found".to_string())?;
+        let job_state = find_job(&state, &id).await?;
         let job = job_state.read().await;
         job.as_ref()
             .map(|j| j.sender.clone())
@@ -266,18 +673,58 @@ pub async fn download_progress(
     ))
 }
 
+/// Current state of a job, including `expired` once the janitor removed its output.
+pub async fn download_status(
+    Path(id): Path<String>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Json<DownloadStatus>, String> {
+    if state.read().await.expired.contains_key(&id) {
+        return Ok(Json(DownloadStatus {
+            download_id: id,
+            status: JobStatus::Expired,
+            filename: None,
+            error: None,
+            expires_in_seconds: None,
+            statistics: None,
+        }));
+    }
+    let job_state = find_job(&state, &id).await?;
+    let policy = RetentionPolicy::from_env();
+
+    let job = job_state.read().await;
+    let j = job.as_ref().ok_or_else(|| "Job not found".to_string())?;
+    Ok(Json(DownloadStatus {
+        download_id: id,
+        status: j.status,
+        filename: (j.status == JobStatus::Completed).then(|| j.filename.clone()),
+        error: j.error.clone(),
+        expires_in_seconds: policy
+            .expires_in(j, Instant::now())
+            .map(|left| left.as_secs()),
+        statistics: j.statistics.clone(),
+    }))
+}
+
+pub async fn cancel_download(
+    Path(id): Path<String>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<&'static str, String> {
+    let job_state = find_job(&state, &id).await?;
+    let job = job_state.read().await;
+    let j = job.as_ref().ok_or_else(|| "Job not found".to_string())?;
+    j.cancel.store(true, Ordering::Relaxed);
+    Ok("Cancelling")
+}
+
+/// Serve a job's output, with `Range` and validator support so downloads can
+/// resume and GDAL can read the COG over `/vsicurl/`.
 pub async fn download_file(
     Path(id): Path<String>,
     State(state): State<Arc<RwLock<AppState>>>,
-) -> Result<impl IntoResponse, String> {
-    let job_state = {
-        let state = state.read().await;
-        state
-            .downloads
-            .get(&id)
-            .cloned()
-            .ok_or_else(|| "Download not found".to_string())?
-    };
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Response, String> {
+    let job_state = find_job(&state, &id).await?;
 
     let (output_path, filename) = {
         let job = job_state.read().await;
@@ -285,36 +732,213 @@ pub async fn download_file(
         (j.output_path.clone(), j.filename.clone())
     };
 
-    let file = tokio::fs::File::open(&output_path)
+    // Only once every byte has gone out, possibly over resumed requests,
+    // may retention delete the output; /vsicurl/ readers fetch only parts.
+    let record_served = move |parts: Vec<(u64, u64)>, total: u64| {
+        tokio::spawn(async move {
+            if let Some(job) = job_state.write().await.as_mut() {
+                for (start, end) in parts {
+                    job.served.add(start, end);
+                }
+                if job.served.covers(total) {
+                    job.downloaded_at.get_or_insert_with(Instant::now);
+                }
+            }
+        });
+    };
+
+    serve_file(
+        std::path::Path::new(&output_path),
+        "image/tiff",
+        Some(format!("attachment; filename=\"{}\"", filename)),
+        &method,
+        &headers,
+        record_served,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// `POST /api/download/{id}/statistics`: zonal statistics of a finished output.
+pub async fn download_statistics(
+    Path(id): Path<String>,
+    State(state): State<Arc<RwLock<AppState>>>,
+    Json(req): Json<StatisticsRequest>,
+) -> Result<Json<ZonalStatistics>, String> {
+    let job_state = find_job(&state, &id).await?;
+    let output_path = {
+        let job = job_state.read().await;
+        let j = job.as_ref().ok_or_else(|| "Job not found".to_string())?;
+        if j.status != JobStatus::Completed {
+            return Err("Download has not finished yet".to_string());
+        }
+        PathBuf::from(&j.output_path)
+    };
+    zonal_statistics(&output_path, &req)
+        .await
+        .map(Json)
+        .map_err(|e| e.to_string())
+}
+
+/// Sample Web Mercator points from a finished job output, or else from the cached packages.
+async fn sample_elevations(
+    state: &Arc<RwLock<AppState>>,
+    points: &[[f64; 2]],
+    download_id: Option<&str>,
+) -> Result<Samples, String> {
+    if let Some(id) = download_id {
+        let job_state = find_job(state, id).await?;
+        let output_path = {
+            let job = job_state.read().await;
+            let j = job.as_ref().ok_or_else(|| "Job not found".to_string())?;
+            if j.status != JobStatus::Completed {
+                return Err("Download has not finished yet".to_string());
+            }
+            PathBuf::from(&j.output_path)
+        };
+        let values = crate::elevation::sample_file(&output_path, points)
+            .await
+            .map_err(|e| e.to_string())?;
+        return Ok(Samples {
+            values: values
+                .into_iter()
+                .map(|v| v.map(|v| (v, "output".to_string())))
+                .collect(),
+            uncached_packages: Vec::new(),
+        });
+    }
+
+    let xs = points.iter().map(|p| p[0]);
+    let ys = points.iter().map(|p| p[1]);
+    let bbox = BoundingBox::new(
+        xs.clone().fold(f64::INFINITY, f64::min),
+        ys.clone().fold(f64::INFINITY, f64::min),
+        xs.fold(f64::NEG_INFINITY, f64::max),
+        ys.fold(f64::NEG_INFINITY, f64::max),
+        3857,
+    );
+    let packages = find_packages(state, &bbox, &PackageFilter::default()).await?;
+    sample_packages(points, &packages, &cache_root_dir())
         .await
-        .map_err(|e| e.to_string())?;
-    let stream = tokio_util::io::ReaderStream::new(file);
+        .map_err(|e| e.to_string())
+}
 
-    Ok(axum::response::Response::builder()
-        .header("Content-Type", "image/tiff")
-        .header(
-            "Content-Disposition",
-            format!("attachment; filename=\"{}\"", filename),
-        )
-        .body(axum::body::Body::from_stream(stream))
-        .map_err(|e| e.to_string())?)
+/// `GET /api/elevation?x=&y=&srid=`: the elevation at a single point.
+pub async fn elevation(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Query(query): Query<ElevationQuery>,
+) -> Result<Json<ElevationResponse>, String> {
+    let crs = Crs::from_epsg(query.srid).map_err(|e| e.to_string())?;
+    let point = transform_point(crs, Crs::WebMercator, query.x, query.y);
+    let samples = sample_elevations(&state, &[point], query.download_id.as_deref()).await?;
+    let value = samples.values.into_iter().next().flatten();
+    Ok(Json(ElevationResponse {
+        x: query.x,
+        y: query.y,
+        srid: query.srid,
+        elevation: value.as_ref().map(|(v, _)| *v),
+        source: value.map(|(_, source)| source),
+        uncached_packages: samples.uncached_packages,
+    }))
+}
+
+/// `POST /api/profile`: elevations at evenly spaced points along a LineString.
+pub async fn profile(
+    State(state): State<Arc<RwLock<AppState>>>,
+    Json(req): Json<ProfileRequest>,
+) -> Result<Json<ProfileResponse>, String> {
+    let crs = Crs::from_epsg(req.srid).map_err(|e| e.to_string())?;
+    let GeoJSONGeometry::LineString(coordinates) = &req.line else {
+        return Err("Profile line must be a LineString".to_string());
+    };
+    let line: Vec<[f64; 2]> = coordinates
+        .iter()
+        .filter(|c| c.len() >= 2)
+        .map(|c| transform_point(crs, Crs::WebMercator, c[0], c[1]))
+        .collect();
+    if line.len() < 2 {
+        return Err("Profile line needs at least two positions".to_string());
+    }
+    let count = req
+        .samples
+        .unwrap_or(DEFAULT_PROFILE_SAMPLES)
+        .clamp(2, MAX_PROFILE_SAMPLES);
+
+    let stations = profile_points(&line, count);
+    let points: Vec<[f64; 2]> = stations.iter().map(|(_, p)| *p).collect();
+    let samples = sample_elevations(&state, &points, req.download_id.as_deref()).await?;
+
+    let points = stations
+        .iter()
+        .zip(samples.values)
+        .map(|((distance, [x, y]), value)| {
+            let [x, y] = transform_point(Crs::WebMercator, crs, *x, *y);
+            ProfilePoint {
+                distance: *distance,
+                x,
+                y,
+                elevation: value.as_ref().map(|(v, _)| *v),
+                source: value.map(|(_, source)| source),
+            }
+        })
+        .collect();
+    Ok(Json(ProfileResponse {
+        srid: req.srid,
+        length: stations.last().map(|(d, _)| *d).unwrap_or(0.0),
+        points,
+        uncached_packages: samples.uncached_packages,
+    }))
+}
+
+/// `GET /api/download/{id}/tiles/{z}/{x}/{y}.png`: a preview tile of a finished output.
+pub async fn download_tile(
+    Path((id, z, x, tile)): Path<(String, u8, u32, String)>,
+    Query(params): Query<TileParams>,
+    State(state): State<Arc<RwLock<AppState>>>,
+) -> Result<Response, String> {
+    let y: u32 = tile
+        .strip_suffix(".png")
+        .and_then(|y| y.parse().ok())
+        .ok_or_else(|| format!("Invalid tile '{}'", tile))?;
+    if !valid_tile(z, x, y) {
+        return Err(format!("Tile {}/{}/{} is out of range", z, x, y));
+    }
+
+    let job_state = find_job(&state, &id).await?;
+    let output_path = {
+        let job = job_state.read().await;
+        let j = job.as_ref().ok_or_else(|| "Job not found".to_string())?;
+        if j.status != JobStatus::Completed {
+            return Err("Download has not finished yet".to_string());
+        }
+        PathBuf::from(&j.output_path)
+    };
+    let tiles = state.read().await.tiles.clone();
+
+    let png = tiles
+        .tile(&id, &output_path, params.style, z, x, y)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, "private, max-age=3600"),
+        ],
+        png,
+    )
+        .into_response())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::PackageBuilder;
 
     fn test_package(package_name: &str, download_url: &str) -> Package {
-        Package {
-            package_name: package_name.to_string(),
-            size_gb: 1.0,
-            resolution: 0.5,
-            download_url: download_url.to_string(),
-            project: "Test Project".to_string(),
-            year_range: Some("2023".to_string()),
-            coverage_km2: 1.0,
-            geometry: crate::api_types::GeoJSONGeometry::Polygon(vec![]),
-        }
+        PackageBuilder::new(package_name)
+            .download_url(download_url)
+            .years("2023")
+            .build()
     }
 
     #[test]
@@ -335,6 +959,36 @@ mod tests {
         assert_eq!(sanitize_for_path("A/B C"), "A_B_C");
     }
 
+    fn running_job() -> DownloadJob {
+        DownloadJob {
+            output_path: String::new(),
+            filename: String::new(),
+            work_dir: PathBuf::new(),
+            sender: broadcast::channel(1).0,
+            cancel: Arc::new(AtomicBool::new(false)),
+            status: JobStatus::Running,
+            error: None,
+            finished_at: None,
+            downloaded_at: None,
+            served: ByteCoverage::default(),
+            statistics: None,
+        }
+    }
+
+    #[test]
+    fn test_finish_tells_cancellation_from_failure() {
+        let mut job = running_job();
+        job.finish(Err(ProcessingError::Cancelled.into()));
+        assert_eq!(job.status, JobStatus::Cancelled);
+        assert_eq!(job.error.as_deref(), Some("Processing was cancelled"));
+
+        let mut job = running_job();
+        job.finish(Err(
+            ProcessingError::GdalError("cancelled".to_string()).into()
+        ));
+        assert_eq!(job.status, JobStatus::Failed);
+    }
+
     #[test]
     fn test_cache_root_dir_uses_override() {
         let original = std::env::var("DTM_CACHE_DIR").ok();
@@ -0,0 +1,328 @@
+//! Package selection: pick the packages that cover an area of interest.
+//!
+//! Candidates are ranked by the requested strategy and added greedily; a
+//! package is only selected when it covers part of the area that the
+//! higher-ranked packages already selected do not.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+use geo::{Area, BooleanOps, MultiPolygon};
+use regex::Regex;
+use thiserror::Error;
+
+use crate::api_types::{GeoJSONGeometry, Package, SelectionResult, SelectionStrategy};
+use crate::coverage::{compute_coverage, normalize, COVERAGE_TOLERANCE_PERCENT};
+
+static PROJECT_YEAR_FRAGMENT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:19|20)\d{2}(?:\s*[-/–]\s*\d{2,4})?\b").expect("valid year fragment regex")
+});
+static NON_ALPHANUMERIC_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[^a-z0-9]+").expect("valid separator regex"));
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SelectionError {
+    #[error("Strategy '{0}' requires selected_project")]
+    MissingSelectedProject(&'static str),
+}
+
+/// Select the packages that cover `area` according to `strategy`.
+///
+/// `selected_project` is the exact project name for the selected-only and
+/// prefer-selected-with-fallback strategies, and ignored otherwise.
+pub fn select_packages(
+    area: &GeoJSONGeometry,
+    packages: &[Package],
+    strategy: SelectionStrategy,
+    selected_project: Option<&str>,
+) -> Result<SelectionResult, SelectionError> {
+    let ranks = rank_packages(packages, strategy, selected_project)?;
+    let area_polygon = normalize(area.to_multi_polygon());
+    let min_gain = area_polygon.unsigned_area() * COVERAGE_TOLERANCE_PERCENT / 100.0;
+
+    let mut candidates: Vec<Candidate> = packages
+        .iter()
+        .zip(ranks)
+        .filter_map(|(package, rank)| {
+            let footprint = normalize(package.geometry.to_multi_polygon());
+            let overlap = area_polygon.intersection(&footprint).unsigned_area();
+            let rank = rank?;
+            (overlap > min_gain).then_some(Candidate {
+                package,
+                footprint,
+                rank,
+                overlap,
+            })
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        a.rank
+            .partial_cmp(&b.rank)
+            .unwrap_or(Ordering::Equal)
+            .then(b.overlap.partial_cmp(&a.overlap).unwrap_or(Ordering::Equal))
+    });
+
+    let mut remaining = area_polygon;
+    let mut selected = Vec::new();
+    for candidate in candidates {
+        if remaining.unsigned_area() <= min_gain {
+            break;
+        }
+        let gain = remaining.intersection(&candidate.footprint).unsigned_area();
+        if gain > min_gain {
+            remaining = remaining.difference(&candidate.footprint);
+            selected.push(candidate.package.clone());
+        }
+    }
+
+    let coverage = compute_coverage(area, &selected);
+    let total_size_gb = selected.iter().map(|p| p.size_gb).sum();
+    Ok(SelectionResult {
+        strategy,
+        packages: selected,
+        total_size_gb,
+        coverage,
+    })
+}
+
+struct Candidate<'a> {
+    package: &'a Package,
+    footprint: MultiPolygon<f64>,
+    rank: Rank,
+    overlap: f64,
+}
+
+/// Sort key for a candidate package; lower ranks are preferred.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+struct Rank(u8, f64, f64);
+
+/// Sort packages best first by the newest or finest-resolution ordering.
+pub(crate) fn sort_packages(packages: &mut [Package], strategy: SelectionStrategy) {
+    let rank = |p: &Package| match strategy {
+        SelectionStrategy::FinestResolution => Rank(0, resolution_key(p), -latest_year(p)),
+        _ => Rank(0, -latest_year(p), resolution_key(p)),
+    };
+    packages.sort_by(|a, b| rank(a).partial_cmp(&rank(b)).unwrap_or(Ordering::Equal));
+}
+
+/// Rank each package for `strategy`; `None` excludes the package.
+fn rank_packages(
+    packages: &[Package],
+    strategy: SelectionStrategy,
+    selected_project: Option<&str>,
+) -> Result<Vec<Option<Rank>>, SelectionError> {
+    match strategy {
+        SelectionStrategy::Newest => Ok(packages
+            .iter()
+            .map(|p| Some(Rank(0, -latest_year(p), resolution_key(p))))
+            .collect()),
+        SelectionStrategy::FinestResolution => Ok(packages
+            .iter()
+            .map(|p| Some(Rank(0, resolution_key(p), -latest_year(p))))
+            .collect()),
+        SelectionStrategy::SelectedOnly | SelectionStrategy::PreferSelectedWithFallback => {
+            let selected =
+                selected_project.ok_or(SelectionError::MissingSelectedProject(strategy.name()))?;
+            let fallback = if strategy == SelectionStrategy::PreferSelectedWithFallback {
+                fallback_project(packages, selected)
+            } else {
+                None
+            };
+            Ok(packages
+                .iter()
+                .map(|p| {
+                    if p.project == selected {
+                        Some(Rank(0, 0.0, 0.0))
+                    } else if fallback.as_deref() == Some(p.project.as_str()) {
+                        Some(Rank(1, 0.0, 0.0))
+                    } else {
+                        None
+                    }
+                })
+                .collect())
+        }
+    }
+}
+
+/// The newest project in the same group as `selected` that predates it,
+/// e.g. "GTA 2014-18" for "GTA 2023".
+fn fallback_project(packages: &[Package], selected: &str) -> Option<String> {
+    let mut project_years: HashMap<&str, f64> = HashMap::new();
+    for package in packages {
+        let year = project_years
+            .entry(package.project.as_str())
+            .or_insert(f64::NEG_INFINITY);
+        *year = year.max(latest_year(package));
+    }
+
+    let selected_year = *project_years.get(selected)?;
+    if !selected_year.is_finite() {
+        return None;
+    }
+    let group = project_group(selected);
+
+    project_years
+        .into_iter()
+        .filter(|&(project, year)| {
+            project != selected
+                && year.is_finite()
+                && year < selected_year
+                && project_group(project) == group
+        })
+        .max_by(|a, b| {
+            a.1.partial_cmp(&b.1)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| b.0.cmp(a.0))
+        })
+        .map(|(project, _)| project.to_string())
+}
+
+/// Project name with acquisition years and punctuation removed.
+fn project_group(project: &str) -> String {
+    let lower = project.to_lowercase();
+    let without_years = PROJECT_YEAR_FRAGMENT_RE.replace_all(&lower, " ");
+    let normalized = NON_ALPHANUMERIC_RE.replace_all(&without_years, " ");
+    let normalized = normalized.split_whitespace().collect::<Vec<_>>().join(" ");
+    if normalized.is_empty() {
+        lower.trim().to_string()
+    } else {
+        normalized
+    }
+}
+
+fn latest_year(package: &Package) -> f64 {
+    package
+        .acquisition_period
+        .map(|period| period.end_year as f64)
+        .unwrap_or(f64::NEG_INFINITY)
+}
+
+/// Resolution for sorting; unknown (zero) resolutions sort last.
+fn resolution_key(package: &Package) -> f64 {
+    if package.resolution > 0.0 {
+        package.resolution
+    } else {
+        f64::INFINITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_types::ClipExtentRequest;
+    use crate::coverage::extent_to_polygon;
+    use crate::test_support::PackageBuilder;
+
+    fn package(name: &str, project: &str, years: &str, resolution: f64, x: [f64; 2]) -> Package {
+        PackageBuilder::new(name)
+            .size_gb(1.5)
+            .resolution(resolution)
+            .project(project)
+            .years(years)
+            .geometry(extent(x[0], 0.0, x[1], 10.0))
+            .build()
+    }
+
+    fn extent(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> GeoJSONGeometry {
+        extent_to_polygon(&ClipExtentRequest {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            srid: 3857,
+        })
+    }
+
+    fn names(result: &SelectionResult) -> Vec<&str> {
+        result
+            .packages
+            .iter()
+            .map(|p| p.package_name.as_str())
+            .collect()
+    }
+
+    fn sample_packages() -> Vec<Package> {
+        vec![
+            package("old-full", "GTA 2014-18", "2014-18", 0.5, [0.0, 10.0]),
+            package("new-left", "GTA 2023", "2023", 0.5, [0.0, 6.0]),
+            package("coarse-full", "LEAP 2009", "2009", 1.0, [0.0, 10.0]),
+            package("fine-right", "Other 2012", "2012", 0.25, [5.0, 10.0]),
+        ]
+    }
+
+    #[test]
+    fn test_newest_fills_gaps_with_older_packages() {
+        let packages = sample_packages();
+        let result = select_packages(
+            &extent(0.0, 0.0, 10.0, 10.0),
+            &packages,
+            SelectionStrategy::Newest,
+            None,
+        )
+        .unwrap();
+        assert_eq!(names(&result), vec!["new-left", "old-full"]);
+        assert!(result.coverage.is_complete);
+        assert!((result.total_size_gb - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_finest_resolution_prefers_small_pixels() {
+        let packages = sample_packages();
+        let result = select_packages(
+            &extent(0.0, 0.0, 10.0, 10.0),
+            &packages,
+            SelectionStrategy::FinestResolution,
+            None,
+        )
+        .unwrap();
+        assert_eq!(names(&result), vec!["fine-right", "new-left"]);
+    }
+
+    #[test]
+    fn test_selected_only_leaves_gaps() {
+        let packages = sample_packages();
+        let result = select_packages(
+            &extent(0.0, 0.0, 10.0, 10.0),
+            &packages,
+            SelectionStrategy::SelectedOnly,
+            Some("GTA 2023"),
+        )
+        .unwrap();
+        assert_eq!(names(&result), vec!["new-left"]);
+        assert!(!result.coverage.is_complete);
+    }
+
+    #[test]
+    fn test_prefer_selected_falls_back_to_same_project_group() {
+        let packages = sample_packages();
+        let result = select_packages(
+            &extent(0.0, 0.0, 10.0, 10.0),
+            &packages,
+            SelectionStrategy::PreferSelectedWithFallback,
+            Some("GTA 2023"),
+        )
+        .unwrap();
+        assert_eq!(names(&result), vec!["new-left", "old-full"]);
+        assert!(result.coverage.is_complete);
+    }
+
+    #[test]
+    fn test_selected_strategy_requires_project() {
+        let err = select_packages(
+            &extent(0.0, 0.0, 10.0, 10.0),
+            &sample_packages(),
+            SelectionStrategy::SelectedOnly,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err, SelectionError::MissingSelectedProject("selected-only"));
+    }
+
+    #[test]
+    fn test_project_group_ignores_years() {
+        assert_eq!(project_group("OMAFRA Lidar 2016-18"), "omafra lidar");
+        assert_eq!(project_group("OMAFRA Lidar 2022"), "omafra lidar");
+        assert_eq!(project_group("2009"), "2009");
+    }
+}
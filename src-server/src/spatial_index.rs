@@ -0,0 +1,143 @@
+//! In-memory R-tree over package footprints.
+//!
+//! Candidates are found by envelope in the R-tree, then confirmed with an
+//! exact polygon intersection test against the footprint.
+
+use geo::{BoundingRect, Intersects, MultiPolygon, Polygon, Rect};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, AABB};
+
+use crate::api_types::{BoundingBox, Package};
+
+type FootprintEnvelope = GeomWithData<Rectangle<[f64; 2]>, usize>;
+
+#[derive(Debug, Default)]
+pub struct SpatialIndex {
+    tree: RTree<FootprintEnvelope>,
+    footprints: Vec<MultiPolygon<f64>>,
+}
+
+impl SpatialIndex {
+    /// Build the index; entries are identified by their position in `packages`.
+    pub fn new(packages: &[Package]) -> Self {
+        let footprints: Vec<MultiPolygon<f64>> = packages
+            .iter()
+            .map(|pkg| pkg.geometry.to_multi_polygon())
+            .collect();
+        let envelopes = footprints
+            .iter()
+            .enumerate()
+            .filter_map(|(i, footprint)| {
+                let rect = footprint.bounding_rect()?;
+                Some(GeomWithData::new(
+                    Rectangle::from_corners(rect.min().into(), rect.max().into()),
+                    i,
+                ))
+            })
+            .collect();
+
+        Self {
+            tree: RTree::bulk_load(envelopes),
+            footprints,
+        }
+    }
+
+    /// Indices of packages whose footprint intersects `area`, in ascending order.
+    pub fn intersecting(&self, area: &Polygon<f64>) -> Vec<usize> {
+        let Some(rect) = area.bounding_rect() else {
+            return Vec::new();
+        };
+        let envelope = AABB::from_corners(rect.min().into(), rect.max().into());
+
+        let mut matches: Vec<usize> = self
+            .tree
+            .locate_in_envelope_intersecting(&envelope)
+            .map(|entry| entry.data)
+            .filter(|&i| self.footprints[i].intersects(area))
+            .collect();
+        matches.sort_unstable();
+        matches
+    }
+
+    /// Indices of packages whose footprint intersects the bounding box.
+    pub fn intersecting_bbox(&self, bbox: &BoundingBox) -> Vec<usize> {
+        let rect = Rect::new((bbox.xmin, bbox.ymin), (bbox.xmax, bbox.ymax));
+        self.intersecting(&rect.to_polygon())
+    }
+
+    /// Bounding box of all footprints, or `None` when the index is empty.
+    pub fn extent(&self) -> Option<BoundingBox> {
+        if self.tree.size() == 0 {
+            return None;
+        }
+        let envelope = self.tree.root().envelope();
+        let [xmin, ymin] = envelope.lower();
+        let [xmax, ymax] = envelope.upper();
+        Some(BoundingBox::new(xmin, ymin, xmax, ymax, 3857))
+    }
+
+    pub fn len(&self) -> usize {
+        self.footprints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.footprints.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_types::GeoJSONGeometry;
+    use crate::test_support::PackageBuilder;
+
+    fn package(name: &str, ring: Vec<[f64; 2]>) -> Package {
+        PackageBuilder::new(name)
+            .geometry(GeoJSONGeometry::Polygon(vec![ring
+                .into_iter()
+                .map(|p| p.to_vec())
+                .collect()]))
+            .build()
+    }
+
+    #[test]
+    fn test_exact_intersection_excludes_bbox_only_matches() {
+        // A triangle whose bounding box covers (8, 8) but whose footprint does not.
+        let triangle = package(
+            "triangle",
+            vec![[0.0, 0.0], [10.0, 0.0], [0.0, 10.0], [0.0, 0.0]],
+        );
+        let square = package(
+            "square",
+            vec![
+                [7.0, 7.0],
+                [12.0, 7.0],
+                [12.0, 12.0],
+                [7.0, 12.0],
+                [7.0, 7.0],
+            ],
+        );
+        let index = SpatialIndex::new(&[triangle, square]);
+
+        let corner = BoundingBox::new(8.0, 8.0, 9.0, 9.0, 3857);
+        assert_eq!(index.intersecting_bbox(&corner), vec![1]);
+
+        let origin = BoundingBox::new(1.0, 1.0, 2.0, 2.0, 3857);
+        assert_eq!(index.intersecting_bbox(&origin), vec![0]);
+    }
+
+    #[test]
+    fn test_touching_footprint_is_included() {
+        let square = package(
+            "square",
+            vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]],
+        );
+        let index = SpatialIndex::new(&[square]);
+
+        let adjacent = BoundingBox::new(1.0, 0.0, 2.0, 1.0, 3857);
+        assert_eq!(index.intersecting_bbox(&adjacent), vec![0]);
+
+        let apart = BoundingBox::new(1.5, 0.0, 2.0, 1.0, 3857);
+        assert!(index.intersecting_bbox(&apart).is_empty());
+    }
+}
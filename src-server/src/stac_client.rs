@@ -0,0 +1,506 @@
+//! Client for STAC API catalogs, such as NRCan CanElevation.
+//!
+//! Items are searched with `POST {api}/search` and mapped onto [`Package`]s,
+//! one per item, using the item's GeoTIFF/COG data asset as the download.
+//! Geometries are reprojected from WGS84 to Web Mercator like the rest of
+//! the package index.
+
+use async_trait::async_trait;
+use geo::GeodesicArea;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::api_types::{AcquisitionPeriod, BoundingBox, GeoJSONGeometry, Package, PackageFilter};
+use crate::package_client::PackageClientError;
+use crate::package_source::PackageSource;
+use crate::projection::{geometry_to_mercator, mercator_to_lon_lat};
+
+/// Items requested per search page.
+const PAGE_LIMIT: usize = 100;
+
+/// Upper bound on followed `next` links, in case a server loops.
+const MAX_PAGES: usize = 1000;
+
+/// Search parameters for the STAC item search endpoint.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StacSearch {
+    /// `[west, south, east, north]` in WGS84 degrees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<[f64; 4]>,
+    /// GeoJSON geometry in WGS84; used instead of `bbox` when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intersects: Option<GeoJSONGeometry>,
+    /// RFC 3339 datetime or interval, e.g. `2015-01-01T00:00:00Z/..`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ItemCollection {
+    #[serde(default)]
+    features: Vec<StacItem>,
+    #[serde(default)]
+    links: Vec<StacLink>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StacItem {
+    pub id: String,
+    #[serde(default)]
+    pub collection: Option<String>,
+    pub geometry: Option<GeoJSONGeometry>,
+    #[serde(default)]
+    pub properties: Map<String, Value>,
+    #[serde(default)]
+    pub assets: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StacLink {
+    rel: String,
+    href: String,
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default)]
+    body: Option<Map<String, Value>>,
+    #[serde(default)]
+    merge: bool,
+}
+
+/// A STAC API catalog used as a package source.
+#[derive(Debug, Clone)]
+pub struct StacClient {
+    client: Client,
+    api_url: String,
+    source_id: String,
+    collections: Vec<String>,
+    /// Preferred asset keys, tried in order before any GeoTIFF data asset.
+    asset_keys: Vec<String>,
+    datetime: Option<String>,
+}
+
+impl StacClient {
+    pub fn new(source_id: impl Into<String>, api_url: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            api_url: api_url.into().trim_end_matches('/').to_string(),
+            source_id: source_id.into(),
+            collections: Vec::new(),
+            asset_keys: Vec::new(),
+            datetime: None,
+        }
+    }
+
+    /// Restrict searches to these collection ids.
+    pub fn with_collections(mut self, collections: Vec<String>) -> Self {
+        self.collections = collections;
+        self
+    }
+
+    /// Prefer these asset keys (e.g. `"dtm"`) when picking the download.
+    pub fn with_asset_keys(mut self, asset_keys: Vec<String>) -> Self {
+        self.asset_keys = asset_keys;
+        self
+    }
+
+    /// Default datetime filter applied to searches that do not set one.
+    pub fn with_datetime(mut self, datetime: Option<String>) -> Self {
+        self.datetime = datetime;
+        self
+    }
+
+    /// Search items, following `next` links until all pages are read.
+    pub async fn search(&self, search: &StacSearch) -> Result<Vec<StacItem>, PackageClientError> {
+        let mut body = match serde_json::to_value(search)? {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        };
+        if body.contains_key("intersects") {
+            body.remove("bbox");
+        }
+        if !body.contains_key("datetime") {
+            if let Some(datetime) = &self.datetime {
+                body.insert("datetime".to_string(), Value::String(datetime.clone()));
+            }
+        }
+        if !self.collections.is_empty() {
+            body.insert(
+                "collections".to_string(),
+                serde_json::json!(self.collections),
+            );
+        }
+        body.insert("limit".to_string(), PAGE_LIMIT.into());
+
+        let mut request = self
+            .client
+            .post(format!("{}/search", self.api_url))
+            .json(&body);
+        let mut items = Vec::new();
+
+        for _ in 0..MAX_PAGES {
+            let page: ItemCollection = request.send().await?.error_for_status()?.json().await?;
+            items.extend(page.features);
+
+            let Some(next) = page.links.into_iter().find(|link| link.rel == "next") else {
+                break;
+            };
+            request = if next
+                .method
+                .as_deref()
+                .unwrap_or("GET")
+                .eq_ignore_ascii_case("POST")
+            {
+                let next_body = match next.body {
+                    Some(next_body) if next.merge => {
+                        let mut merged = body.clone();
+                        merged.extend(next_body);
+                        merged
+                    }
+                    Some(next_body) => next_body,
+                    None => body.clone(),
+                };
+                self.client.post(&next.href).json(&next_body)
+            } else {
+                self.client.get(&next.href)
+            };
+        }
+
+        println!("STAC search returned {} items", items.len());
+        Ok(items)
+    }
+
+    /// Convert an item to a package, or `None` if it has no usable geometry
+    /// or GeoTIFF asset.
+    pub fn item_to_package(&self, item: StacItem) -> Option<Package> {
+        let Some(geometry) = item.geometry else {
+            println!("Skipping STAC item '{}' - no geometry", item.id);
+            return None;
+        };
+        let Some((asset_key, asset)) = self.select_asset(&item.assets) else {
+            println!("Skipping STAC item '{}' - no GeoTIFF asset", item.id);
+            return None;
+        };
+        let download_url = asset.get("href")?.as_str()?.to_string();
+
+        let size_gb = asset
+            .get("file:size")
+            .and_then(Value::as_f64)
+            .map(|bytes| bytes / 1e9)
+            .unwrap_or(0.0);
+        let resolution = asset
+            .get("gsd")
+            .or_else(|| item.properties.get("gsd"))
+            .and_then(Value::as_f64)
+            .unwrap_or(0.0);
+        let acquisition_period = acquisition_period(&item.properties);
+        let year_range = acquisition_period.map(|period| {
+            if period.start_year == period.end_year {
+                period.start_year.to_string()
+            } else {
+                format!("{}-{}", period.start_year, period.end_year)
+            }
+        });
+        let coverage_km2 = geometry.to_multi_polygon().geodesic_area_unsigned() / 1_000_000.0;
+        let project = item
+            .collection
+            .clone()
+            .unwrap_or_else(|| self.source_id.clone());
+
+        Some(Package {
+            package_name: format!("{} ({})", item.id, asset_key),
+            size_gb,
+            resolution,
+            download_url,
+            project,
+            year_range,
+            acquisition_period,
+            source: self.source_id.clone(),
+            coverage_km2,
+            geometry: geometry_to_mercator(&geometry),
+        })
+    }
+
+    /// The configured `asset_keys` first, then a GeoTIFF data asset named
+    /// as a terrain model. Surface models are never picked implicitly.
+    fn select_asset<'a>(&self, assets: &'a Map<String, Value>) -> Option<(&'a str, &'a Value)> {
+        let preferred = self
+            .asset_keys
+            .iter()
+            .find_map(|key| assets.get_key_value(key.as_str()));
+        let data_assets = || {
+            assets.iter().filter(|(_, asset)| {
+                is_geotiff_asset(asset)
+                    && asset
+                        .get("roles")
+                        .and_then(Value::as_array)
+                        .is_none_or(|roles| roles.iter().any(|r| r == "data"))
+            })
+        };
+        preferred
+            .or_else(|| data_assets().find(|(key, asset)| names_match(key, asset, TERRAIN_NAMES)))
+            .or_else(|| data_assets().find(|(key, asset)| !names_match(key, asset, SURFACE_NAMES)))
+            .map(|(key, asset)| (key.as_str(), asset))
+    }
+
+    fn packages(&self, items: Vec<StacItem>, filter: &PackageFilter) -> Vec<Package> {
+        items
+            .into_iter()
+            .filter_map(|item| self.item_to_package(item))
+            .filter(|pkg| filter.matches(pkg))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl PackageSource for StacClient {
+    fn id(&self) -> &str {
+        &self.source_id
+    }
+
+    async fn query_by_extent(
+        &self,
+        bbox: &BoundingBox,
+        filter: &PackageFilter,
+    ) -> Result<Vec<Package>, PackageClientError> {
+        let bbox = if bbox.srid == 4326 {
+            [bbox.xmin, bbox.ymin, bbox.xmax, bbox.ymax]
+        } else {
+            let [west, south] = mercator_to_lon_lat(bbox.xmin, bbox.ymin);
+            let [east, north] = mercator_to_lon_lat(bbox.xmax, bbox.ymax);
+            [west, south, east, north]
+        };
+        let items = self
+            .search(&StacSearch {
+                bbox: Some(bbox),
+                ..Default::default()
+            })
+            .await?;
+        Ok(self.packages(items, filter))
+    }
+
+    async fn query_all(&self) -> Result<Vec<Package>, PackageClientError> {
+        let items = self.search(&StacSearch::default()).await?;
+        Ok(self.packages(items, &PackageFilter::default()))
+    }
+}
+
+/// Asset names that denote a bare-earth terrain model.
+const TERRAIN_NAMES: &[&str] = &["dtm", "terrain"];
+
+/// Asset names that denote surface or canopy models rather than terrain.
+const SURFACE_NAMES: &[&str] = &["dsm", "surface", "chm", "canopy"];
+
+/// Whether the asset key, title or a role contains one of `names`.
+fn names_match(key: &str, asset: &Value, names: &[&str]) -> bool {
+    let title = asset.get("title").and_then(Value::as_str).unwrap_or("");
+    let roles = asset
+        .get("roles")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str);
+    [key, title]
+        .into_iter()
+        .chain(roles)
+        .map(str::to_lowercase)
+        .any(|text| names.iter().any(|name| text.contains(name)))
+}
+
+fn is_geotiff_asset(asset: &Value) -> bool {
+    let media_type = asset.get("type").and_then(Value::as_str).unwrap_or("");
+    let href = asset.get("href").and_then(Value::as_str).unwrap_or("");
+    media_type.starts_with("image/tiff")
+        || media_type.starts_with("image/geotiff")
+        || href.ends_with(".tif")
+        || href.ends_with(".tiff")
+}
+
+/// Acquisition years from `start_datetime`/`end_datetime`, or `datetime`.
+fn acquisition_period(properties: &Map<String, Value>) -> Option<AcquisitionPeriod> {
+    let year = |key: &str| {
+        properties
+            .get(key)
+            .and_then(Value::as_str)
+            .and_then(|value| value.get(..4))
+            .and_then(|year| year.parse::<u32>().ok())
+    };
+    let start = year("start_datetime").or_else(|| year("datetime"))?;
+    let end = year("end_datetime").unwrap_or(start).max(start);
+    Some(AcquisitionPeriod {
+        start_year: start,
+        end_year: end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn item(id: &str, start: &str, end: &str) -> Value {
+        serde_json::json!({
+            "type": "Feature",
+            "stac_version": "1.0.0",
+            "id": id,
+            "collection": "hrdem-lidar",
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [[[-80.0, 43.0], [-79.9, 43.0], [-79.9, 43.1], [-80.0, 43.1], [-80.0, 43.0]]]
+            },
+            "bbox": [-80.0, 43.0, -79.9, 43.1],
+            "properties": {"datetime": null, "start_datetime": start, "end_datetime": end},
+            "assets": {
+                "thumbnail": {"href": format!("https://example.com/{}.png", id), "type": "image/png", "roles": ["thumbnail"]},
+                "dsm": {"href": format!("https://example.com/{}-dsm.tif", id), "type": "image/tiff; application=geotiff; profile=cloud-optimized", "roles": ["data"]},
+                "dtm": {
+                    "href": format!("https://example.com/{}-dtm.tif", id),
+                    "type": "image/tiff; application=geotiff; profile=cloud-optimized",
+                    "roles": ["data"],
+                    "gsd": 1.0,
+                    "file:size": 250_000_000
+                }
+            },
+            "links": []
+        })
+    }
+
+    /// Serve a two-page item search that records every request body.
+    async fn spawn_mock_stac() -> (String, Arc<Mutex<Vec<Value>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let next_href = format!("{}/search", base);
+
+        let app = axum::Router::new().route(
+            "/search",
+            axum::routing::post(move |axum::Json(body): axum::Json<Value>| {
+                let recorded = recorded.clone();
+                let next_href = next_href.clone();
+                async move {
+                    recorded.lock().unwrap().push(body.clone());
+                    if body.get("token").is_none() {
+                        axum::Json(serde_json::json!({
+                            "type": "FeatureCollection",
+                            "features": [item("tile-1", "2016-05-01T00:00:00Z", "2018-10-01T00:00:00Z")],
+                            "links": [{
+                                "rel": "next",
+                                "href": next_href,
+                                "method": "POST",
+                                "body": {"token": "page2"},
+                                "merge": true
+                            }]
+                        }))
+                    } else {
+                        axum::Json(serde_json::json!({
+                            "type": "FeatureCollection",
+                            "features": [item("tile-2", "2022-06-01T00:00:00Z", "2022-06-30T00:00:00Z")],
+                            "links": []
+                        }))
+                    }
+                }
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base, requests)
+    }
+
+    #[tokio::test]
+    async fn test_query_by_extent_against_mock_stac() {
+        let (base, requests) = spawn_mock_stac().await;
+        let client = StacClient::new("canelevation", base)
+            .with_collections(vec!["hrdem-lidar".to_string()])
+            .with_asset_keys(vec!["dtm".to_string()]);
+
+        let bbox = BoundingBox::new(-8_905_559.0, 5_311_971.0, -8_894_427.0, 5_327_240.0, 3857);
+        let packages = client
+            .query_by_extent(&bbox, &PackageFilter::default())
+            .await
+            .unwrap();
+
+        assert_eq!(packages.len(), 2);
+        let first = &packages[0];
+        assert_eq!(first.package_name, "tile-1 (dtm)");
+        assert_eq!(first.download_url, "https://example.com/tile-1-dtm.tif");
+        assert_eq!(first.source, "canelevation");
+        assert_eq!(first.project, "hrdem-lidar");
+        assert_eq!(first.year_range.as_deref(), Some("2016-2018"));
+        assert!((first.size_gb - 0.25).abs() < 1e-9);
+        assert_eq!(first.resolution, 1.0);
+        assert!(first.coverage_km2 > 80.0 && first.coverage_km2 < 100.0);
+        let GeoJSONGeometry::Polygon(rings) = &first.geometry else {
+            panic!("expected a polygon");
+        };
+        assert!((rings[0][0][0] - -8_905_559.26).abs() < 1.0);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let sent_bbox = requests[0]["bbox"].as_array().unwrap();
+        assert!((sent_bbox[0].as_f64().unwrap() - -80.0).abs() < 1e-4);
+        assert!((sent_bbox[1].as_f64().unwrap() - 43.0).abs() < 1e-4);
+        assert_eq!(
+            requests[0]["collections"],
+            serde_json::json!(["hrdem-lidar"])
+        );
+        // The next link's body is merged into the original search.
+        assert_eq!(requests[1]["token"], "page2");
+        assert_eq!(
+            requests[1]["collections"],
+            serde_json::json!(["hrdem-lidar"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_intersects_search_and_filters() {
+        let (base, requests) = spawn_mock_stac().await;
+        let client = StacClient::new("canelevation", base);
+
+        let items = client
+            .search(&StacSearch {
+                bbox: Some([0.0, 0.0, 1.0, 1.0]),
+                intersects: Some(GeoJSONGeometry::Point(vec![-79.95, 43.05])),
+                datetime: Some("2020-01-01T00:00:00Z/..".to_string()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 2);
+        {
+            let requests = requests.lock().unwrap();
+            assert!(requests[0].get("bbox").is_none());
+            assert_eq!(requests[0]["intersects"]["type"], "Point");
+            assert_eq!(requests[0]["datetime"], "2020-01-01T00:00:00Z/..");
+        }
+
+        let filter = PackageFilter {
+            min_year: Some(2020),
+            ..Default::default()
+        };
+        let packages = client.packages(items, &filter);
+        assert_eq!(packages.len(), 1);
+        // Without preferred keys, the terrain model wins over the DSM.
+        assert_eq!(
+            packages[0].download_url,
+            "https://example.com/tile-2-dtm.tif"
+        );
+    }
+
+    #[test]
+    fn test_surface_models_are_not_picked_implicitly() {
+        let client = StacClient::new("hrdem", "https://example.com/stac");
+        let geotiff = |href: &str| serde_json::json!({"href": href, "type": "image/tiff; application=geotiff", "roles": ["data"]});
+
+        let mut assets = Map::new();
+        assets.insert("dsm".to_string(), geotiff("https://example.com/dsm.tif"));
+        assert!(client.select_asset(&assets).is_none());
+
+        assets.insert(
+            "elevation".to_string(),
+            geotiff("https://example.com/elevation.tif"),
+        );
+        assert_eq!(client.select_asset(&assets).unwrap().0, "elevation");
+
+        let client = client.with_asset_keys(vec!["dsm".to_string()]);
+        assert_eq!(client.select_asset(&assets).unwrap().0, "dsm");
+    }
+}
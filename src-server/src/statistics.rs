@@ -0,0 +1,520 @@
+//! Zonal statistics of a merged raster.
+//!
+//! The zone is cut from band 1 with `gdalwarp -cutline -crop_to_cutline`,
+//! which keeps the source pixel grid, and written as raw ENVI. The values
+//! are then streamed twice: once for count, range, mean and standard
+//! deviation, and once for the histogram and percentiles. Nodata and NaN
+//! pixels are skipped, so min, max, mean and standard deviation match
+//! `gdalinfo -stats` on the same pixels.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde_json::json;
+
+use crate::api_types::{
+    GeoJSONGeometry, Histogram, PercentileValue, StatisticsRequest, ZonalStatistics,
+};
+use crate::disk_space::ensure_space;
+use crate::processing::{format_nodata, inspect_raster, nodata_equals, ProcessingError};
+use crate::projection::{transform_geometry, Crs};
+
+pub const DEFAULT_HISTOGRAM_BINS: usize = 256;
+pub const MAX_HISTOGRAM_BINS: usize = 10_000;
+pub const DEFAULT_PERCENTILES: [f64; 5] = [5.0, 25.0, 50.0, 75.0, 95.0];
+
+/// Up to this many values, percentiles are exact; beyond it they are read
+/// from a fine histogram and are accurate to `(max - min) / PERCENTILE_BINS`.
+const EXACT_PERCENTILE_LIMIT: u64 = 16 * 1024 * 1024;
+const PERCENTILE_BINS: usize = 1 << 16;
+
+/// Values read per chunk while streaming the raw raster.
+const CHUNK_VALUES: usize = 1 << 16;
+
+/// Compute zonal statistics of band 1 of `raster`.
+pub async fn zonal_statistics(
+    raster: &Path,
+    request: &StatisticsRequest,
+) -> Result<ZonalStatistics, ProcessingError> {
+    let polygon = match &request.polygon {
+        Some(polygon) => {
+            if !matches!(
+                polygon,
+                GeoJSONGeometry::Polygon(_) | GeoJSONGeometry::MultiPolygon(_)
+            ) {
+                return Err(ProcessingError::InvalidRequest(
+                    "Statistics zone must be a Polygon or MultiPolygon".to_string(),
+                ));
+            }
+            let crs = Crs::from_epsg(request.srid)
+                .map_err(|e| ProcessingError::InvalidRequest(e.to_string()))?;
+            Some(transform_geometry(polygon, crs, Crs::Wgs84))
+        }
+        None => None,
+    };
+    let bins = request
+        .histogram_bins
+        .unwrap_or(DEFAULT_HISTOGRAM_BINS)
+        .clamp(1, MAX_HISTOGRAM_BINS);
+    let percentiles: Vec<f64> = request
+        .percentiles
+        .clone()
+        .unwrap_or_else(|| DEFAULT_PERCENTILES.to_vec())
+        .into_iter()
+        .filter(|p| (0.0..=100.0).contains(p))
+        .collect();
+
+    let raster = raster.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        raster_statistics(&raster, polygon.as_ref(), bins, &percentiles)
+    })
+    .await
+    .map_err(|e| ProcessingError::GdalError(e.to_string()))?
+}
+
+fn raster_statistics(
+    raster: &Path,
+    polygon: Option<&GeoJSONGeometry>,
+    bins: usize,
+    percentiles: &[f64],
+) -> Result<ZonalStatistics, ProcessingError> {
+    let info = inspect_raster(&raster.to_string_lossy())?;
+    let stem = raster.with_extension(format!("stats-{}", uuid::Uuid::new_v4().simple()));
+    let with_ext = |ext: &str| PathBuf::from(format!("{}.{}", stem.display(), ext));
+    let (raw, header, cutline) = (with_ext("bin"), with_ext("hdr"), with_ext("geojson"));
+
+    // The raw copy is at most the whole band as Float64.
+    if let Some([columns, rows]) = info.size {
+        let dir = raster.parent().unwrap_or(Path::new("."));
+        ensure_space(dir, columns * rows * 8)?;
+    }
+
+    let result = (|| {
+        export_band(raster, &raw, &cutline, polygon, info.nodata)?;
+        let envi = EnviHeader::parse(&std::fs::read_to_string(&header)?)
+            .ok_or_else(|| ProcessingError::GdalError("Unreadable ENVI header".to_string()))?;
+        let values = envi.columns * envi.rows;
+        let for_each_chunk = |visit: &mut dyn FnMut(&[f64])| read_raw(&raw, &envi, values, visit);
+        Ok(compute_statistics(
+            for_each_chunk,
+            info.nodata,
+            bins,
+            percentiles,
+        )?)
+    })();
+
+    for path in [&raw, &header, &cutline, &with_ext("bin.aux.xml")] {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+/// Write band 1, cut to `polygon` when given, as raw ENVI at `raw`.
+fn export_band(
+    raster: &Path,
+    raw: &Path,
+    cutline: &Path,
+    polygon: Option<&GeoJSONGeometry>,
+    nodata: Option<f64>,
+) -> Result<(), ProcessingError> {
+    let mut command;
+    match polygon {
+        Some(polygon) => {
+            let feature = json!({
+                "type": "FeatureCollection",
+                "features": [{"type": "Feature", "properties": {}, "geometry": polygon}],
+            });
+            std::fs::write(cutline, feature.to_string())?;
+            command = Command::new("gdalwarp");
+            command
+                .args(["-q", "-of", "ENVI", "-ot", "Float64", "-r", "near"])
+                .arg("-cutline")
+                .arg(cutline)
+                .arg("-crop_to_cutline");
+            // Pixels outside the polygon and source nodata both become NaN,
+            // which the statistics always skip.
+            if let Some(nodata) = nodata {
+                command.args(["-srcnodata", &format_nodata(nodata)]);
+            }
+            command.args(["-dstnodata", "nan"]);
+        }
+        None => {
+            command = Command::new("gdal_translate");
+            command.args(["-q", "-of", "ENVI", "-ot", "Float64", "-b", "1"]);
+        }
+    }
+    let output = command.arg(raster).arg(raw).output()?;
+    if !output.status.success() {
+        return Err(ProcessingError::GdalError(format!(
+            "Exporting statistics zone failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct EnviHeader {
+    columns: u64,
+    rows: u64,
+    big_endian: bool,
+}
+
+impl EnviHeader {
+    fn parse(text: &str) -> Option<Self> {
+        let field = |name: &str| {
+            text.lines().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == name).then(|| value.trim().to_string())
+            })
+        };
+        // ENVI data type 5 is Float64.
+        if field("data type")? != "5" {
+            return None;
+        }
+        Some(Self {
+            columns: field("samples")?.parse().ok()?,
+            rows: field("lines")?.parse().ok()?,
+            big_endian: field("byte order").as_deref() == Some("1"),
+        })
+    }
+}
+
+/// Stream the first `values` Float64 values of a raw file in chunks.
+fn read_raw(
+    path: &Path,
+    header: &EnviHeader,
+    values: u64,
+    visit: &mut dyn FnMut(&[f64]),
+) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut bytes = vec![0u8; CHUNK_VALUES * 8];
+    let mut chunk = Vec::with_capacity(CHUNK_VALUES);
+    let mut remaining = values;
+    while remaining > 0 {
+        let n = remaining.min(CHUNK_VALUES as u64) as usize;
+        reader.read_exact(&mut bytes[..n * 8])?;
+        chunk.clear();
+        chunk.extend(bytes[..n * 8].chunks_exact(8).map(|b| {
+            let b: [u8; 8] = b.try_into().expect("8 byte chunk");
+            if header.big_endian {
+                f64::from_be_bytes(b)
+            } else {
+                f64::from_le_bytes(b)
+            }
+        }));
+        visit(&chunk);
+        remaining -= n as u64;
+    }
+    Ok(())
+}
+
+/// Statistics of the values produced by `for_each_chunk`, which is called
+/// twice and must produce the same values both times.
+fn compute_statistics(
+    for_each_chunk: impl Fn(&mut dyn FnMut(&[f64])) -> io::Result<()>,
+    nodata: Option<f64>,
+    bins: usize,
+    percentiles: &[f64],
+) -> io::Result<ZonalStatistics> {
+    let is_valid = |v: f64| !v.is_nan() && !nodata.is_some_and(|nodata| nodata_equals(v, nodata));
+
+    // Pass 1: count, range, and Welford's running mean and variance.
+    let mut count = 0u64;
+    let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+    let (mut mean, mut m2) = (0.0f64, 0.0f64);
+    for_each_chunk(&mut |chunk| {
+        for &v in chunk {
+            if !is_valid(v) {
+                continue;
+            }
+            count += 1;
+            min = min.min(v);
+            max = max.max(v);
+            let delta = v - mean;
+            mean += delta / count as f64;
+            m2 += delta * (v - mean);
+        }
+    })?;
+
+    if count == 0 {
+        return Ok(ZonalStatistics {
+            count,
+            min: None,
+            max: None,
+            mean: None,
+            std_dev: None,
+            percentiles: Vec::new(),
+            histogram: Histogram::default(),
+        });
+    }
+
+    // Pass 2: histogram, and either every value or a fine histogram for percentiles.
+    let bucket = |v: f64, n: usize| {
+        if max > min {
+            (((v - min) / (max - min) * n as f64) as usize).min(n - 1)
+        } else {
+            0
+        }
+    };
+    let exact = count <= EXACT_PERCENTILE_LIMIT;
+    let mut counts = vec![0u64; bins];
+    let mut fine = vec![0u64; if exact { 0 } else { PERCENTILE_BINS }];
+    let mut all = Vec::with_capacity(if exact { count as usize } else { 0 });
+    for_each_chunk(&mut |chunk| {
+        for &v in chunk.iter().filter(|&&v| is_valid(v)) {
+            counts[bucket(v, bins)] += 1;
+            if exact {
+                all.push(v);
+            } else {
+                fine[bucket(v, PERCENTILE_BINS)] += 1;
+            }
+        }
+    })?;
+
+    let percentiles = percentiles
+        .iter()
+        .map(|&percentile| {
+            let rank = percentile / 100.0 * (count - 1) as f64;
+            let value = if exact {
+                exact_percentile(&mut all, rank)
+            } else {
+                histogram_percentile(&fine, min, max, rank)
+            };
+            PercentileValue { percentile, value }
+        })
+        .collect();
+
+    Ok(ZonalStatistics {
+        count,
+        min: Some(min),
+        max: Some(max),
+        mean: Some(mean),
+        std_dev: Some((m2 / count as f64).sqrt()),
+        percentiles,
+        histogram: Histogram { min, max, counts },
+    })
+}
+
+/// Value at fractional `rank` of the sorted values, interpolating between neighbours.
+fn exact_percentile(values: &mut [f64], rank: f64) -> f64 {
+    let below = rank.floor() as usize;
+    let fraction = rank - below as f64;
+    let (_, &mut low, above) = values.select_nth_unstable_by(below, f64::total_cmp);
+    if fraction == 0.0 || above.is_empty() {
+        return low;
+    }
+    let high = above.iter().copied().fold(f64::INFINITY, f64::min);
+    low + (high - low) * fraction
+}
+
+/// Value at fractional `rank`, spreading each bucket's values evenly across it.
+fn histogram_percentile(counts: &[u64], min: f64, max: f64, rank: f64) -> f64 {
+    let width = (max - min) / counts.len() as f64;
+    let mut seen = 0u64;
+    for (i, &n) in counts.iter().enumerate() {
+        if n > 0 && (seen + n) as f64 > rank {
+            let within = (rank - seen as f64 + 0.5) / n as f64;
+            return (min + (i as f64 + within) * width).clamp(min, max);
+        }
+        seen += n;
+    }
+    max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statistics_of(values: &[f64], nodata: Option<f64>, bins: usize) -> ZonalStatistics {
+        compute_statistics(
+            |visit| {
+                // Uneven chunks, as when streaming a file.
+                for chunk in values.chunks(3) {
+                    visit(chunk);
+                }
+                Ok(())
+            },
+            nodata,
+            bins,
+            &[0.0, 50.0, 90.0, 100.0],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_statistics_skip_nodata_and_match_gdalinfo() {
+        // `gdalinfo -stats` skips nodata and NaN and reports the population
+        // standard deviation: MIN=100, MAX=110, MEAN=104.6, STDDEV=3.040.
+        let values = [
+            100.0,
+            101.0,
+            102.0,
+            -9999.0,
+            103.0,
+            104.0,
+            105.0,
+            106.0,
+            f64::NAN,
+            107.0,
+            108.0,
+            110.0,
+        ];
+        let stats = statistics_of(&values, Some(-9999.0), 5);
+        assert_eq!(stats.count, 10);
+        assert_eq!(stats.min, Some(100.0));
+        assert_eq!(stats.max, Some(110.0));
+        assert!((stats.mean.unwrap() - 104.6).abs() < 1e-12);
+        assert!((stats.std_dev.unwrap() - 9.24f64.sqrt()).abs() < 1e-12);
+
+        assert_eq!(stats.histogram.counts, vec![2, 2, 2, 2, 2]);
+        assert_eq!(stats.histogram.counts.iter().sum::<u64>(), stats.count);
+
+        let expected = [100.0, 104.5, 108.2, 110.0];
+        for (p, expected) in stats.percentiles.iter().zip(expected) {
+            assert!((p.value - expected).abs() < 1e-9, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn test_statistics_of_empty_zone() {
+        let stats = statistics_of(&[-9999.0, -9999.0], Some(-9999.0), 4);
+        assert_eq!(stats.count, 0);
+        assert_eq!(stats.mean, None);
+        assert!(stats.histogram.counts.is_empty());
+    }
+
+    #[test]
+    fn test_histogram_percentile_is_close_to_exact() {
+        let mut values: Vec<f64> = (0..10_000)
+            .map(|i| (i as f64 * 0.37).sin() * 50.0)
+            .collect();
+        let (min, max) = values
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+                (lo.min(v), hi.max(v))
+            });
+        let mut fine = vec![0u64; PERCENTILE_BINS];
+        for &v in &values {
+            fine[(((v - min) / (max - min) * PERCENTILE_BINS as f64) as usize)
+                .min(PERCENTILE_BINS - 1)] += 1;
+        }
+        for percentile in [5.0, 50.0, 95.0] {
+            let rank = percentile / 100.0 * (values.len() - 1) as f64;
+            let approx = histogram_percentile(&fine, min, max, rank);
+            // Within a bin of the two values the exact percentile interpolates between.
+            let width = (max - min) / PERCENTILE_BINS as f64;
+            let below = exact_percentile(&mut values, rank.floor());
+            let above = exact_percentile(&mut values, rank.ceil());
+            assert!(approx >= below - width && approx <= above + width);
+        }
+    }
+
+    #[test]
+    fn test_parse_envi_header() {
+        let header = "ENVI\nsamples = 640\nlines   = 480\nbands   = 1\nheader offset = 0\nfile type = ENVI Standard\ndata type = 5\ninterleave = bsq\nbyte order = 0\n";
+        assert_eq!(
+            EnviHeader::parse(header),
+            Some(EnviHeader {
+                columns: 640,
+                rows: 480,
+                big_endian: false
+            })
+        );
+        assert_eq!(EnviHeader::parse(&header.replace("= 5", "= 4")), None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_zone_is_a_request_error() {
+        let request = StatisticsRequest {
+            polygon: Some(GeoJSONGeometry::Point(vec![0.0, 0.0])),
+            ..Default::default()
+        };
+        let err = zonal_statistics(Path::new("/nonexistent.tif"), &request)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProcessingError::InvalidRequest(_)));
+    }
+
+    /// `[minimum, maximum, mean, stdDev]` of band 1 from `gdalinfo -stats -json`.
+    fn gdalinfo_stats(path: &Path) -> [f64; 4] {
+        let output = Command::new("gdalinfo")
+            .args(["-stats", "-json"])
+            .arg(path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let band = &info["bands"][0];
+        ["minimum", "maximum", "mean", "stdDev"].map(|key| band[key].as_f64().unwrap())
+    }
+
+    fn assert_matches_gdalinfo(stats: &ZonalStatistics, expected: [f64; 4]) {
+        let actual = [stats.min, stats.max, stats.mean, stats.std_dev].map(Option::unwrap);
+        for (actual, expected) in actual.into_iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() <= 1e-9 * expected.abs().max(1.0),
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    // Requires the GDAL command line tools.
+    // Run with: cargo test -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_raster_statistics_match_gdalinfo_stats() {
+        let dir = std::env::temp_dir().join(format!("dtm-stats-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // A 4x3 grid of 10 m cells from (0, 0), with two nodata cells.
+        let grid = dir.join("grid.asc");
+        std::fs::write(
+            &grid,
+            "ncols 4\nnrows 3\nxllcorner 0\nyllcorner 0\ncellsize 10\nNODATA_value -9999\n\
+             100.5 101.25 102 -9999\n103 104.75 105 106.5\n107 108.25 110 -9999\n",
+        )
+        .unwrap();
+        let translate = |args: &[&str], input: &Path, output: &Path| {
+            let status = Command::new("gdal_translate")
+                .arg("-q")
+                .args(args)
+                .arg(input)
+                .arg(output)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        let raster = dir.join("grid.tif");
+        translate(&["-ot", "Float64", "-a_srs", "EPSG:3857"], &grid, &raster);
+        let left = dir.join("left.tif");
+        translate(&["-srcwin", "0", "0", "2", "3"], &raster, &left);
+
+        let whole = zonal_statistics(&raster, &StatisticsRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(whole.count, 10);
+        assert_matches_gdalinfo(&whole, gdalinfo_stats(&raster));
+
+        // The two left columns, selected by pixel centre.
+        let request = StatisticsRequest {
+            polygon: Some(GeoJSONGeometry::Polygon(vec![vec![
+                vec![1.0, 1.0],
+                vec![19.0, 1.0],
+                vec![19.0, 29.0],
+                vec![1.0, 29.0],
+                vec![1.0, 1.0],
+            ]])),
+            srid: 3857,
+            ..Default::default()
+        };
+        let zone = zonal_statistics(&raster, &request).await.unwrap();
+        assert_eq!(zone.count, 6);
+        assert_matches_gdalinfo(&zone, gdalinfo_stats(&left));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,70 @@
+//! Builders shared by the unit tests of several modules.
+
+use crate::api_types::{AcquisitionPeriod, GeoJSONGeometry, Package};
+
+/// Test packages: a 1 GB, 0.5 m package of the "Test" project with no
+/// footprint, unless overridden.
+pub(crate) struct PackageBuilder(Package);
+
+impl PackageBuilder {
+    pub(crate) fn new(package_name: &str) -> Self {
+        Self(Package {
+            package_name: package_name.to_string(),
+            size_gb: 1.0,
+            resolution: 0.5,
+            download_url: format!("https://example.com/{}.zip", package_name),
+            project: "Test".to_string(),
+            year_range: None,
+            acquisition_period: None,
+            source: "test".to_string(),
+            coverage_km2: 1.0,
+            geometry: GeoJSONGeometry::Polygon(vec![]),
+        })
+    }
+
+    pub(crate) fn size_gb(mut self, size_gb: f64) -> Self {
+        self.0.size_gb = size_gb;
+        self
+    }
+
+    pub(crate) fn resolution(mut self, resolution: f64) -> Self {
+        self.0.resolution = resolution;
+        self
+    }
+
+    pub(crate) fn download_url(mut self, download_url: &str) -> Self {
+        self.0.download_url = download_url.to_string();
+        self
+    }
+
+    pub(crate) fn project(mut self, project: &str) -> Self {
+        self.0.project = project.to_string();
+        self
+    }
+
+    /// Sets `year_range` and the `acquisition_period` parsed from it.
+    pub(crate) fn years(mut self, years: &str) -> Self {
+        self.0.year_range = Some(years.to_string());
+        self.0.acquisition_period = AcquisitionPeriod::parse(years);
+        self
+    }
+
+    pub(crate) fn source(mut self, source: &str) -> Self {
+        self.0.source = source.to_string();
+        self
+    }
+
+    pub(crate) fn coverage_km2(mut self, coverage_km2: f64) -> Self {
+        self.0.coverage_km2 = coverage_km2;
+        self
+    }
+
+    pub(crate) fn geometry(mut self, geometry: GeoJSONGeometry) -> Self {
+        self.0.geometry = geometry;
+        self
+    }
+
+    pub(crate) fn build(self) -> Package {
+        self.0
+    }
+}
@@ -0,0 +1,509 @@
+//! XYZ preview tiles of finished job outputs.
+//!
+//! Tiles are rendered on demand with `gdalwarp` into Web Mercator, which
+//! reads from the COG overview closest to the tile resolution, and then
+//! shaded with `gdaldem`. Rendered PNGs are kept in a size-bounded LRU cache.
+//! Only a few tiles are rendered at a time, and tiles outside the output are
+//! answered with an empty tile without running GDAL.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use axum::body::Bytes;
+use serde_json::Value;
+use tokio::sync::Semaphore;
+
+use crate::api_types::TileStyle;
+use crate::processing::{format_nodata, inspect_raster, ProcessingError};
+use crate::projection::{transform_point, Crs};
+
+pub const TILE_SIZE: u32 = 256;
+
+/// Deepest zoom level served; tiles there are a few centimetres across.
+pub const MAX_ZOOM: u8 = 24;
+
+/// Tile cache size unless `DTM_TILE_CACHE_MB` is set.
+const DEFAULT_CACHE_MB: usize = 64;
+
+/// Fully transparent 256 px PNG: 1-bit grayscale whose only value is marked
+/// transparent by a `tRNS` chunk.
+const EMPTY_TILE: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x74, 0x09, 0x95,
+    0xcb, 0x00, 0x00, 0x00, 0x02, 0x74, 0x52, 0x4e, 0x53, 0x00, 0x00, 0x76, 0x93, 0xcd, 0x38, 0x00,
+    0x00, 0x00, 0x1f, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0xed, 0xc1, 0x01, 0x0d, 0x00, 0x00, 0x00,
+    0xc2, 0xa0, 0xf7, 0x4f, 0x6d, 0x0e, 0x37, 0xa0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xbe, 0x0d, 0x21, 0x00, 0x00, 0x01, 0x60, 0xe4, 0x9d, 0x97, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45,
+    0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+];
+
+/// Half the Web Mercator world width in metres.
+const ORIGIN_SHIFT: f64 = 20_037_508.342_789_244;
+
+/// Color stops from low to high ground, as fractions of the elevation range.
+const COLOR_RAMP: [(f64, [u8; 3]); 5] = [
+    (0.0, [38, 115, 0]),
+    (0.25, [152, 194, 61]),
+    (0.5, [245, 230, 145]),
+    (0.75, [168, 112, 0]),
+    (1.0, [255, 255, 255]),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct TileKey {
+    download_id: String,
+    style: TileStyle,
+    z: u8,
+    x: u32,
+    y: u32,
+}
+
+#[derive(Default)]
+struct CacheState {
+    tiles: HashMap<TileKey, (Bytes, u64)>,
+    /// Tiles by last use, oldest first.
+    recency: BTreeMap<u64, TileKey>,
+    bytes: usize,
+    tick: u64,
+    /// Elevation range per output, for the color ramp.
+    ranges: HashMap<String, (f64, f64)>,
+    /// Web Mercator extent per output; `None` when GDAL reported none.
+    extents: HashMap<String, Option<[f64; 4]>>,
+}
+
+/// Rendered tiles, evicted least recently used first once over `max_bytes`.
+pub struct TileCache {
+    max_bytes: usize,
+    state: Mutex<CacheState>,
+    /// Limits concurrent renders, each of which spawns GDAL processes.
+    renders: Semaphore,
+}
+
+impl TileCache {
+    pub fn new(max_bytes: usize) -> Self {
+        let renders = std::thread::available_parallelism().map_or(2, |n| n.get());
+        Self {
+            max_bytes,
+            state: Mutex::new(CacheState::default()),
+            renders: Semaphore::new(renders),
+        }
+    }
+
+    /// Cache sized by `DTM_TILE_CACHE_MB`.
+    pub fn from_env() -> Self {
+        let mb = std::env::var("DTM_TILE_CACHE_MB")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .unwrap_or(DEFAULT_CACHE_MB);
+        Self::new(mb * 1024 * 1024)
+    }
+
+    fn get(&self, key: &TileKey) -> Option<Bytes> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let (tile, last_used) = state.tiles.get_mut(key)?;
+        let tile = tile.clone();
+        let previous = std::mem::replace(last_used, tick);
+        state.recency.remove(&previous);
+        state.recency.insert(tick, key.clone());
+        Some(tile)
+    }
+
+    fn insert(&self, key: TileKey, tile: Bytes) {
+        if tile.len() > self.max_bytes {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        state.bytes += tile.len();
+        if let Some((old, last_used)) = state.tiles.insert(key.clone(), (tile, tick)) {
+            state.bytes -= old.len();
+            state.recency.remove(&last_used);
+        }
+        state.recency.insert(tick, key);
+
+        while state.bytes > self.max_bytes {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = state.tiles.remove(&oldest) {
+                state.bytes -= evicted.len();
+            }
+        }
+    }
+
+    /// Drop everything cached for a job, e.g. once its output expired.
+    pub fn remove_job(&self, download_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<_> = state
+            .tiles
+            .keys()
+            .filter(|key| key.download_id == download_id)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some((tile, last_used)) = state.tiles.remove(&key) {
+                state.bytes -= tile.len();
+                state.recency.remove(&last_used);
+            }
+        }
+        state.ranges.remove(download_id);
+        state.extents.remove(download_id);
+    }
+
+    /// Bytes of tiles currently cached.
+    pub fn size(&self) -> usize {
+        self.state.lock().unwrap().bytes
+    }
+
+    /// Return the tile from the cache, rendering it from `output_path` if needed.
+    pub async fn tile(
+        &self,
+        download_id: &str,
+        output_path: &Path,
+        style: TileStyle,
+        z: u8,
+        x: u32,
+        y: u32,
+    ) -> Result<Bytes, ProcessingError> {
+        let key = TileKey {
+            download_id: download_id.to_string(),
+            style,
+            z,
+            x,
+            y,
+        };
+        if let Some(tile) = self.get(&key) {
+            return Ok(tile);
+        }
+
+        let _permit = self
+            .renders
+            .acquire()
+            .await
+            .map_err(|e| ProcessingError::GdalError(e.to_string()))?;
+        // Another request may have rendered the tile while this one waited.
+        if let Some(tile) = self.get(&key) {
+            return Ok(tile);
+        }
+
+        let bounds = tile_bounds(z, x, y);
+        if let Some(extent) = self.output_extent(download_id, output_path).await? {
+            if !overlaps(bounds, extent) {
+                return Ok(Bytes::from_static(EMPTY_TILE));
+            }
+        }
+
+        let range = match style {
+            TileStyle::Hillshade => None,
+            TileStyle::Color => Some(self.elevation_range(download_id, output_path).await?),
+        };
+        let output_path = output_path.to_path_buf();
+        let tile =
+            tokio::task::spawn_blocking(move || render_tile(&output_path, bounds, style, range))
+                .await
+                .map_err(|e| ProcessingError::GdalError(e.to_string()))??;
+
+        let tile = Bytes::from(tile);
+        self.insert(key, tile.clone());
+        Ok(tile)
+    }
+
+    async fn elevation_range(
+        &self,
+        download_id: &str,
+        output_path: &Path,
+    ) -> Result<(f64, f64), ProcessingError> {
+        if let Some(range) = self.state.lock().unwrap().ranges.get(download_id) {
+            return Ok(*range);
+        }
+        let path = output_path.to_path_buf();
+        let range = tokio::task::spawn_blocking(move || approx_elevation_range(&path))
+            .await
+            .map_err(|e| ProcessingError::GdalError(e.to_string()))??;
+        self.state
+            .lock()
+            .unwrap()
+            .ranges
+            .insert(download_id.to_string(), range);
+        Ok(range)
+    }
+
+    async fn output_extent(
+        &self,
+        download_id: &str,
+        output_path: &Path,
+    ) -> Result<Option<[f64; 4]>, ProcessingError> {
+        if let Some(extent) = self.state.lock().unwrap().extents.get(download_id) {
+            return Ok(*extent);
+        }
+        let path = output_path.to_path_buf();
+        let extent = tokio::task::spawn_blocking(move || web_mercator_extent(&path))
+            .await
+            .map_err(|e| ProcessingError::GdalError(e.to_string()))??;
+        self.state
+            .lock()
+            .unwrap()
+            .extents
+            .insert(download_id.to_string(), extent);
+        Ok(extent)
+    }
+}
+
+/// Whether two `[min_x, min_y, max_x, max_y]` boxes share some area.
+fn overlaps(a: [f64; 4], b: [f64; 4]) -> bool {
+    a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3]
+}
+
+/// Whether `x`/`y` address a tile at zoom `z`.
+pub fn valid_tile(z: u8, x: u32, y: u32) -> bool {
+    z <= MAX_ZOOM && u64::from(x) < 1u64 << z && u64::from(y) < 1u64 << z
+}
+
+/// Web Mercator bounds `[min_x, min_y, max_x, max_y]` of an XYZ tile.
+pub fn tile_bounds(z: u8, x: u32, y: u32) -> [f64; 4] {
+    let size = 2.0 * ORIGIN_SHIFT / f64::from(1u32 << z);
+    let min_x = -ORIGIN_SHIFT + f64::from(x) * size;
+    let max_y = ORIGIN_SHIFT - f64::from(y) * size;
+    [min_x, max_y - size, min_x + size, max_y]
+}
+
+/// Elevation range from `gdalinfo -approx_stats`, which reads an overview.
+fn approx_elevation_range(path: &Path) -> Result<(f64, f64), ProcessingError> {
+    let output = Command::new("gdalinfo")
+        .arg("-json")
+        .arg("-approx_stats")
+        .arg(path)
+        .output()?;
+    if !output.status.success() {
+        return Err(ProcessingError::GdalError(format!(
+            "gdalinfo failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    parse_band_range(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+        ProcessingError::GdalError("gdalinfo output missing band statistics".to_string())
+    })
+}
+
+/// Web Mercator extent of the raster at `path`, from `gdalinfo -json`.
+fn web_mercator_extent(path: &Path) -> Result<Option<[f64; 4]>, ProcessingError> {
+    let output = Command::new("gdalinfo").arg("-json").arg(path).output()?;
+    if !output.status.success() {
+        return Err(ProcessingError::GdalError(format!(
+            "gdalinfo failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(parse_wgs84_extent(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Envelope of gdalinfo's `wgs84Extent` polygon in Web Mercator, padded by
+/// 1% on each side as the raster's edges may bow between its corners.
+fn parse_wgs84_extent(gdalinfo_json: &str) -> Option<[f64; 4]> {
+    let value: Value = serde_json::from_str(gdalinfo_json).ok()?;
+    let ring = value
+        .get("wgs84Extent")?
+        .get("coordinates")?
+        .as_array()?
+        .first()?
+        .as_array()?;
+    let mut extent = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+    for position in ring {
+        let position = position.as_array()?;
+        let (lon, lat) = (position.first()?.as_f64()?, position.get(1)?.as_f64()?);
+        let [x, y] = transform_point(Crs::Wgs84, Crs::WebMercator, lon, lat);
+        extent = [
+            extent[0].min(x),
+            extent[1].min(y),
+            extent[2].max(x),
+            extent[3].max(y),
+        ];
+    }
+    if extent[0] > extent[2] {
+        return None;
+    }
+    let (pad_x, pad_y) = (
+        (extent[2] - extent[0]) * 0.01,
+        (extent[3] - extent[1]) * 0.01,
+    );
+    Some([
+        extent[0] - pad_x,
+        extent[1] - pad_y,
+        extent[2] + pad_x,
+        extent[3] + pad_y,
+    ])
+}
+
+fn parse_band_range(gdalinfo_json: &str) -> Option<(f64, f64)> {
+    let value: Value = serde_json::from_str(gdalinfo_json).ok()?;
+    let band = value.get("bands")?.as_array()?.first()?;
+    let min = band.get("minimum")?.as_f64()?;
+    let max = band.get("maximum")?.as_f64()?;
+    Some((min, max))
+}
+
+/// `gdaldem color-relief` color file spanning `min..max`, nodata transparent.
+fn color_ramp(min: f64, max: f64) -> String {
+    let span = (max - min).max(f64::EPSILON);
+    let mut ramp = String::from("nv 0 0 0 0\n");
+    for (fraction, [r, g, b]) in COLOR_RAMP {
+        ramp.push_str(&format!(
+            "{} {} {} {} 255\n",
+            min + fraction * span,
+            r,
+            g,
+            b
+        ));
+    }
+    ramp
+}
+
+fn render_tile(
+    output_path: &Path,
+    bounds: [f64; 4],
+    style: TileStyle,
+    range: Option<(f64, f64)>,
+) -> Result<Vec<u8>, ProcessingError> {
+    let dir = std::env::temp_dir().join("dtm-tiles");
+    std::fs::create_dir_all(&dir)?;
+    let stem = dir.join(uuid::Uuid::new_v4().to_string());
+    let with_ext = |ext: &str| PathBuf::from(format!("{}.{}", stem.display(), ext));
+    let (warped, ramp, png) = (with_ext("tif"), with_ext("txt"), with_ext("png"));
+
+    let result = (|| {
+        let nodata = inspect_raster(&output_path.to_string_lossy())?.nodata;
+        let mut warp = Command::new("gdalwarp");
+        warp.args(["-q", "-t_srs", "EPSG:3857", "-te"])
+            .args(bounds.map(|v| v.to_string()))
+            .args(["-ts", &TILE_SIZE.to_string(), &TILE_SIZE.to_string()])
+            .args(["-r", "bilinear", "-ovr", "AUTO", "-of", "GTiff"]);
+        if let Some(nodata) = nodata {
+            warp.args(["-dstnodata", &format_nodata(nodata)]);
+        }
+        run(warp.arg(output_path).arg(&warped), "gdalwarp")?;
+
+        let mut dem = Command::new("gdaldem");
+        match (style, range) {
+            (TileStyle::Color, Some((min, max))) => {
+                std::fs::write(&ramp, color_ramp(min, max))?;
+                dem.args(["color-relief", "-q", "-alpha", "-of", "PNG"])
+                    .arg(&warped)
+                    .arg(&ramp);
+            }
+            _ => {
+                dem.args(["hillshade", "-q", "-compute_edges", "-of", "PNG"])
+                    .arg(&warped);
+            }
+        }
+        run(dem.arg(&png), "gdaldem")?;
+        Ok(std::fs::read(&png)?)
+    })();
+
+    for path in [&warped, &ramp, &png, &with_ext("png.aux.xml")] {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+fn run(command: &mut Command, name: &str) -> Result<(), ProcessingError> {
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ProcessingError::GdalError(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr)
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(y: u32) -> TileKey {
+        TileKey {
+            download_id: "job".to_string(),
+            style: TileStyle::Hillshade,
+            z: 10,
+            x: 0,
+            y,
+        }
+    }
+
+    #[test]
+    fn test_tile_bounds() {
+        assert_eq!(
+            tile_bounds(0, 0, 0),
+            [-ORIGIN_SHIFT, -ORIGIN_SHIFT, ORIGIN_SHIFT, ORIGIN_SHIFT]
+        );
+        let [min_x, min_y, max_x, max_y] = tile_bounds(1, 1, 0);
+        assert_eq!(
+            [min_x, min_y, max_x, max_y],
+            [0.0, 0.0, ORIGIN_SHIFT, ORIGIN_SHIFT]
+        );
+
+        assert!(valid_tile(2, 3, 3));
+        assert!(!valid_tile(2, 4, 0));
+        assert!(!valid_tile(MAX_ZOOM + 1, 0, 0));
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let cache = TileCache::new(10);
+        cache.insert(key(0), Bytes::from_static(b"aaaa"));
+        cache.insert(key(1), Bytes::from_static(b"bbbb"));
+        assert!(cache.get(&key(0)).is_some());
+
+        cache.insert(key(2), Bytes::from_static(b"cccc"));
+        assert_eq!(cache.size(), 8);
+        assert!(cache.get(&key(0)).is_some());
+        assert!(cache.get(&key(1)).is_none());
+        assert!(cache.get(&key(2)).is_some());
+
+        cache.remove_job("job");
+        assert_eq!(cache.size(), 0);
+        assert!(cache.get(&key(2)).is_none());
+    }
+
+    #[test]
+    fn test_tiles_outside_the_output_are_empty() {
+        assert!(EMPTY_TILE.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(&EMPTY_TILE[16..24], &[0, 0, 1, 0, 0, 0, 1, 0]);
+
+        let json = r#"{"wgs84Extent":{"type":"Polygon","coordinates":[[
+            [-79.5,43.7],[-79.5,43.6],[-79.3,43.6],[-79.3,43.7],[-79.5,43.7]]]}}"#;
+        let extent = parse_wgs84_extent(json).unwrap();
+        let [x, y] = transform_point(Crs::Wgs84, Crs::WebMercator, -79.4, 43.65);
+        assert!(extent[0] < x && x < extent[2] && extent[1] < y && y < extent[3]);
+        assert_eq!(parse_wgs84_extent(r#"{"size":[1,1]}"#), None);
+
+        let tile_at = |z: u8, x: f64, y: f64| {
+            let n = f64::from(1u32 << z);
+            let column = ((x + ORIGIN_SHIFT) / (2.0 * ORIGIN_SHIFT) * n) as u32;
+            let row = ((ORIGIN_SHIFT - y) / (2.0 * ORIGIN_SHIFT) * n) as u32;
+            tile_bounds(z, column, row)
+        };
+        assert!(overlaps(tile_at(14, x, y), extent));
+        assert!(!overlaps(tile_at(14, x + 50_000.0, y), extent));
+        assert!(overlaps(tile_bounds(0, 0, 0), extent));
+    }
+
+    #[test]
+    fn test_parse_band_range_and_color_ramp() {
+        let json = r#"{"bands":[{"band":1,"minimum":74.5,"maximum":312.0}]}"#;
+        assert_eq!(parse_band_range(json), Some((74.5, 312.0)));
+        assert_eq!(parse_band_range(r#"{"bands":[{"band":1}]}"#), None);
+
+        let ramp = color_ramp(100.0, 300.0);
+        assert!(ramp.starts_with("nv 0 0 0 0\n100 "));
+        assert!(ramp.contains("\n200 "));
+        assert!(ramp.trim_end().ends_with("300 255 255 255 255"));
+    }
+}
@@ -0,0 +1,291 @@
+//! Vertical datum conversion between CGVD2013 and CGVD28.
+//!
+//! The shift is applied from a local height-difference grid so conversions
+//! work offline. The grid is a GeoTIFF in any CRS whose values are
+//! `H(CGVD28) - H(CGVD2013)` in metres, e.g. derived from the NRCan HT2 and
+//! CGG2013 models. Its path is read from `DTM_VDATUM_GRID`.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::api_types::VerticalDatum;
+use crate::processing::{format_nodata, inspect_raster, ProcessingError};
+
+/// Vertical datum of the Ontario lidar-derived DTM packages, assumed when a
+/// request does not name one.
+pub const SOURCE_VERTICAL_DATUM: VerticalDatum = VerticalDatum::Cgvd2013;
+
+/// Environment variable holding the path of the height-difference grid.
+const GRID_PATH_ENV: &str = "DTM_VDATUM_GRID";
+
+/// A resolved conversion between the source and target datums.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerticalDatumTransform {
+    pub source: VerticalDatum,
+    pub target: VerticalDatum,
+    /// Height-difference grid; only present when a shift is required.
+    pub grid_path: Option<PathBuf>,
+}
+
+impl VerticalDatumTransform {
+    /// Resolve a conversion from `source` to `target`, locating the grid
+    /// file when a shift is needed.
+    pub fn resolve(source: VerticalDatum, target: VerticalDatum) -> Result<Self, ProcessingError> {
+        Self::resolve_with_grid(source, target, configured_grid_path())
+    }
+
+    fn resolve_with_grid(
+        source: VerticalDatum,
+        target: VerticalDatum,
+        grid_path: Option<PathBuf>,
+    ) -> Result<Self, ProcessingError> {
+        if source == target {
+            return Ok(Self {
+                source,
+                target,
+                grid_path: None,
+            });
+        }
+
+        let grid_path = grid_path.ok_or_else(|| {
+            ProcessingError::VerticalGridUnavailable(format!(
+                "set {} to a local height-difference grid",
+                GRID_PATH_ENV
+            ))
+        })?;
+        if !grid_path.is_file() {
+            return Err(ProcessingError::VerticalGridUnavailable(format!(
+                "grid file not found: {}",
+                grid_path.display()
+            )));
+        }
+
+        Ok(Self {
+            source,
+            target,
+            grid_path: Some(grid_path),
+        })
+    }
+
+    pub fn requires_shift(&self) -> bool {
+        self.source != self.target
+    }
+
+    /// Human readable description, e.g. "CGVD2013 to CGVD28".
+    pub fn describe(&self) -> String {
+        format!("{} to {}", self.source.name(), self.target.name())
+    }
+
+    /// `gdal_calc.py` expression applying the grid (`B`) to heights (`A`).
+    fn calc_expression(&self) -> &'static str {
+        match self.target {
+            VerticalDatum::Cgvd28 => "A+B",
+            VerticalDatum::Cgvd2013 => "A-B",
+        }
+    }
+
+    /// Metadata items recorded on the output describing the applied transformation.
+    pub fn metadata(&self) -> Vec<String> {
+        let transformation = match &self.grid_path {
+            Some(grid) => format!(
+                "{} using grid {}",
+                self.describe(),
+                grid.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            ),
+            None => "none".to_string(),
+        };
+        vec![
+            format!("VERTICAL_DATUM={}", self.target.name()),
+            format!("SOURCE_VERTICAL_DATUM={}", self.source.name()),
+            format!("VERTICAL_DATUM_TRANSFORMATION={}", transformation),
+        ]
+    }
+}
+
+fn configured_grid_path() -> Option<PathBuf> {
+    let value = std::env::var(GRID_PATH_ENV).ok()?;
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(trimmed))
+}
+
+/// Resample the grid onto the raster at `input_path` and apply it, writing
+/// the converted heights to `output_path`.
+pub(crate) fn apply_vertical_shift(
+    input_path: &str,
+    output_path: &str,
+    output_stem: &str,
+    transform: &VerticalDatumTransform,
+    nodata: f64,
+) -> Result<(), ProcessingError> {
+    let grid_path = transform.grid_path.as_deref().ok_or_else(|| {
+        ProcessingError::VerticalGridUnavailable("no grid resolved for transformation".to_string())
+    })?;
+    let resampled_grid = format!("{}.vdatum_grid.tif", output_stem);
+    resample_grid(grid_path, input_path, &resampled_grid, nodata)?;
+
+    let output = Command::new("gdal_calc.py")
+        .arg("-A")
+        .arg(input_path)
+        .arg("-B")
+        .arg(&resampled_grid)
+        .arg(format!("--calc={}", transform.calc_expression()))
+        .arg("--type=Float32")
+        .arg(format!("--NoDataValue={}", format_nodata(nodata)))
+        .arg("--overwrite")
+        .arg(format!("--outfile={}", output_path))
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ProcessingError::GdalError(format!(
+            "gdal_calc failed: {}",
+            stderr
+        )));
+    }
+
+    Ok(())
+}
+
+/// Warp the grid onto the exact pixel grid of `template_path`.
+///
+/// Pixels outside the grid's coverage are `nodata`, so `gdal_calc.py` leaves
+/// them without heights instead of applying a zero shift.
+fn resample_grid(
+    grid_path: &Path,
+    template_path: &str,
+    output_path: &str,
+    nodata: f64,
+) -> Result<(), ProcessingError> {
+    let info = inspect_raster(template_path)?;
+    let (size, extent, srs_wkt) = match (info.size, info.extent, info.srs_wkt) {
+        (Some(size), Some(extent), Some(srs_wkt)) => (size, extent, srs_wkt),
+        _ => {
+            return Err(ProcessingError::GdalError(
+                "merged raster is missing georeferencing".to_string(),
+            ))
+        }
+    };
+
+    let output = Command::new("gdalwarp")
+        .arg("-overwrite")
+        .arg("-r")
+        .arg("bilinear")
+        .arg("-ot")
+        .arg("Float32")
+        .arg("-dstnodata")
+        .arg(format_nodata(nodata))
+        .arg("-t_srs")
+        .arg(&srs_wkt)
+        .arg("-te")
+        .arg(extent[0].to_string())
+        .arg(extent[1].to_string())
+        .arg(extent[2].to_string())
+        .arg(extent[3].to_string())
+        .arg("-ts")
+        .arg(size[0].to_string())
+        .arg(size[1].to_string())
+        .arg(grid_path)
+        .arg(output_path)
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ProcessingError::GdalError(format!(
+            "gdalwarp failed resampling vertical datum grid: {}",
+            stderr
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_datum_needs_no_grid() {
+        let transform = VerticalDatumTransform::resolve_with_grid(
+            VerticalDatum::Cgvd2013,
+            VerticalDatum::Cgvd2013,
+            None,
+        )
+        .unwrap();
+        assert!(!transform.requires_shift());
+        assert!(transform
+            .metadata()
+            .contains(&"VERTICAL_DATUM_TRANSFORMATION=none".to_string()));
+    }
+
+    #[test]
+    fn test_missing_grid_is_an_error() {
+        let result = VerticalDatumTransform::resolve_with_grid(
+            VerticalDatum::Cgvd2013,
+            VerticalDatum::Cgvd28,
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(ProcessingError::VerticalGridUnavailable(_))
+        ));
+
+        let result = VerticalDatumTransform::resolve_with_grid(
+            VerticalDatum::Cgvd2013,
+            VerticalDatum::Cgvd28,
+            Some(PathBuf::from("/nonexistent/ht2_cgvd2013.tif")),
+        );
+        assert!(matches!(
+            result,
+            Err(ProcessingError::VerticalGridUnavailable(_))
+        ));
+    }
+
+    #[test]
+    fn test_transform_to_cgvd28_records_metadata() {
+        let grid = std::env::temp_dir().join(format!("dtm-vdatum-grid-{}.tif", std::process::id()));
+        std::fs::write(&grid, b"grid").unwrap();
+
+        let transform = VerticalDatumTransform::resolve_with_grid(
+            VerticalDatum::Cgvd2013,
+            VerticalDatum::Cgvd28,
+            Some(grid.clone()),
+        )
+        .unwrap();
+        assert!(transform.requires_shift());
+        assert_eq!(transform.calc_expression(), "A+B");
+
+        let metadata = transform.metadata();
+        assert!(metadata.contains(&"VERTICAL_DATUM=CGVD28".to_string()));
+        assert!(metadata.contains(&"SOURCE_VERTICAL_DATUM=CGVD2013".to_string()));
+        assert!(metadata
+            .iter()
+            .any(|m| m.starts_with("VERTICAL_DATUM_TRANSFORMATION=CGVD2013 to CGVD28")));
+
+        let _ = std::fs::remove_file(grid);
+    }
+
+    #[test]
+    fn test_transform_from_cgvd28_subtracts_grid() {
+        let grid =
+            std::env::temp_dir().join(format!("dtm-vdatum-grid-28-{}.tif", std::process::id()));
+        std::fs::write(&grid, b"grid").unwrap();
+
+        let transform = VerticalDatumTransform::resolve_with_grid(
+            VerticalDatum::Cgvd28,
+            VerticalDatum::Cgvd2013,
+            Some(grid.clone()),
+        )
+        .unwrap();
+        assert!(transform.requires_shift());
+        assert_eq!(transform.calc_expression(), "A-B");
+        assert_eq!(transform.describe(), "CGVD28 to CGVD2013");
+        assert!(transform
+            .metadata()
+            .contains(&"SOURCE_VERTICAL_DATUM=CGVD28".to_string()));
+
+        let _ = std::fs::remove_file(grid);
+    }
+}
@@ -46,6 +46,7 @@ interface ProgressEvent {
   Download?: DownloadProgress;
   Processing?: ProcessingProgress;
   Complete?: { output_filename: string };
+  Warning?: { message: string };
   Error?: { message: string };
 }
 
@@ -87,6 +88,7 @@ function App() {
   const [processingProgress, setProcessingProgress] = useState<ProcessingProgress | null>(null);
   const [compression, setCompression] = useState<string>('deflate');
   const [error, setError] = useState<string | null>(null);
+  const [warnings, setWarnings] = useState<string[]>([]);
   const [loading, setLoading] = useState(false);
   const [downloadId, setDownloadId] = useState<string | null>(null);
 
@@ -302,6 +304,8 @@ function App() {
           setStep('complete');
           eventSource.close();
           downloadFile(downloadId, progress.Complete.output_filename);
+        } else if (progress.Warning) {
+          setWarnings(prev => [...prev, progress.Warning!.message]);
         } else if (progress.Error) {
           setError(progress.Error.message);
           setStep('packages');
@@ -349,6 +353,7 @@ function App() {
 
     setStep('download');
     setError(null);
+    setWarnings([]);
 
     let clip_extent = null;
     if (extent) {
@@ -415,6 +420,7 @@ function App() {
     setProcessingProgress(null);
     setDownloadId(null);
     setError(null);
+    setWarnings([]);
     if (rectangleRef.current && mapRef.current) {
       mapRef.current.removeLayer(rectangleRef.current);
       rectangleRef.current = null;
@@ -459,6 +465,17 @@ function App() {
           </div>
         )}
 
+        {warnings.length > 0 && (
+          <div className={`warning-banner ${error ? 'below-error' : ''}`}>
+            <ul>
+              {warnings.map((warning, i) => (
+                <li key={i}>{warning}</li>
+              ))}
+            </ul>
+            <button onClick={() => setWarnings([])}>×</button>
+          </div>
+        )}
+
         {step === 'extent' && (
           <div className="control-panel">
             <h2>Step 1: Select Your Area</h2>